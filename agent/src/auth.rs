@@ -0,0 +1,37 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::AppState;
+
+/// Authenticates the orchestrator by the node's token. Without a token nothing
+/// is let through.
+pub async fn require_orchestrator(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = state
+        .config
+        .node_token
+        .as_ref()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let bearer = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // digests have the same length whatever was sent, so the comparison takes
+    // the same time however much of the token matches
+    let matches = Sha256::digest(bearer.as_bytes()).ct_eq(&Sha256::digest(token.as_bytes()));
+    if !bool::from(matches) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(request).await)
+}
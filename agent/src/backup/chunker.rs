@@ -0,0 +1,179 @@
+use std::io::{self, Read};
+
+pub const MIN_CHUNK_SIZE: usize = 256 * 1024;
+pub const AVG_CHUNK_SIZE: usize = 1024 * 1024;
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// FastCDC style normalized chunking: a harder mask before the average size and an
+// easier one after it keeps chunk sizes close to AVG_CHUNK_SIZE.
+const MASK_HARD: u64 = !0 << (64 - 22);
+const MASK_EASY: u64 = !0 << (64 - 18);
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+/// Returns the length of the first chunk in `data`.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let normal = AVG_CHUNK_SIZE.min(data.len());
+    let end = MAX_CHUNK_SIZE.min(data.len());
+
+    let mut hash: u64 = 0;
+    let mut i = MIN_CHUNK_SIZE;
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_HARD == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < end {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_EASY == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    end
+}
+
+/// Splits a reader into content-defined chunks, so an insertion in a file only
+/// changes the chunks around it instead of every chunk after it.
+pub struct Chunker<R> {
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+            eof: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        while !self.eof && self.buf.len() < MAX_CHUNK_SIZE {
+            let len = self.buf.len();
+            self.buf.resize(MAX_CHUNK_SIZE, 0);
+            match self.reader.read(&mut self.buf[len..]) {
+                Ok(0) => {
+                    self.buf.truncate(len);
+                    self.eof = true;
+                }
+                Ok(n) => self.buf.truncate(len + n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => self.buf.truncate(len),
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buf.is_empty() {
+            return None;
+        }
+        let cut = cut_point(&self.buf);
+        let rest = self.buf.split_off(cut);
+        Some(Ok(std::mem::replace(&mut self.buf, rest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic bytes that do not repeat, like compressed game data.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> Vec<Vec<u8>> {
+        Chunker::new(data).collect::<io::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn empty_input_has_no_chunks() {
+        assert!(chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn small_input_is_one_chunk() {
+        let data = noise(1000, 1);
+        assert_eq!(chunks(&data), vec![data]);
+    }
+
+    #[test]
+    fn chunks_are_within_bounds_and_make_up_the_input() {
+        let data = noise(8 * AVG_CHUNK_SIZE, 2);
+        let chunks = chunks(&data);
+        assert!(chunks.len() > 1);
+        let (last, rest) = chunks.split_last().unwrap();
+        for chunk in rest {
+            assert!((MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk.len()));
+        }
+        assert!(last.len() <= MAX_CHUNK_SIZE);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn uniform_input_is_cut_at_the_maximum() {
+        let data = vec![0; 2 * MAX_CHUNK_SIZE + 10];
+        let sizes: Vec<usize> = chunks(&data).iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![MAX_CHUNK_SIZE, MAX_CHUNK_SIZE, 10]);
+    }
+
+    #[test]
+    fn insertion_only_changes_nearby_chunks() {
+        let data = noise(8 * AVG_CHUNK_SIZE, 3);
+        let mut shifted = b"inserted".to_vec();
+        shifted.extend_from_slice(&data);
+
+        let before = chunks(&data);
+        let after = chunks(&shifted);
+        let shared = after.iter().filter(|chunk| before.contains(chunk)).count();
+        assert!(
+            shared >= before.len() - 2,
+            "only {} of {} chunks survived the insertion",
+            shared,
+            before.len()
+        );
+    }
+}
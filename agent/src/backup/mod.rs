@@ -0,0 +1,175 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use common::agent_types::{PruneResult, RestoreSnapshot, RetentionPolicy, Snapshot, VerifyReport};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    power,
+    utils::{get_folder, AppError},
+    AppState,
+};
+
+pub mod chunker;
+pub mod repository;
+
+use repository::Repository;
+
+pub fn backup_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(list, create))
+        .routes(routes!(delete))
+        .routes(routes!(restore))
+        .routes(routes!(prune))
+        .routes(routes!(verify))
+}
+
+/// Runs a blocking repository operation off the async runtime.
+async fn with_repository<T, F>(state: &AppState, f: F) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Repository) -> std::io::Result<T> + Send + 'static,
+{
+    let repository: Arc<Repository> = state.backups.clone();
+    tokio::task::spawn_blocking(move || f(&repository))
+        .await
+        .map_err(|_| AppError::InternalError)?
+        .map_err(AppError::from)
+}
+
+/// Removes the snapshots of a server that is deleted from this node.
+pub async fn delete_server(state: &AppState, id: i32) -> Result<(), AppError> {
+    with_repository(state, move |repo| repo.delete_server(id)).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = [Snapshot]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::BACKUP_TAG
+)]
+pub async fn list(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let snapshots = with_repository(&state, move |repo| {
+        Ok(repo
+            .manifests(id)?
+            .iter()
+            .map(|m| m.snapshot())
+            .collect::<Vec<_>>())
+    })
+    .await?;
+    Ok((StatusCode::OK, Json(snapshots)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Snapshot), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::BACKUP_TAG
+)]
+pub async fn create(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let source = PathBuf::from(get_folder(id));
+    let snapshot = with_repository(&state, move |repo| {
+        Ok(repo.create_snapshot(id, &source)?.snapshot())
+    })
+    .await?;
+    tracing::info!(
+        "Created snapshot {} for server {} ({} new bytes)",
+        snapshot.id,
+        id,
+        snapshot.added_size
+    );
+    Ok((StatusCode::OK, Json(snapshot)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{id}/{snapshot}",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("snapshot" = String, Path, description = "snapshot id")
+    ),
+    responses((status = OK, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::BACKUP_TAG
+)]
+pub async fn delete(
+    Path((id, snapshot)): Path<(i32, String)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    with_repository(&state, move |repo| repo.delete_snapshot(id, &snapshot)).await?;
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/{snapshot}/restore",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("snapshot" = String, Path, description = "snapshot id")
+    ),
+    responses((status = OK, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::BACKUP_TAG
+)]
+pub async fn restore(
+    Path((id, snapshot)): Path<(i32, String)>,
+    State(state): State<AppState>,
+    Json(body): Json<RestoreSnapshot>,
+) -> Result<impl IntoResponse, AppError> {
+    if power::is_running(state.runtime.as_ref(), id).await? {
+        return Err(AppError::Conflict(
+            "server must be stopped before restoring a backup".to_string(),
+        ));
+    }
+
+    let target = PathBuf::from(get_folder(id));
+    with_repository(&state, move |repo| {
+        repo.restore(id, &snapshot, &target, body.wipe)
+    })
+    .await?;
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/prune",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = PruneResult), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::BACKUP_TAG
+)]
+pub async fn prune(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(policy): Json<RetentionPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    let result = with_repository(&state, move |repo| repo.prune(id, &policy)).await?;
+    tracing::info!(
+        "Pruned {} snapshots of server {}, freed {} bytes",
+        result.removed.len(),
+        id,
+        result.freed_size
+    );
+    Ok((StatusCode::OK, Json(result)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/verify",
+    responses((status = OK, body = VerifyReport), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::BACKUP_TAG
+)]
+pub async fn verify(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let report = with_repository(&state, |repo| repo.verify()).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
@@ -0,0 +1,706 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::fs::{symlink, MetadataExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Datelike, Utc};
+use common::agent_types::{PruneResult, RetentionPolicy, Snapshot, VerifyReport};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::chunker::Chunker;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EntryKind {
+    Dir,
+    File {
+        size: u64,
+        mtime_ns: i64,
+        chunks: Vec<String>,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub mode: u32,
+    #[serde(flatten)]
+    pub kind: EntryKind,
+}
+
+/// A single backup: the file tree of a server volume at one point in time,
+/// with file contents referenced by chunk hash.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub id: String,
+    pub server_id: i32,
+    pub parent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub added_size: u64,
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn snapshot(&self) -> Snapshot {
+        let (file_count, total_size) =
+            self.entries
+                .iter()
+                .fold((0, 0), |(count, size), e| match &e.kind {
+                    EntryKind::File { size: s, .. } => (count + 1, size + *s as i64),
+                    _ => (count, size),
+                });
+        Snapshot {
+            id: self.id.clone(),
+            server_id: self.server_id,
+            parent: self.parent.clone(),
+            created_at: self.created_at,
+            file_count,
+            total_size,
+            added_size: self.added_size as i64,
+        }
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().flat_map(|e| match &e.kind {
+            EntryKind::File { chunks, .. } => chunks.as_slice(),
+            _ => &[],
+        })
+    }
+}
+
+/// Content addressed backup repository.
+///
+/// ```text
+/// <root>/chunks/<first two hex chars>/<sha256>
+/// <root>/snapshots/<server id>/<snapshot id>.json
+/// ```
+///
+/// Chunks are only ever written once, so every snapshot is incremental against
+/// everything already in the repository.
+pub struct Repository {
+    root: PathBuf,
+    // Serializes writers against garbage collection so a chunk that a running
+    // backup just deduplicated against is never removed underneath it. Restores
+    // only read chunks and share the lock with each other.
+    lock: RwLock<()>,
+}
+
+impl Repository {
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(root.join("chunks"))?;
+        fs::create_dir_all(root.join("snapshots"))?;
+        Ok(Self {
+            root,
+            lock: RwLock::new(()),
+        })
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.root.join("chunks").join(&hash[..2]).join(hash)
+    }
+
+    fn snapshot_dir(&self, server_id: i32) -> PathBuf {
+        self.root.join("snapshots").join(server_id.to_string())
+    }
+
+    fn snapshot_path(&self, server_id: i32, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid snapshot id",
+            ));
+        }
+        Ok(self.snapshot_dir(server_id).join(format!("{}.json", id)))
+    }
+
+    fn server_ids(&self) -> io::Result<Vec<i32>> {
+        let mut ids = vec![];
+        for entry in fs::read_dir(self.root.join("snapshots"))? {
+            if let Some(id) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn manifest(&self, server_id: i32, id: &str) -> io::Result<Manifest> {
+        let file = File::open(self.snapshot_path(server_id, id)?)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::other)
+    }
+
+    /// All manifests of a server, oldest first.
+    pub fn manifests(&self, server_id: i32) -> io::Result<Vec<Manifest>> {
+        let dir = self.snapshot_dir(server_id);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut manifests = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let file = File::open(&path)?;
+                let manifest: Manifest =
+                    serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::other)?;
+                manifests.push(manifest);
+            }
+        }
+        manifests.sort_by_key(|m| m.created_at);
+        Ok(manifests)
+    }
+
+    /// Writes a chunk if the repository does not have it yet. Returns the hash
+    /// and the number of bytes actually written.
+    fn store_chunk(&self, data: &[u8]) -> io::Result<(String, u64)> {
+        let hash = format!("{:x}", Sha256::digest(data));
+        let path = self.chunk_path(&hash);
+        if path.exists() {
+            return Ok((hash, 0));
+        }
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+        let tmp = dir.join(format!("{}.tmp", hash));
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        Ok((hash, data.len() as u64))
+    }
+
+    /// Takes a snapshot of `source`. Files whose size and modification time match
+    /// the previous snapshot reuse its chunk list without being read again.
+    pub fn create_snapshot(&self, server_id: i32, source: &Path) -> io::Result<Manifest> {
+        let _guard = self.lock.write().unwrap();
+
+        let parent = self.manifests(server_id)?.pop();
+        let previous: HashMap<&str, (u64, i64, &Vec<String>)> = parent
+            .iter()
+            .flat_map(|m| m.entries.iter())
+            .filter_map(|e| match &e.kind {
+                EntryKind::File {
+                    size,
+                    mtime_ns,
+                    chunks,
+                } => Some((e.path.as_str(), (*size, *mtime_ns, chunks))),
+                _ => None,
+            })
+            .collect();
+
+        let mut entries = vec![];
+        let mut added_size = 0;
+        let mut stack = vec![PathBuf::new()];
+        while let Some(rel) = stack.pop() {
+            let mut children = fs::read_dir(source.join(&rel))?
+                .map(|e| e.map(|e| e.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+            for name in children.into_iter().rev() {
+                let rel = rel.join(name);
+                let full = source.join(&rel);
+                let meta = fs::symlink_metadata(&full)?;
+                let path = rel.to_string_lossy().to_string();
+                let mode = meta.mode() & 0o7777;
+
+                let kind = if meta.file_type().is_symlink() {
+                    EntryKind::Symlink {
+                        target: fs::read_link(&full)?.to_string_lossy().to_string(),
+                    }
+                } else if meta.is_dir() {
+                    stack.push(rel);
+                    EntryKind::Dir
+                } else if meta.is_file() {
+                    let size = meta.len();
+                    let mtime_ns = meta.mtime() * 1_000_000_000 + meta.mtime_nsec();
+                    let chunks = match previous.get(path.as_str()) {
+                        Some((s, m, chunks))
+                            if *s == size
+                                && *m == mtime_ns
+                                && chunks.iter().all(|c| self.chunk_path(c).exists()) =>
+                        {
+                            (*chunks).clone()
+                        }
+                        _ => {
+                            let mut chunks = vec![];
+                            for chunk in Chunker::new(File::open(&full)?) {
+                                let (hash, written) = self.store_chunk(&chunk?)?;
+                                added_size += written;
+                                chunks.push(hash);
+                            }
+                            chunks
+                        }
+                    };
+                    EntryKind::File {
+                        size,
+                        mtime_ns,
+                        chunks,
+                    }
+                } else {
+                    // sockets, fifos and devices have no place in a backup
+                    continue;
+                };
+                entries.push(Entry { path, mode, kind });
+            }
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let created_at = Utc::now();
+        let id = {
+            let mut hasher = Sha256::new();
+            hasher.update(server_id.to_be_bytes());
+            hasher.update(created_at.to_rfc3339().as_bytes());
+            hasher.update(serde_json::to_vec(&entries).map_err(io::Error::other)?);
+            format!(
+                "{}-{}",
+                created_at.format("%Y%m%d%H%M%S"),
+                &format!("{:x}", hasher.finalize())[..12]
+            )
+        };
+        let manifest = Manifest {
+            id,
+            server_id,
+            parent: parent.map(|p| p.id),
+            created_at,
+            added_size,
+            entries,
+        };
+
+        let path = self.snapshot_path(server_id, &manifest.id)?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp)?;
+        serde_json::to_writer(&mut file, &manifest).map_err(io::Error::other)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(manifest)
+    }
+
+    /// Restores a snapshot into `target`. With `wipe` everything in `target` is
+    /// removed first, otherwise files that are not part of the snapshot are kept.
+    pub fn restore(&self, server_id: i32, id: &str, target: &Path, wipe: bool) -> io::Result<()> {
+        let _guard = self.lock.read().unwrap();
+        let manifest = self.manifest(server_id, id)?;
+
+        if wipe {
+            for entry in fs::read_dir(target)? {
+                let path = entry?.path();
+                if fs::symlink_metadata(&path)?.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        for entry in &manifest.entries {
+            let rel = Path::new(&entry.path);
+            if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsafe path in snapshot: {}", entry.path),
+                ));
+            }
+            let path = target.join(rel);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if let Ok(meta) = fs::symlink_metadata(&path) {
+                let is_dir = matches!(entry.kind, EntryKind::Dir);
+                if meta.is_dir() && !is_dir {
+                    fs::remove_dir_all(&path)?;
+                } else if !meta.is_dir() && (is_dir || meta.file_type().is_symlink()) {
+                    fs::remove_file(&path)?;
+                }
+            }
+
+            match &entry.kind {
+                EntryKind::Dir => {
+                    fs::create_dir_all(&path)?;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(entry.mode))?;
+                }
+                EntryKind::Symlink { target } => symlink(target, &path)?,
+                EntryKind::File {
+                    mtime_ns, chunks, ..
+                } => {
+                    let tmp = path.with_file_name(format!(
+                        ".{}.restore",
+                        path.file_name().unwrap().to_string_lossy()
+                    ));
+                    let mut file = File::create(&tmp)?;
+                    for chunk in chunks {
+                        file.write_all(&fs::read(self.chunk_path(chunk))?)?;
+                    }
+                    file.set_permissions(fs::Permissions::from_mode(entry.mode))?;
+                    let mtime = UNIX_EPOCH + Duration::from_nanos((*mtime_ns).max(0) as u64);
+                    file.set_modified(mtime)?;
+                    fs::rename(&tmp, &path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete_snapshot(&self, server_id: i32, id: &str) -> io::Result<()> {
+        let _guard = self.lock.write().unwrap();
+        fs::remove_file(self.snapshot_path(server_id, id)?)?;
+        self.collect_garbage()?;
+        Ok(())
+    }
+
+    /// Removes all snapshots of a deleted server and the chunks only they used.
+    pub fn delete_server(&self, server_id: i32) -> io::Result<()> {
+        let _guard = self.lock.write().unwrap();
+        match fs::remove_dir_all(self.snapshot_dir(server_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        self.collect_garbage()?;
+        Ok(())
+    }
+
+    /// Removes every snapshot of a server that the retention policy does not keep,
+    /// then drops chunks that no snapshot references anymore. A policy without any
+    /// rule keeps everything.
+    pub fn prune(&self, server_id: i32, policy: &RetentionPolicy) -> io::Result<PruneResult> {
+        let _guard = self.lock.write().unwrap();
+
+        let mut manifests = self.manifests(server_id)?;
+        manifests.reverse();
+        let keep = retained(
+            &manifests.iter().map(|m| m.created_at).collect::<Vec<_>>(),
+            policy,
+        );
+
+        let mut result = PruneResult {
+            kept: vec![],
+            removed: vec![],
+            freed_chunks: 0,
+            freed_size: 0,
+        };
+        for (i, manifest) in manifests.into_iter().enumerate() {
+            if keep.contains(&i) {
+                result.kept.push(manifest.id);
+            } else {
+                fs::remove_file(self.snapshot_path(server_id, &manifest.id)?)?;
+                result.removed.push(manifest.id);
+            }
+        }
+        if !result.removed.is_empty() {
+            let (chunks, size) = self.collect_garbage()?;
+            result.freed_chunks = chunks as i64;
+            result.freed_size = size as i64;
+        }
+        Ok(result)
+    }
+
+    fn referenced_chunks(&self) -> io::Result<(HashSet<String>, usize)> {
+        let mut referenced = HashSet::new();
+        let mut snapshots = 0;
+        for server_id in self.server_ids()? {
+            for manifest in self.manifests(server_id)? {
+                referenced.extend(manifest.chunks().cloned());
+                snapshots += 1;
+            }
+        }
+        Ok((referenced, snapshots))
+    }
+
+    fn stored_chunks(&self) -> io::Result<Vec<PathBuf>> {
+        let mut chunks = vec![];
+        for dir in fs::read_dir(self.root.join("chunks"))? {
+            let dir = dir?.path();
+            if dir.is_dir() {
+                for chunk in fs::read_dir(dir)? {
+                    chunks.push(chunk?.path());
+                }
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// Deletes unreferenced chunks and leftovers of interrupted writes. Callers
+    /// must hold the repository lock.
+    fn collect_garbage(&self) -> io::Result<(u64, u64)> {
+        let (referenced, _) = self.referenced_chunks()?;
+        let (mut count, mut size) = (0, 0);
+        for path in self.stored_chunks()? {
+            let name = path.file_name().unwrap().to_string_lossy();
+            if !referenced.contains(name.as_ref()) {
+                size += fs::metadata(&path)?.len();
+                fs::remove_file(&path)?;
+                count += 1;
+            }
+        }
+        Ok((count, size))
+    }
+
+    /// Checks that every chunk referenced by a snapshot exists and still hashes to
+    /// its name.
+    pub fn verify(&self) -> io::Result<VerifyReport> {
+        let _guard = self.lock.write().unwrap();
+
+        let (referenced, snapshots) = self.referenced_chunks()?;
+        let mut report = VerifyReport {
+            snapshots: snapshots as i64,
+            chunks: referenced.len() as i64,
+            missing_chunks: vec![],
+            corrupt_chunks: vec![],
+            unreferenced_chunks: 0,
+        };
+        for hash in &referenced {
+            let mut data = vec![];
+            match File::open(self.chunk_path(hash)) {
+                Ok(mut file) => file.read_to_end(&mut data)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    report.missing_chunks.push(hash.clone());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if format!("{:x}", Sha256::digest(&data)) != *hash {
+                report.corrupt_chunks.push(hash.clone());
+            }
+        }
+        report.unreferenced_chunks = self
+            .stored_chunks()?
+            .iter()
+            .filter(|p| !referenced.contains(p.file_name().unwrap().to_string_lossy().as_ref()))
+            .count() as i64;
+        report.missing_chunks.sort();
+        report.corrupt_chunks.sort();
+        Ok(report)
+    }
+}
+
+/// Indexes into `times` (newest first) of the snapshots kept by `policy`.
+fn retained(times: &[DateTime<Utc>], policy: &RetentionPolicy) -> HashSet<usize> {
+    if policy.keep_last.is_none() && policy.keep_daily.is_none() && policy.keep_weekly.is_none() {
+        return (0..times.len()).collect();
+    }
+
+    let mut keep: HashSet<usize> = (0..times.len())
+        .take(policy.keep_last.unwrap_or(0) as usize)
+        .collect();
+
+    let mut keep_newest_per = |bucket: &dyn Fn(&DateTime<Utc>) -> (i32, u32), limit: u32| {
+        let mut seen = HashSet::new();
+        for (i, time) in times.iter().enumerate() {
+            if seen.len() as u32 >= limit {
+                break;
+            }
+            if seen.insert(bucket(time)) {
+                keep.insert(i);
+            }
+        }
+    };
+    if let Some(days) = policy.keep_daily {
+        keep_newest_per(&|t| (t.year(), t.ordinal()), days);
+    }
+    if let Some(weeks) = policy.keep_weekly {
+        keep_newest_per(
+            &|t| {
+                let week = t.iso_week();
+                (week.year(), week.week())
+            },
+            weeks,
+        );
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// An empty folder of its own for a test.
+    fn folder(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "nerdpanel-repository-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn volume(name: &str) -> PathBuf {
+        let source = folder(name);
+        fs::create_dir_all(source.join("world/region")).unwrap();
+        fs::write(source.join("server.properties"), "motd=hello\n").unwrap();
+        fs::write(source.join("world/region/r.0.0.mca"), vec![7; 300_000]).unwrap();
+        symlink("server.properties", source.join("link")).unwrap();
+        source
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, day, hour, 0, 0).unwrap()
+    }
+
+    fn policy(
+        keep_last: Option<u32>,
+        keep_daily: Option<u32>,
+        keep_weekly: Option<u32>,
+    ) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_last,
+            keep_daily,
+            keep_weekly,
+        }
+    }
+
+    fn sorted(keep: HashSet<usize>) -> Vec<usize> {
+        let mut keep: Vec<usize> = keep.into_iter().collect();
+        keep.sort();
+        keep
+    }
+
+    #[test]
+    fn empty_policy_keeps_everything() {
+        let times = [at(3, 12), at(2, 12), at(1, 12)];
+        assert_eq!(
+            sorted(retained(&times, &policy(None, None, None))),
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn keeps_the_last_snapshots() {
+        let times = [at(3, 12), at(2, 12), at(1, 12)];
+        assert_eq!(
+            sorted(retained(&times, &policy(Some(2), None, None))),
+            [0, 1]
+        );
+    }
+
+    #[test]
+    fn keeps_the_newest_snapshot_of_each_day() {
+        let times = [at(3, 18), at(3, 6), at(2, 18), at(2, 6), at(1, 18)];
+        assert_eq!(
+            sorted(retained(&times, &policy(None, Some(2), None))),
+            [0, 2]
+        );
+    }
+
+    #[test]
+    fn combines_rules() {
+        // the 5th and 12th of October 2026 are Mondays
+        let times = [at(13, 12), at(12, 12), at(11, 12), at(5, 12), at(4, 12)];
+        assert_eq!(
+            sorted(retained(&times, &policy(Some(1), None, Some(3)))),
+            [0, 2, 4]
+        );
+    }
+
+    #[test]
+    fn restores_a_snapshot() {
+        let repository = Repository::open(folder("restore-repo")).unwrap();
+        let source = volume("restore-source");
+        let snapshot = repository.create_snapshot(1, &source).unwrap();
+        assert_eq!(snapshot.snapshot().file_count, 2);
+        assert_eq!(repository.manifests(1).unwrap().len(), 1);
+
+        let target = folder("restore-target");
+        fs::write(target.join("stray"), "kept").unwrap();
+        repository.restore(1, &snapshot.id, &target, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.join("server.properties")).unwrap(),
+            "motd=hello\n"
+        );
+        assert_eq!(
+            fs::read(target.join("world/region/r.0.0.mca")).unwrap(),
+            vec![7; 300_000]
+        );
+        assert_eq!(
+            fs::read_link(target.join("link")).unwrap(),
+            Path::new("server.properties")
+        );
+        assert!(target.join("stray").exists());
+
+        repository.restore(1, &snapshot.id, &target, true).unwrap();
+        assert!(!target.join("stray").exists());
+        assert!(target.join("server.properties").exists());
+    }
+
+    #[test]
+    fn snapshots_only_store_what_changed() {
+        let repository = Repository::open(folder("incremental-repo")).unwrap();
+        let source = volume("incremental-source");
+        let first = repository.create_snapshot(2, &source).unwrap();
+        assert!(first.added_size > 0);
+
+        let unchanged = repository.create_snapshot(2, &source).unwrap();
+        assert_eq!(unchanged.added_size, 0);
+        assert_eq!(unchanged.parent.as_deref(), Some(first.id.as_str()));
+
+        fs::write(source.join("server.properties"), "motd=changed\n").unwrap();
+        let changed = repository.create_snapshot(2, &source).unwrap();
+        assert_eq!(changed.added_size, "motd=changed\n".len() as u64);
+    }
+
+    #[test]
+    fn prune_removes_snapshots_and_their_chunks() {
+        let repository = Repository::open(folder("prune-repo")).unwrap();
+        let source = volume("prune-source");
+        let old = repository.create_snapshot(3, &source).unwrap();
+        fs::write(source.join("server.properties"), "motd=new\n").unwrap();
+        let new = repository.create_snapshot(3, &source).unwrap();
+
+        let result = repository.prune(3, &policy(Some(1), None, None)).unwrap();
+        assert_eq!(result.kept, [new.id.as_str()]);
+        assert_eq!(result.removed, [old.id.as_str()]);
+        assert_eq!(result.freed_chunks, 1);
+        assert_eq!(result.freed_size, "motd=hello\n".len() as i64);
+
+        let report = repository.verify().unwrap();
+        assert_eq!(report.snapshots, 1);
+        assert!(report.missing_chunks.is_empty());
+        assert_eq!(report.unreferenced_chunks, 0);
+
+        let target = folder("prune-target");
+        repository.restore(3, &new.id, &target, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(target.join("server.properties")).unwrap(),
+            "motd=new\n"
+        );
+    }
+
+    #[test]
+    fn deleting_a_server_removes_its_snapshots_and_chunks() {
+        let repository = Repository::open(folder("delete-server-repo")).unwrap();
+        let source = volume("delete-server-source");
+        repository.create_snapshot(5, &source).unwrap();
+        repository.create_snapshot(6, &source).unwrap();
+        fs::write(source.join("server.properties"), "motd=only five\n").unwrap();
+        repository.create_snapshot(5, &source).unwrap();
+
+        repository.delete_server(5).unwrap();
+        assert!(repository.manifests(5).unwrap().is_empty());
+        assert_eq!(repository.manifests(6).unwrap().len(), 1);
+        let report = repository.verify().unwrap();
+        assert_eq!(report.snapshots, 1);
+        assert!(report.missing_chunks.is_empty());
+        assert_eq!(report.unreferenced_chunks, 0);
+
+        // deletes are retried
+        repository.delete_server(5).unwrap();
+    }
+
+    #[test]
+    fn rejects_snapshot_ids_outside_the_repository() {
+        let repository = Repository::open(folder("ids-repo")).unwrap();
+        let error = repository.manifest(4, "../../etc").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,44 @@
+/// Agent settings, read from the environment.
+#[derive(Clone)]
+pub struct Config {
+    pub orchestrator_url: String,
+    /// Token the agent authenticates with against the orchestrator, generated
+    /// per node by staff. The orchestrator authenticates with it as well.
+    pub node_token: Option<String>,
+    /// PEM certificate to trust besides the system's, for an orchestrator with a
+    /// self signed certificate.
+    pub orchestrator_ca: Option<Vec<u8>>,
+    /// User and group servers run as and their volumes belong to, unless their
+    /// pod keeps the image's user.
+    pub container_uid: u32,
+    pub container_gid: u32,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let orchestrator_url = std::env::var("NERDPANEL_ORCHESTRATOR_URL")
+            .unwrap_or_else(|_| "http://localhost:3000".to_string());
+        let node_token = std::env::var("NERDPANEL_NODE_TOKEN").ok();
+        if node_token.is_none() {
+            tracing::warn!(
+                "NERDPANEL_NODE_TOKEN is not set, the orchestrator can not be reached and its requests are rejected"
+            );
+        }
+        let orchestrator_ca = std::env::var("NERDPANEL_ORCHESTRATOR_CA").ok().map(|path| {
+            std::fs::read(path).expect("Failed to read the orchestrator CA certificate.")
+        });
+        let id = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(1000)
+        };
+        Self {
+            orchestrator_url: orchestrator_url.trim_end_matches('/').to_string(),
+            node_token,
+            orchestrator_ca,
+            container_uid: id("NERDPANEL_CONTAINER_UID"),
+            container_gid: id("NERDPANEL_CONTAINER_GID"),
+        }
+    }
+}
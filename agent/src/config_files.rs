@@ -0,0 +1,606 @@
+use std::path::{Component, Path, PathBuf};
+
+use common::{
+    orch_types::{ConfigFile, ConfigFormat, Server},
+    startup,
+};
+use regex::Regex;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use crate::utils::get_folder;
+
+// Config files are edited line by line where the format allows comments, so
+// everything but the patched values stays as the server or its owner wrote it.
+// Files that do not exist yet are created with just the patched values.
+
+/// Sets the values of the server's config files. A file that can not be patched
+/// is skipped, the server is still started.
+pub async fn apply(server: &Server) {
+    for config_file in &server.config_files {
+        if let Err(e) = apply_file(server, config_file).await {
+            tracing::warn!(
+                "Failed to patch config file {} of server {}: {}",
+                config_file.path,
+                server.id,
+                e
+            );
+        }
+    }
+}
+
+async fn apply_file(server: &Server, config_file: &ConfigFile) -> Result<(), String> {
+    let path = resolve_path(server.id, &config_file.path).await?;
+    let mut content = String::new();
+    match no_follow().read(true).open(&path).await {
+        Ok(mut file) => {
+            file.read_to_string(&mut content)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.to_string()),
+    }
+    for value in &config_file.values {
+        let rendered = startup::render_value(&value.value, server).map_err(|e| e.to_string())?;
+        content = match config_file.format {
+            ConfigFormat::Properties => patch_properties(&content, &value.key, &rendered),
+            ConfigFormat::Ini => patch_ini(&content, &value.key, &rendered),
+            ConfigFormat::Yaml => patch_yaml(&content, &value.key, &rendered)?,
+            ConfigFormat::Json => patch_json(&content, &value.key, &rendered)?,
+        };
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    // the folders may have been swapped for links in the meantime
+    let path = resolve_path(server.id, &config_file.path).await?;
+    let mut file = no_follow()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .await
+        .map_err(|e| e.to_string())?;
+    file.write_all(content.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    file.flush().await.map_err(|e| e.to_string())
+}
+
+/// Opens files refusing to follow a link in their place.
+fn no_follow() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.custom_flags(libc::O_NOFOLLOW);
+    options
+}
+
+/// The file in the server's volume. Anything in the container can write to the
+/// volume, so a path through a link is refused, wherever the link points.
+async fn resolve_path(id: i32, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err("path is outside of the volume".to_string());
+    }
+    let volume = tokio::fs::canonicalize(get_folder(id))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut existing = volume.clone();
+    for component in relative.components() {
+        existing.push(component);
+        match tokio::fs::symlink_metadata(&existing).await {
+            Ok(metadata) if metadata.is_symlink() => {
+                return Err(format!("{} is a link", existing.display()));
+            }
+            Ok(_) => {}
+            // nothing below a missing folder exists either
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(volume.join(relative))
+}
+
+fn lines(content: &str) -> Vec<String> {
+    content.lines().map(str::to_string).collect()
+}
+
+fn join_lines(lines: Vec<String>) -> String {
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+fn escape_properties(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Sets `key` in a Java properties file.
+fn patch_properties(content: &str, key: &str, value: &str) -> String {
+    let mut lines = lines(content);
+    let mut found = false;
+    for line in &mut lines {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('#') || trimmed.starts_with('!') {
+            continue;
+        }
+        let key_end = trimmed
+            .find(|c: char| c == '=' || c == ':' || c.is_whitespace())
+            .unwrap_or(trimmed.len());
+        if &trimmed[..key_end] != key {
+            continue;
+        }
+        // keeps the separator the file uses
+        let rest = &trimmed[key_end..];
+        let separator_end = rest.len()
+            - rest
+                .trim_start_matches(|c: char| c == '=' || c == ':' || c.is_whitespace())
+                .len();
+        let prefix = format!(
+            "{}{}",
+            &line[..line.len() - trimmed.len() + key_end],
+            &rest[..separator_end]
+        );
+        *line = format!("{}{}", prefix, escape_properties(value));
+        found = true;
+    }
+    if !found {
+        lines.push(format!("{}={}", key, escape_properties(value)));
+    }
+    join_lines(lines)
+}
+
+/// Sets `section.key` in an INI file, or `key` before the first section.
+fn patch_ini(content: &str, key: &str, value: &str) -> String {
+    let (section, key) = match key.rsplit_once('.') {
+        Some((section, key)) => (Some(section), key),
+        None => (None, key),
+    };
+    let mut lines = lines(content);
+    let mut current: Option<String> = None;
+    // index after the last line of the wanted section
+    let mut section_end = (section.is_none()).then_some(0);
+    let mut found = false;
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            current = Some(trimmed[1..trimmed.len() - 1].trim().to_string());
+            if current.as_deref() == section {
+                section_end = Some(i + 1);
+            }
+            continue;
+        }
+        if current.as_deref() != section {
+            continue;
+        }
+        if !trimmed.is_empty() {
+            section_end = Some(i + 1);
+        }
+        if trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+        let Some((line_key, _)) = line.split_once('=') else {
+            continue;
+        };
+        if line_key.trim() == key {
+            let spacing = if line_key.ends_with(' ') { " " } else { "" };
+            *line = format!("{}={}{}", line_key, spacing, value);
+            found = true;
+        }
+    }
+    if !found {
+        match section_end {
+            Some(end) => lines.insert(end, format!("{}={}", key, value)),
+            None => {
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("[{}]", section.unwrap_or_default()));
+                lines.push(format!("{}={}", key, value));
+            }
+        }
+    }
+    join_lines(lines)
+}
+
+struct YamlKey {
+    line: usize,
+    indent: usize,
+    path: Vec<String>,
+}
+
+fn yaml_keys(lines: &[String]) -> Vec<YamlKey> {
+    let key_line = Regex::new(r#"^(\s*)("[^"]*"|'[^']*'|[^\s#'"\-][^:]*?)\s*:(\s.*)?$"#).unwrap();
+    let mut keys = vec![];
+    // open mappings as (indent, key), list items are kept so keys inside them never match
+    let mut stack: Vec<(usize, String)> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        while stack.last().is_some_and(|(open, _)| *open >= indent) {
+            stack.pop();
+        }
+        if trimmed.starts_with("- ") || trimmed == "-" {
+            stack.push((indent, "[]".to_string()));
+            continue;
+        }
+        let Some(captures) = key_line.captures(line) else {
+            continue;
+        };
+        let key = captures[2]
+            .trim_matches(|c| c == '"' || c == '\'')
+            .to_string();
+        let mut path: Vec<String> = stack.iter().map(|(_, key)| key.clone()).collect();
+        path.push(key.clone());
+        keys.push(YamlKey {
+            line: i,
+            indent,
+            path,
+        });
+        stack.push((indent, key));
+    }
+    keys
+}
+
+/// Splits a YAML value from its trailing comment.
+fn split_comment(rest: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '#') if previous.is_whitespace() => return (&rest[..i], &rest[i..]),
+            _ => {}
+        }
+        previous = c;
+    }
+    (rest, "")
+}
+
+/// Writes a YAML scalar, quoted like the value it replaces.
+fn yaml_scalar(value: &str, previous: &str) -> String {
+    let previous = previous.trim();
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./@".contains(c));
+    if previous.starts_with('\'') {
+        format!("'{}'", value.replace('\'', "''"))
+    } else if previous.starts_with('"') || !plain {
+        serde_json::to_string(value).unwrap()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Index after the last line nested below a key, ignoring trailing blank lines.
+fn block_end(lines: &[String], key: &YamlKey) -> usize {
+    let mut end = key.line + 1;
+    for (i, line) in lines.iter().enumerate().skip(key.line + 1) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if line.len() - trimmed.len() <= key.indent {
+            break;
+        }
+        end = i + 1;
+    }
+    end
+}
+
+/// Sets a dotted `key` in a YAML file of block mappings.
+fn patch_yaml(content: &str, key: &str, value: &str) -> Result<String, String> {
+    let path: Vec<&str> = key.split('.').collect();
+    let mut lines = lines(content);
+    let keys = yaml_keys(&lines);
+
+    if let Some(found) = keys.iter().find(|found| found.path == path) {
+        let line = &lines[found.line];
+        let colon = line[found.indent..]
+            .find(':')
+            .map(|colon| found.indent + colon)
+            .unwrap();
+        let (previous, comment) = split_comment(&line[colon + 1..]);
+        let has_children = keys.iter().any(|child| {
+            child.line > found.line
+                && child.path.starts_with(&found.path)
+                && child.path.len() > path.len()
+        });
+        if has_children || previous.trim().starts_with('|') || previous.trim().starts_with('>') {
+            return Err(format!("{} is not a plain value", key));
+        }
+        let comment = if comment.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", comment)
+        };
+        lines[found.line] = format!(
+            "{}: {}{}",
+            &line[..colon],
+            yaml_scalar(value, previous),
+            comment
+        );
+        return Ok(join_lines(lines));
+    }
+
+    // adds the key below its closest existing parent
+    let parent = (1..path.len()).rev().find_map(|depth| {
+        keys.iter()
+            .find(|parent| parent.path == path[..depth])
+            .map(|parent| (depth, parent))
+    });
+    if let Some((depth, parent)) = parent {
+        let list = lines[parent.line + 1..]
+            .iter()
+            .map(|line| line.trim_start())
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .is_some_and(|line| line.starts_with("- ") || line == "-");
+        let line = &lines[parent.line];
+        let colon = parent.indent + line[parent.indent..].find(':').unwrap();
+        let scalar = !split_comment(&line[colon + 1..]).0.trim().is_empty();
+        if list || scalar {
+            return Err(format!("{} is not a mapping", path[..depth].join(".")));
+        }
+    }
+    let (depth, insert_at, indent) = parent
+        .map(|(depth, parent)| {
+            let indent = keys
+                .iter()
+                .find(|child| {
+                    child.line > parent.line
+                        && child.path.len() == depth + 1
+                        && child.path.starts_with(&parent.path)
+                })
+                .map_or(parent.indent + 2, |child| child.indent);
+            (depth, block_end(&lines, parent), indent)
+        })
+        .unwrap_or((0, lines.len(), 0));
+    let added: Vec<String> = path[depth..]
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let padding = " ".repeat(indent + 2 * i);
+            if depth + i == path.len() - 1 {
+                format!("{}{}: {}", padding, segment, yaml_scalar(value, ""))
+            } else {
+                format!("{}{}:", padding, segment)
+            }
+        })
+        .collect();
+    lines.splice(insert_at..insert_at, added);
+    Ok(join_lines(lines))
+}
+
+/// Sets a dotted `key` in a JSON file, keeping the type of the value it replaces.
+fn patch_json(content: &str, key: &str, value: &str) -> Result<String, String> {
+    let mut root: serde_json::Value = if content.trim().is_empty() {
+        serde_json::Value::Object(Default::default())
+    } else {
+        serde_json::from_str(content).map_err(|e| e.to_string())?
+    };
+    let mut current = &mut root;
+    let path: Vec<&str> = key.split('.').collect();
+    for segment in &path[..path.len() - 1] {
+        current = current
+            .as_object_mut()
+            .ok_or_else(|| format!("{} is not an object", segment))?
+            .entry(segment.to_string())
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    }
+    let object = current
+        .as_object_mut()
+        .ok_or_else(|| format!("parent of {} is not an object", key))?;
+    let typed = serde_json::from_str::<serde_json::Value>(value)
+        .ok()
+        .filter(|typed| typed.is_number() || typed.is_boolean());
+    let replaced = match (object.get(path[path.len() - 1]), typed) {
+        (Some(serde_json::Value::String(_)), _) | (_, None) => {
+            serde_json::Value::String(value.to_string())
+        }
+        (_, Some(typed)) => typed,
+    };
+    object.insert(path[path.len() - 1].to_string(), replaced);
+    let mut content = serde_json::to_string_pretty(&root).map_err(|e| e.to_string())?;
+    content.push('\n');
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use common::orch_types::ConfigValue;
+
+    use super::*;
+    use crate::test_utils::{server, TestAgent};
+
+    fn properties(id: i32, path: &str) -> Server {
+        let mut server = server(id);
+        server.config_files = vec![ConfigFile {
+            path: path.to_string(),
+            format: ConfigFormat::Properties,
+            values: vec![ConfigValue {
+                key: "server-port".to_string(),
+                value: "{{SERVER_PORT}}".to_string(),
+            }],
+        }];
+        server
+    }
+
+    fn volume(id: i32) -> PathBuf {
+        let volume = PathBuf::from(get_folder(id));
+        let _ = std::fs::remove_dir_all(&volume);
+        std::fs::create_dir_all(&volume).unwrap();
+        volume
+    }
+
+    #[tokio::test]
+    async fn creates_missing_files_and_folders() {
+        let _agent = TestAgent::new();
+        let volume = volume(201);
+        apply(&properties(201, "config/server.properties")).await;
+        assert_eq!(
+            std::fs::read_to_string(volume.join("config/server.properties")).unwrap(),
+            "server-port=25565\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_write_through_dangling_links() {
+        let _agent = TestAgent::new();
+        let volume = volume(202);
+        let outside =
+            std::env::temp_dir().join(format!("nerdpanel-config-outside-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&outside);
+        std::fs::create_dir_all(&outside).unwrap();
+        symlink(outside.join("planted"), volume.join("server.properties")).unwrap();
+        symlink(&outside, volume.join("config")).unwrap();
+
+        apply(&properties(202, "server.properties")).await;
+        apply(&properties(202, "config/new/server.properties")).await;
+        assert!(!outside.join("planted").exists());
+        assert!(!outside.join("new").exists());
+        assert!(volume.join("server.properties").is_symlink());
+    }
+
+    #[test]
+    fn properties_keep_comments_order_and_separators() {
+        let content = "#Minecraft server properties\nserver-port=25565\nmotd : hello\n! legacy comment\nmax-players=20\n";
+        assert_eq!(
+            patch_properties(content, "motd", "A\\B"),
+            "#Minecraft server properties\nserver-port=25565\nmotd : A\\\\B\n! legacy comment\nmax-players=20\n"
+        );
+        assert_eq!(
+            patch_properties(content, "server-port", "25570"),
+            content.replace("25565", "25570")
+        );
+    }
+
+    #[test]
+    fn properties_ignore_commented_keys_and_add_missing_ones() {
+        let content = "# server-port=1\nmotd=hello";
+        assert_eq!(
+            patch_properties(content, "server-port", "25565"),
+            "# server-port=1\nmotd=hello\nserver-port=25565\n"
+        );
+        assert_eq!(patch_properties("", "motd", "hi"), "motd=hi\n");
+    }
+
+    #[test]
+    fn ini_sets_keys_in_their_section() {
+        let content = "; global\nname = top\n\n[server]\n; port comment\nport = 1\nname=inner\n\n[other]\nport=2\n";
+        assert_eq!(
+            patch_ini(content, "server.port", "27015"),
+            "; global\nname = top\n\n[server]\n; port comment\nport = 27015\nname=inner\n\n[other]\nport=2\n"
+        );
+        assert_eq!(
+            patch_ini(content, "name", "changed"),
+            content.replace("name = top", "name = changed")
+        );
+    }
+
+    #[test]
+    fn ini_adds_missing_keys_and_sections() {
+        let content = "[server]\nport=1\n\n[other]\nport=2\n";
+        assert_eq!(
+            patch_ini(content, "server.name", "x"),
+            "[server]\nport=1\nname=x\n\n[other]\nport=2\n"
+        );
+        assert_eq!(
+            patch_ini(content, "rcon.password", "secret"),
+            "[server]\nport=1\n\n[other]\nport=2\n\n[rcon]\npassword=secret\n"
+        );
+        assert_eq!(
+            patch_ini(content, "top", "1"),
+            format!("top=1\n{}", content)
+        );
+    }
+
+    #[test]
+    fn ini_section_names_may_contain_dots() {
+        let content = "[a.b]\nkey=1\n";
+        assert_eq!(patch_ini(content, "a.b.key", "2"), "[a.b]\nkey=2\n");
+    }
+
+    #[test]
+    fn yaml_sets_nested_keys_keeping_comments_and_quotes() {
+        let content = "# settings\nserver:\n  host: 0.0.0.0 # bind\n  port: 25565\n  motd: 'hello'\nlisteners:\n  - port: 1\n";
+        assert_eq!(
+            patch_yaml(content, "server.port", "25570").unwrap(),
+            content.replace("25565", "25570")
+        );
+        assert_eq!(
+            patch_yaml(content, "server.host", "127.0.0.1").unwrap(),
+            content.replace("0.0.0.0 # bind", "127.0.0.1 # bind")
+        );
+        assert_eq!(
+            patch_yaml(content, "server.motd", "it's").unwrap(),
+            content.replace("'hello'", "'it''s'")
+        );
+        assert_eq!(
+            patch_yaml(content, "server.host", "a b").unwrap(),
+            content.replace("0.0.0.0", "\"a b\"")
+        );
+    }
+
+    #[test]
+    fn yaml_adds_missing_keys_below_their_parent() {
+        let content = "server:\n    port: 1\n\nother: x\n";
+        assert_eq!(
+            patch_yaml(content, "server.host", "h").unwrap(),
+            "server:\n    port: 1\n    host: h\n\nother: x\n"
+        );
+        assert_eq!(
+            patch_yaml(content, "server.rcon.port", "2").unwrap(),
+            "server:\n    port: 1\n    rcon:\n      port: 2\n\nother: x\n"
+        );
+        assert_eq!(
+            patch_yaml(content, "top.key", "v").unwrap(),
+            "server:\n    port: 1\n\nother: x\ntop:\n  key: v\n"
+        );
+    }
+
+    #[test]
+    fn yaml_refuses_to_replace_mappings_and_list_items() {
+        let content = "server:\n  port: 1\nlisteners:\n  - port: 1\n";
+        assert!(patch_yaml(content, "server", "x").is_err());
+        assert!(patch_yaml(content, "listeners.port", "2").is_err());
+        assert!(patch_yaml(content, "listeners.host", "h").is_err());
+        assert!(patch_yaml(content, "server.port.value", "2").is_err());
+    }
+
+    #[test]
+    fn json_keeps_order_and_value_types() {
+        let content = "{\"b\": {\"port\": 1, \"name\": \"x\"}, \"a\": \"1\"}";
+        let patched = patch_json(content, "b.port", "25565").unwrap();
+        assert_eq!(
+            patched,
+            "{\n  \"b\": {\n    \"port\": 25565,\n    \"name\": \"x\"\n  },\n  \"a\": \"1\"\n}\n"
+        );
+        let patched = patch_json(content, "a", "2").unwrap();
+        assert!(patched.contains("\"a\": \"2\""));
+    }
+
+    #[test]
+    fn json_adds_missing_objects() {
+        assert_eq!(
+            patch_json("", "a.b", "true").unwrap(),
+            "{\n  \"a\": {\n    \"b\": true\n  }\n}\n"
+        );
+        assert!(patch_json("{\"a\": 1}", "a.b", "x").is_err());
+        assert!(patch_json("not json", "a", "x").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bollard::{container::LogsOptions, models::EventMessage};
+use chrono::{DateTime, Utc};
+use common::agent_types::{CrashReport, ServerStatus};
+use futures_util::StreamExt;
+
+use crate::{
+    power, sidecar,
+    utils::{container_name, load_server_spec, CONTAINER_PREFIX},
+    AppState,
+};
+
+const CONSOLE_LINES: usize = 100;
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(300);
+/// A server that crashes this often within the window is considered crash
+/// looping and is no longer restarted automatically.
+const CRASH_LOOP_LIMIT: usize = 5;
+const CRASH_LOOP_WINDOW: chrono::Duration = chrono::Duration::minutes(10);
+
+#[derive(Default)]
+struct CrashHistory {
+    crashes: VecDeque<DateTime<Utc>>,
+    restart_disabled: bool,
+    report: Option<CrashReport>,
+}
+
+/// Recent crashes of every server, used for the restart backoff.
+#[derive(Clone, Default)]
+pub struct Crashes(Arc<Mutex<HashMap<i32, CrashHistory>>>);
+
+impl Crashes {
+    pub fn report(&self, id: i32) -> Option<CrashReport> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|history| history.report.clone())
+    }
+
+    /// Forgets the crash loop of a server, a manual start re-enables restarts.
+    pub fn reset(&self, id: i32) {
+        if let Some(history) = self.0.lock().unwrap().get_mut(&id) {
+            history.crashes.clear();
+            history.restart_disabled = false;
+        }
+    }
+
+    pub fn clear(&self, id: i32) {
+        self.0.lock().unwrap().remove(&id);
+    }
+
+    /// Records a crash and returns the report together with the delay before
+    /// the server should be restarted, if it should be restarted at all.
+    fn record(
+        &self,
+        id: i32,
+        exit_code: Option<i64>,
+        console: Vec<String>,
+        restart: bool,
+    ) -> (CrashReport, Option<Duration>) {
+        let now = Utc::now();
+        let mut histories = self.0.lock().unwrap();
+        let history = histories.entry(id).or_default();
+
+        history.crashes.push_back(now);
+        while history
+            .crashes
+            .front()
+            .is_some_and(|crashed_at| now - *crashed_at > CRASH_LOOP_WINDOW)
+        {
+            history.crashes.pop_front();
+        }
+        let recent = history.crashes.len();
+        if recent >= CRASH_LOOP_LIMIT {
+            history.restart_disabled = true;
+        }
+
+        let delay = (restart && !history.restart_disabled).then(|| {
+            RESTART_DELAY
+                .saturating_mul(1 << (recent - 1).min(16))
+                .min(MAX_RESTART_DELAY)
+        });
+        let report = CrashReport {
+            crashed_at: now,
+            exit_code,
+            console,
+            recent_crashes: recent as u32,
+            restart_in: delay.map(|delay| delay.as_secs()),
+            restart_disabled: history.restart_disabled,
+        };
+        history.report = Some(report.clone());
+        (report, delay)
+    }
+}
+
+/// Watches docker for containers that exit and handles the ones that were not
+/// stopped through the agent as crashes. Reconnects if the event stream breaks.
+pub async fn monitor(state: AppState) {
+    loop {
+        let mut events = state.runtime.events(&["die"]);
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => handle_exit(&state, event).await,
+                Err(e) => {
+                    tracing::error!("Docker event stream failed: {}", e);
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
+
+async fn handle_exit(state: &AppState, event: EventMessage) {
+    let Some(attributes) = event.actor.and_then(|actor| actor.attributes) else {
+        return;
+    };
+    let Some(id) = attributes
+        .get("name")
+        .and_then(|name| name.strip_prefix(CONTAINER_PREFIX))
+        .and_then(|id| id.parse::<i32>().ok())
+    else {
+        return;
+    };
+
+    // stops and kills through the agent always mark the server as stopping first
+    if matches!(
+        state.statuses.get(id),
+        Some(ServerStatus::Stopping | ServerStatus::Stopped | ServerStatus::Installing)
+    ) {
+        return;
+    }
+    state.statuses.set(id, ServerStatus::Crashed);
+
+    let exit_code = attributes
+        .get("exitCode")
+        .and_then(|code| code.parse::<i64>().ok());
+    let console = last_console_lines(state, id).await;
+    let restart = match load_server_spec(id).await {
+        Ok(server) => server.is_some_and(|server| server.restart_on_crash),
+        Err(e) => {
+            tracing::error!("Failed to load server {}: {}", id, e);
+            false
+        }
+    };
+
+    let (report, delay) = state.crashes.record(id, exit_code, console, restart);
+    tracing::warn!(
+        "Server {} crashed with exit code {:?} ({} crashes recently)",
+        id,
+        exit_code,
+        report.recent_crashes
+    );
+    if report.restart_disabled {
+        tracing::warn!("Server {} is crash looping, not restarting it", id);
+    }
+
+    let Some(delay) = delay else {
+        // the server stays down, and so do its sidecars
+        if let Err(e) = sidecar::stop(state.runtime.as_ref(), id).await {
+            tracing::error!("Failed to stop sidecars of crashed server {}: {}", id, e);
+        }
+        return;
+    };
+    tracing::info!("Restarting server {} in {}s", id, delay.as_secs());
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        // the server was started, stopped or deleted in the meantime
+        if state.statuses.get(id) != Some(ServerStatus::Crashed) {
+            return;
+        }
+        if let Err(e) = power::start(&state, id).await {
+            tracing::error!("Failed to restart crashed server {}: {}", id, e);
+        }
+    });
+}
+
+async fn last_console_lines(state: &AppState, id: i32) -> Vec<String> {
+    let mut logs = state.runtime.logs(
+        &container_name(id),
+        LogsOptions {
+            stdout: true,
+            stderr: true,
+            tail: CONSOLE_LINES.to_string(),
+            ..Default::default()
+        },
+    );
+
+    let mut console = String::new();
+    while let Some(output) = logs.next().await {
+        match output {
+            Ok(output) => console.push_str(&String::from_utf8_lossy(&output.into_bytes())),
+            Err(e) => {
+                tracing::warn!("Failed to read console of server {}: {}", id, e);
+                break;
+            }
+        }
+    }
+    console.lines().map(str::to_string).collect()
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    io,
+    os::unix::fs::{self as unix_fs, MetadataExt},
+    path::Path,
+};
+
+use bollard::secret::HostConfig;
+use common::orch_types::Server;
+
+use crate::{config::Config, utils::get_folder};
+
+// Server containers run with the restrictions of their pod's hardening
+// settings. Running as the node's container user only works if that user owns
+// the volume, so it is handed over before each start: installers, restores and
+// transfers write into it as root.
+
+pub const NO_NEW_PRIVILEGES: &str = "no-new-privileges:true";
+const TMPFS_OPTIONS: &str = "rw,nosuid,nodev";
+
+/// Restricts a server's container according to its pod.
+pub fn apply(host_config: &mut HostConfig, server: &Server) {
+    let hardening = &server.hardening;
+    if hardening.drop_capabilities {
+        host_config.cap_drop = Some(vec!["ALL".to_string()]);
+    }
+    if hardening.no_new_privileges {
+        host_config.security_opt = Some(vec![NO_NEW_PRIVILEGES.to_string()]);
+    }
+    host_config.pids_limit = hardening.pid_limit;
+    if hardening.read_only_root {
+        host_config.readonly_rootfs = Some(true);
+        host_config.tmpfs = Some(
+            hardening
+                .tmpfs
+                .iter()
+                .map(|path| (path.clone(), TMPFS_OPTIONS.to_string()))
+                .collect::<HashMap<_, _>>(),
+        );
+    }
+}
+
+/// The user a server's container runs as, the image's if left out.
+pub fn user(config: &Config, server: &Server) -> Option<String> {
+    server
+        .hardening
+        .non_root_user
+        .then(|| format!("{}:{}", config.container_uid, config.container_gid))
+}
+
+fn chown_all(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    let metadata = path.symlink_metadata()?;
+    if metadata.uid() != uid || metadata.gid() != gid {
+        unix_fs::lchown(path, Some(uid), Some(gid))?;
+    }
+    // links are not followed, they could point out of the volume
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            chown_all(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hands the server's volume to the container user, if the server runs as it.
+/// Failing to is logged, the server may still be able to start.
+pub async fn own_volume(config: &Config, server: &Server) {
+    if !server.hardening.non_root_user {
+        return;
+    }
+    let (id, uid, gid) = (server.id, config.container_uid, config.container_gid);
+    let folder = get_folder(id);
+    let result = tokio::task::spawn_blocking(move || chown_all(Path::new(&folder), uid, gid)).await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!("Failed to hand the volume of server {} over: {}", id, e),
+        Err(e) => tracing::warn!("Failed to hand the volume of server {} over: {}", id, e),
+    }
+}
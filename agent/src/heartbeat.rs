@@ -0,0 +1,76 @@
+use std::{path::Path, time::Duration};
+
+use common::agent_types::{Heartbeat, HostResources};
+use sysinfo::{Disks, System};
+
+use crate::{
+    utils::{get_volume_root, AppError},
+    AppState,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Reports to the orchestrator that this agent is alive, along with the state of
+/// the host.
+pub async fn monitor(state: AppState) {
+    if state.config.node_token.is_none() {
+        tracing::warn!("No node token configured, not sending heartbeats");
+        return;
+    }
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = send(&state).await {
+            tracing::warn!("Failed to send heartbeat: {}", e);
+        }
+    }
+}
+
+async fn send(state: &AppState) -> Result<(), AppError> {
+    let docker_version = match state.runtime.version().await {
+        Ok(version) => version.version,
+        Err(e) => {
+            tracing::error!("Docker is unreachable: {}", e);
+            None
+        }
+    };
+    let resources = tokio::task::spawn_blocking(host_resources)
+        .await
+        .map_err(|_| AppError::InternalError)?;
+    let heartbeat = Heartbeat {
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        docker_version,
+        resources,
+    };
+
+    state.orchestrator.send_heartbeat(&heartbeat).await
+}
+
+fn host_resources() -> HostResources {
+    let mut system = System::new();
+    system.refresh_memory();
+
+    // the disk with the longest mount point containing the volumes is the one they are on
+    let volumes = std::fs::canonicalize(get_volume_root()).unwrap_or_else(|_| "/".into());
+    let disks = Disks::new_with_refreshed_list();
+    let disk = disks
+        .iter()
+        .filter(|disk| volumes.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .or_else(|| {
+            disks
+                .iter()
+                .find(|disk| disk.mount_point() == Path::new("/"))
+        });
+
+    HostResources {
+        cpu_count: std::thread::available_parallelism()
+            .map(|count| count.get() as u32)
+            .unwrap_or(1),
+        load_average: System::load_average().one,
+        memory_total: system.total_memory(),
+        memory_available: system.available_memory(),
+        disk_total: disk.map(|disk| disk.total_space()).unwrap_or(0),
+        disk_available: disk.map(|disk| disk.available_space()).unwrap_or(0),
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bollard::{
+    container::{Config, LogsOptions},
+    models::{HostConfig, Mount, MountTypeEnum},
+};
+use chrono::Utc;
+use common::{
+    agent_types::{InstallOptions, InstallReport, ServerStatus},
+    orch_types::Server,
+};
+use futures_util::TryStreamExt;
+
+use crate::{
+    power,
+    utils::{get_folder, get_installer_folder, load_server_spec, set_should_run, AppError},
+    AppState,
+};
+
+const CONSOLE_LINES: usize = 100;
+/// Installer containers are named apart from server containers, so the crash
+/// monitor and the reconciler leave them alone.
+const INSTALLER_PREFIX: &str = "nerdpanel-installer-";
+
+fn installer_name(id: i32) -> String {
+    format!("{}{}", INSTALLER_PREFIX, id)
+}
+
+/// The last install of every server.
+#[derive(Clone, Default)]
+pub struct Installs(Arc<Mutex<HashMap<i32, InstallReport>>>);
+
+impl Installs {
+    pub fn report(&self, id: i32) -> Option<InstallReport> {
+        self.0.lock().unwrap().get(&id).cloned()
+    }
+
+    fn update(&self, id: i32, update: impl FnOnce(&mut InstallReport)) {
+        if let Some(report) = self.0.lock().unwrap().get_mut(&id) {
+            update(report);
+        }
+    }
+
+    pub fn clear(&self, id: i32) {
+        self.0.lock().unwrap().remove(&id);
+    }
+}
+
+/// Stops the server and starts its pod's installer in the background. The
+/// server is reported as installing until the installer exited.
+pub async fn start(state: &AppState, id: i32, options: InstallOptions) -> Result<(), AppError> {
+    let server = load_server_spec(id).await?.ok_or(AppError::NotFound)?;
+    if server.installer_image.is_empty() {
+        return Err(AppError::BadRequest(
+            "the server's pod has no installer".to_string(),
+        ));
+    }
+    if power::status(state, id).await? == ServerStatus::Installing {
+        return Err(AppError::Conflict(
+            "server is already being installed".to_string(),
+        ));
+    }
+
+    set_should_run(id, false).await?;
+    power::stop(state, id).await?;
+    state.statuses.set(id, ServerStatus::Installing);
+    state.crashes.clear(id);
+    state.installs.0.lock().unwrap().insert(
+        id,
+        InstallReport {
+            started_at: Utc::now(),
+            finished_at: None,
+            wiped: options.wipe,
+            exit_code: None,
+            error: None,
+            console: vec![],
+        },
+    );
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let result = install(&state, &server, options).await;
+        let console = last_console_lines(&state, id).await;
+        remove_installer(&state, id).await;
+
+        let (status, exit_code, error) = match result {
+            Ok(0) => (ServerStatus::Stopped, Some(0), None),
+            Ok(code) => (
+                ServerStatus::InstallFailed,
+                Some(code),
+                Some(format!("installer exited with code {}", code)),
+            ),
+            Err(e) => (ServerStatus::InstallFailed, None, Some(e.to_string())),
+        };
+        match &error {
+            None => tracing::info!("Installed server {}", id),
+            Some(error) => tracing::error!("Failed to install server {}: {}", id, error),
+        }
+        state.installs.update(id, |report| {
+            report.finished_at = Some(Utc::now());
+            report.exit_code = exit_code;
+            report.error = error;
+            report.console = console;
+        });
+        state.statuses.set(id, status);
+    });
+    Ok(())
+}
+
+/// Runs the installer and returns its exit code.
+async fn install(
+    state: &AppState,
+    server: &Server,
+    options: InstallOptions,
+) -> Result<i64, AppError> {
+    let folder = get_folder(server.id);
+    if options.wipe {
+        tracing::info!("Wiping the files of server {}", server.id);
+        match tokio::fs::remove_dir_all(&folder).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    tokio::fs::create_dir_all(&folder).await?;
+    let folder = tokio::fs::canonicalize(&folder).await?;
+    let mut mounts = vec![bind(&folder, "/data")];
+    let mut cmd = None;
+    if let Some(script) = &server.installer_script {
+        // pods imported from eggs expect their files and script at these paths
+        let script_folder = PathBuf::from(get_installer_folder(server.id));
+        tokio::fs::create_dir_all(&script_folder).await?;
+        tokio::fs::write(script_folder.join("install.sh"), script).await?;
+        let script_folder = tokio::fs::canonicalize(&script_folder).await?;
+        mounts.push(bind(&folder, "/mnt/server"));
+        mounts.push(bind(&script_folder, "/mnt/install"));
+        cmd = Some(vec![
+            server.installer_entrypoint.clone(),
+            "/mnt/install/install.sh".to_string(),
+        ]);
+    }
+
+    state.runtime.pull_image(&server.installer_image).await?;
+
+    // a previous install may have left its container behind
+    remove_installer(state, server.id).await;
+    let config = Config {
+        image: Some(server.installer_image.clone()),
+        env: Some(
+            server
+                .env_vars
+                .iter()
+                .map(|env_var| format!("{}={}", env_var.key, env_var.value))
+                .chain([
+                    format!("SERVER_IMAGE={}", server.image),
+                    format!("STARTUP={}", server.startup_command),
+                ])
+                .collect(),
+        ),
+        cmd,
+        host_config: Some(HostConfig {
+            mounts: Some(mounts),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    state
+        .runtime
+        .create(&installer_name(server.id), config)
+        .await?;
+    state.runtime.start(&installer_name(server.id)).await?;
+    Ok(state.runtime.wait(&installer_name(server.id)).await?)
+}
+
+fn bind(source: &Path, target: &str) -> Mount {
+    Mount {
+        target: Some(target.to_string()),
+        source: Some(source.to_string_lossy().to_string()),
+        typ: Some(MountTypeEnum::BIND),
+        ..Default::default()
+    }
+}
+
+async fn remove_installer(state: &AppState, id: i32) {
+    let removed = state.runtime.remove(&installer_name(id), true).await;
+    match removed {
+        Ok(())
+        | Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, ..
+        }) => {}
+        Err(e) => tracing::warn!("Failed to remove installer of server {}: {}", id, e),
+    }
+    match tokio::fs::remove_dir_all(get_installer_folder(id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            tracing::warn!("Failed to remove install script of server {}: {}", id, e)
+        }
+        _ => {}
+    }
+}
+
+async fn last_console_lines(state: &AppState, id: i32) -> Vec<String> {
+    let logs = state
+        .runtime
+        .logs(
+            &installer_name(id),
+            LogsOptions {
+                stdout: true,
+                stderr: true,
+                tail: CONSOLE_LINES.to_string(),
+                ..Default::default()
+            },
+        )
+        .try_collect::<Vec<_>>()
+        .await;
+    match logs {
+        Ok(logs) => logs
+            .into_iter()
+            .map(|output| String::from_utf8_lossy(&output.into_bytes()).into_owned())
+            .collect::<String>()
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        Err(e) => {
+            tracing::warn!(
+                "Failed to read the installer output of server {}: {}",
+                id,
+                e
+            );
+            vec![]
+        }
+    }
+}
@@ -1,20 +1,68 @@
-use axum::extract::Request;
-use bollard::Docker;
+use std::sync::Arc;
+
+use axum::{extract::Request, middleware};
+use backup::repository::Repository;
+use common::agent_types::DriftReport;
+use config::Config;
+use crash::Crashes;
+use install::Installs;
+use orchestrator::OrchestratorClient;
 use routes::ApiDoc;
+use runtime::{docker::DockerRuntime, ContainerRuntime};
+use state::ServerStates;
 use tower_http::trace::TraceLayer;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+use transfer::Transfers;
 use utoipa::OpenApi;
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_swagger_ui::SwaggerUi;
 
+mod auth;
+mod backup;
+mod config;
+mod config_files;
+mod crash;
+mod hardening;
+mod heartbeat;
+mod install;
+mod network;
+mod orchestrator;
+mod power;
+mod reconcile;
 mod routes;
+mod runtime;
 mod server;
+mod sidecar;
+mod state;
+#[cfg(test)]
+mod test_utils;
+mod transfer;
 mod utils;
 #[derive(Clone)]
 pub struct AppState {
-    orchestrator_fqdn: String,
-    docker: Docker,
+    config: Config,
+    orchestrator: OrchestratorClient,
+    runtime: Arc<dyn ContainerRuntime>,
+    backups: Arc<Repository>,
+    statuses: ServerStates,
+    crashes: Crashes,
+    drift: Arc<tokio::sync::Mutex<Option<DriftReport>>>,
+    transfers: Transfers,
+    installs: Installs,
+}
+
+/// The agent's routes and their OpenAPI document.
+fn api(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .nest("/server", server::server_routes())
+        .nest("/backup", backup::backup_routes())
+        .nest("/reconcile", reconcile::reconcile_routes())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_orchestrator,
+        ))
+        .nest("/transfer", transfer::transfer_routes(state))
 }
 
 #[tokio::main]
@@ -46,16 +94,27 @@ async fn main() {
         .try_init()
         .unwrap();
 
-    let docker = Docker::connect_with_local_defaults().unwrap();
+    let runtime = DockerRuntime::connect().unwrap();
+    let backups = Repository::open(utils::get_backup_repository()).unwrap();
+    let config = Config::from_env();
+    let orchestrator =
+        OrchestratorClient::new(&config).expect("Failed to set up the orchestrator client.");
     let state = AppState {
-        // TODO: get orchestrator_fqdn from env
-        orchestrator_fqdn: "http://localhost:3000".to_string(),
-        docker,
+        config,
+        orchestrator,
+        runtime: Arc::new(runtime),
+        backups: Arc::new(backups),
+        statuses: ServerStates::default(),
+        crashes: Crashes::default(),
+        drift: Default::default(),
+        transfers: Transfers::default(),
+        installs: Installs::default(),
     };
+    tokio::spawn(crash::monitor(state.clone()));
+    tokio::spawn(reconcile::monitor(state.clone()));
+    tokio::spawn(heartbeat::monitor(state.clone()));
 
-    let app = OpenApiRouter::with_openapi(ApiDoc::openapi());
-    let app = app.nest("/server", server::server_routes());
-    let (app, api) = app.split_for_parts();
+    let (app, api) = api(state.clone()).split_for_parts();
     let app = app.with_state(state);
     let app = app.merge(SwaggerUi::new("/api").url("/api/openapi.json", api));
     let app = app.layer(TraceLayer::new_for_http().make_span_with(|req: &Request| {
@@ -67,3 +126,93 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind("0.0.0.0:5000").await.unwrap();
     axum::serve(listener, app).await.unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::http::{Method, StatusCode};
+    use common::{
+        agent_client::{AgentClient, AgentClientConfig, AgentError, OPERATIONS},
+        agent_types::{IncomingTransfer, ServerStatus},
+    };
+
+    use crate::test_utils::{server, TestAgent, NODE_TOKEN};
+
+    #[test]
+    fn client_covers_every_operation() {
+        let (_, doc) = super::api(TestAgent::new().state).split_for_parts();
+        let mut served = BTreeSet::new();
+        for (path, item) in doc.paths.paths {
+            let methods = [
+                (Method::GET, item.get),
+                (Method::PUT, item.put),
+                (Method::POST, item.post),
+                (Method::DELETE, item.delete),
+                (Method::PATCH, item.patch),
+            ];
+            for (method, operation) in methods {
+                if operation.is_some() {
+                    served.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+        let called: BTreeSet<_> = OPERATIONS
+            .iter()
+            .map(|operation| (operation.method.to_string(), operation.path.to_string()))
+            .collect();
+        assert_eq!(served, called);
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_the_node_token() {
+        let agent = TestAgent::new();
+        let uri = "/server/118";
+        let none = agent.request_as(None, Method::GET, uri, None::<&()>).await;
+        assert_eq!(none.0, StatusCode::UNAUTHORIZED);
+        // a token that only shares a prefix is as wrong as any other
+        let token = &NODE_TOKEN[..NODE_TOKEN.len() - 1];
+        let wrong = agent
+            .request_as(Some(token), Method::GET, uri, None::<&()>)
+            .await;
+        assert_eq!(wrong.0, StatusCode::UNAUTHORIZED);
+
+        // the archive is authenticated by the transfer's token alone
+        let incoming = IncomingTransfer {
+            token: "transfer-token".to_string(),
+        };
+        let status = agent
+            .send(Method::PUT, "/transfer/118/incoming", &incoming)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let archive = "/transfer/118/archive";
+        let (status, _) = agent
+            .request_as(Some("transfer-token"), Method::PUT, archive, None::<&()>)
+            .await;
+        assert_ne!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn client_talks_to_the_agent() {
+        let agent = TestAgent::new();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = agent.app.clone();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = AgentClient::new(AgentClientConfig::default()).unwrap();
+
+        let remote = client.agent(&url, Some(NODE_TOKEN));
+        remote.create_server(&server(119)).await.unwrap();
+        assert_eq!(remote.get_status(119).await.unwrap(), ServerStatus::Stopped);
+        assert!(matches!(
+            remote.send_command(119, "say hi").await,
+            Err(AgentError::Conflict(_))
+        ));
+
+        let stranger = client.agent(&url, Some("wrong"));
+        assert!(matches!(
+            stranger.get_status(119).await,
+            Err(AgentError::Unauthorized)
+        ));
+    }
+}
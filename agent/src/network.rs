@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use bollard::{container::NetworkingConfig, secret::EndpointSettings};
+
+use crate::{runtime::ContainerRuntime, utils::AppError};
+
+// Every server runs on a bridge network of its own, so servers on the same node
+// can not reach each other. Only the server's container and its sidecars join it.
+
+const NETWORK_PREFIX: &str = "nerdpanel-network-";
+/// Host name of the server's own container on its network.
+pub const SERVER_ALIAS: &str = "server";
+
+pub fn network_name(id: i32) -> String {
+    format!("{}{}", NETWORK_PREFIX, id)
+}
+
+/// Joins a container to the server's network under an alias.
+pub fn networking_config(id: i32, alias: &str) -> NetworkingConfig<String> {
+    NetworkingConfig {
+        endpoints_config: HashMap::from([(
+            network_name(id),
+            EndpointSettings {
+                aliases: Some(vec![alias.to_string()]),
+                ..Default::default()
+            },
+        )]),
+    }
+}
+
+fn is_not_found(e: &bollard::errors::Error) -> bool {
+    matches!(
+        e,
+        bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
+/// Creates the server's network, unless it exists already.
+pub async fn create(runtime: &dyn ContainerRuntime, id: i32) -> Result<(), AppError> {
+    Ok(runtime.create_network(&network_name(id)).await?)
+}
+
+/// Removes the server's network, which no container may be attached to anymore.
+pub async fn remove(runtime: &dyn ContainerRuntime, id: i32) -> Result<(), AppError> {
+    match runtime.remove_network(&network_name(id)).await {
+        Err(e) if !is_not_found(&e) => Err(e.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use common::{agent_types::Heartbeat, orch_types::Server};
+use reqwest::{Certificate, Method, Response};
+use serde::Serialize;
+
+use crate::{config::Config, utils::AppError};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a call is retried while no connection to the orchestrator can be made.
+const RETRIES: u32 = 3;
+/// Delay before the first retry, doubled for every further one.
+const BACKOFF: Duration = Duration::from_millis(250);
+
+/// Client of the orchestrator's remote API, which the agent authenticates to
+/// with its node token. Clones share one connection pool.
+#[derive(Clone)]
+pub struct OrchestratorClient {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl OrchestratorClient {
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(TIMEOUT);
+        if let Some(pem) = &config.orchestrator_ca {
+            builder = builder.add_root_certificate(Certificate::from_pem(pem)?);
+        }
+        Ok(Self {
+            http: builder.build()?,
+            url: config.orchestrator_url.clone(),
+            token: config.node_token.clone(),
+        })
+    }
+
+    async fn send<B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<Response, AppError> {
+        let token = self
+            .token
+            .as_deref()
+            .ok_or_else(|| AppError::OrchestratorError("no node token configured".to_string()))?;
+        let url = format!("{}/api/remote{}", self.url, path);
+        let mut delay = BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.http.request(method.clone(), &url).bearer_auth(token);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let res = request.send().await;
+            // only a request that never reached the orchestrator is safe to send again
+            let unreachable = res.as_ref().is_err_and(|e| e.is_connect());
+            if !unreachable || attempt == RETRIES {
+                let res = res?;
+                if !res.status().is_success() {
+                    return Err(AppError::OrchestratorError(res.text().await?));
+                }
+                return Ok(res);
+            }
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    /// The servers the orchestrator expects on this node.
+    pub async fn get_servers(&self) -> Result<Vec<Server>, AppError> {
+        let res = self.send(Method::GET, "/servers", None::<&()>).await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn send_heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), AppError> {
+        self.send(Method::POST, "/heartbeat", Some(heartbeat))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::get,
+        Json, Router,
+    };
+    use common::orch_types::Server;
+
+    use super::OrchestratorClient;
+    use crate::{config::Config, test_utils::server};
+
+    fn config(url: String, token: &str) -> Config {
+        Config {
+            orchestrator_url: url,
+            node_token: Some(token.to_string()),
+            orchestrator_ca: None,
+            container_uid: 1000,
+            container_gid: 1000,
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_servers_with_the_node_token() {
+        let app = Router::new().route(
+            "/api/remote/servers",
+            get(|headers: HeaderMap| async move {
+                match headers.get("authorization") {
+                    Some(value) if value == "Bearer node" => Ok(Json(vec![server(124)])),
+                    _ => Err((StatusCode::UNAUTHORIZED, "unknown node")),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = OrchestratorClient::new(&config(url.clone(), "node")).unwrap();
+        let servers: Vec<Server> = client.get_servers().await.unwrap();
+        assert_eq!(servers[0].id, 124);
+
+        let stranger = OrchestratorClient::new(&config(url, "stranger")).unwrap();
+        let error = stranger.get_servers().await.err().unwrap();
+        assert_eq!(error.to_string(), "Orchestrator error: unknown node");
+    }
+}
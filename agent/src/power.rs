@@ -0,0 +1,275 @@
+use std::time::Duration;
+
+use bollard::container::LogsOptions;
+use chrono::Utc;
+use common::{
+    agent_types::{ServerStatus, ServerStatusReport},
+    orch_types::StopMethod,
+};
+use futures_util::StreamExt;
+use regex::Regex;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    config_files, hardening,
+    runtime::ContainerRuntime,
+    sidecar,
+    utils::{container_name, load_server_spec, AppError},
+    AppState,
+};
+
+const DEFAULT_STOP_TIMEOUT: i32 = 30;
+
+pub async fn is_running(runtime: &dyn ContainerRuntime, id: i32) -> Result<bool, AppError> {
+    Ok(runtime
+        .inspect(&container_name(id))
+        .await?
+        .state
+        .unwrap()
+        .running
+        .unwrap())
+}
+
+/// Combines the tracked lifecycle status with what docker reports, so servers
+/// that were started or died behind the agent's back are still reported right.
+pub async fn status(state: &AppState, id: i32) -> Result<ServerStatus, AppError> {
+    let running = match is_running(state.runtime.as_ref(), id).await {
+        Ok(running) => running,
+        Err(AppError::DockerError(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            ..
+        })) => return Ok(ServerStatus::Unknown),
+        Err(e) => return Err(e),
+    };
+
+    let status = match (state.statuses.get(id), running) {
+        (Some(ServerStatus::Starting | ServerStatus::Running), false) => {
+            state.statuses.set(id, ServerStatus::Crashed);
+            ServerStatus::Crashed
+        }
+        (Some(status @ (ServerStatus::Starting | ServerStatus::Running)), true) => status,
+        (Some(status @ (ServerStatus::Stopping | ServerStatus::Installing)), _) => status,
+        (Some(status), false) => status,
+        (_, true) => ServerStatus::Running,
+        (None, false) => ServerStatus::Stopped,
+    };
+    Ok(status)
+}
+
+/// Starts a server. It is reported as starting until the pod's done pattern
+/// shows up in the console output, or right away as running if the pod has none.
+pub async fn start(state: &AppState, id: i32) -> Result<(), AppError> {
+    let server = load_server_spec(id).await?;
+    // covers crash restarts and restores too, not only start signals
+    if server.as_ref().is_some_and(|server| server.suspended) {
+        return Err(AppError::Conflict("server is suspended".to_string()));
+    }
+    if let Some(server) = &server {
+        config_files::apply(server).await;
+        hardening::own_volume(&state.config, server).await;
+        sidecar::start(state.runtime.as_ref(), server).await?;
+    }
+    let done = server
+        .and_then(|server| server.done_regex)
+        .and_then(|done| match Regex::new(&done) {
+            Ok(done) => Some(done),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid done regex of server {}: {}", id, e);
+                None
+            }
+        });
+
+    let since = Utc::now().timestamp();
+    state.statuses.set(id, ServerStatus::Starting);
+    if let Err(e) = state.runtime.start(&container_name(id)).await {
+        state.statuses.set(id, ServerStatus::Stopped);
+        return Err(e.into());
+    }
+
+    match done {
+        Some(done) => {
+            tokio::spawn(watch_startup(state.clone(), id, done, since));
+        }
+        None => {
+            state
+                .statuses
+                .transition(id, &[ServerStatus::Starting], ServerStatus::Running);
+        }
+    }
+    Ok(())
+}
+
+async fn watch_startup(state: AppState, id: i32, done: Regex, since: i64) {
+    let mut logs = state.runtime.logs(
+        &container_name(id),
+        LogsOptions {
+            follow: true,
+            stdout: true,
+            stderr: true,
+            since,
+            ..Default::default()
+        },
+    );
+
+    let mut buffer = String::new();
+    while let Some(Ok(output)) = logs.next().await {
+        if state.statuses.get(id) != Some(ServerStatus::Starting) {
+            return;
+        }
+        buffer.push_str(&String::from_utf8_lossy(&output.into_bytes()));
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            if done.is_match(line.trim_end()) {
+                state
+                    .statuses
+                    .transition(id, &[ServerStatus::Starting], ServerStatus::Running);
+                return;
+            }
+        }
+        // prompts are often printed without a trailing newline
+        if done.is_match(&buffer) {
+            state
+                .statuses
+                .transition(id, &[ServerStatus::Starting], ServerStatus::Running);
+            return;
+        }
+    }
+
+    // the log stream only ends when the process exited
+    state
+        .statuses
+        .transition(id, &[ServerStatus::Starting], ServerStatus::Crashed);
+}
+
+/// Writes a line to the stdin of the server process.
+pub async fn send_command(
+    runtime: &dyn ContainerRuntime,
+    id: i32,
+    command: &str,
+) -> Result<(), AppError> {
+    let mut attach = runtime.attach(&container_name(id)).await?;
+    attach
+        .input
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    attach.input.flush().await?;
+    Ok(())
+}
+
+async fn wait_for_exit(runtime: &dyn ContainerRuntime, id: i32) {
+    // the container may have been removed in the meantime, either way it is gone
+    let _ = runtime.wait(&container_name(id)).await;
+}
+
+/// Stops a server using the stop method of its pod and waits for the process to
+/// exit. A server that does not exit within the pod's stop timeout is killed.
+pub async fn stop(state: &AppState, id: i32) -> Result<(), AppError> {
+    if !is_running(state.runtime.as_ref(), id).await? {
+        // sidecars keep running when the server crashes
+        return sidecar::stop(state.runtime.as_ref(), id).await;
+    }
+
+    let (method, timeout) = match load_server_spec(id).await? {
+        Some(server) => (server.stop_method, server.stop_timeout),
+        None => (StopMethod::Docker, DEFAULT_STOP_TIMEOUT),
+    };
+    let timeout = Duration::from_secs(timeout.max(0) as u64);
+
+    let previous = state.statuses.get(id);
+    state.statuses.set(id, ServerStatus::Stopping);
+    if let Err(e) = stop_with(state.runtime.as_ref(), id, method, timeout).await {
+        restore_status(state, id, previous);
+        return Err(e);
+    }
+    state.statuses.set(id, ServerStatus::Stopped);
+    sidecar::stop(state.runtime.as_ref(), id).await
+}
+
+/// Puts back the status a server had before a stop that failed, it may well
+/// still be running.
+fn restore_status(state: &AppState, id: i32, previous: Option<ServerStatus>) {
+    match previous {
+        Some(status) => state.statuses.set(id, status),
+        None => state.statuses.clear(id),
+    }
+}
+
+async fn stop_with(
+    runtime: &dyn ContainerRuntime,
+    id: i32,
+    method: StopMethod,
+    timeout: Duration,
+) -> Result<(), AppError> {
+    let requested = match method {
+        StopMethod::Command { command } => send_command(runtime, id, &command).await,
+        StopMethod::Signal { signal } => runtime
+            .kill(&container_name(id), Some(signal))
+            .await
+            .map_err(AppError::from),
+        StopMethod::Docker => runtime
+            .stop(&container_name(id), timeout.as_secs() as i64)
+            .await
+            .map_err(AppError::from),
+    };
+    if let Err(e) = requested {
+        tracing::warn!("Failed to ask server {} to stop: {}, killing", id, e);
+        return kill(runtime, id).await;
+    }
+
+    if tokio::time::timeout(timeout, wait_for_exit(runtime, id))
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "Server {} did not stop within {}s, killing",
+            id,
+            timeout.as_secs()
+        );
+        return kill(runtime, id).await;
+    }
+    Ok(())
+}
+
+async fn kill(runtime: &dyn ContainerRuntime, id: i32) -> Result<(), AppError> {
+    match runtime.kill(&container_name(id), None).await {
+        Ok(()) => Ok(()),
+        // the container exited on its own in the meantime
+        Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 409, ..
+        }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Kills a server immediately, without giving it a chance to save.
+pub async fn force_stop(state: &AppState, id: i32) -> Result<(), AppError> {
+    let previous = state.statuses.get(id);
+    state.statuses.set(id, ServerStatus::Stopping);
+    if let Err(e) = kill(state.runtime.as_ref(), id).await {
+        restore_status(state, id, previous);
+        return Err(e);
+    }
+    state.statuses.set(id, ServerStatus::Stopped);
+    sidecar::stop(state.runtime.as_ref(), id).await
+}
+
+/// Status of a server together with its sidecars.
+pub async fn report(state: &AppState, id: i32) -> Result<ServerStatusReport, AppError> {
+    let status = status(state, id).await?;
+    let sidecars = match load_server_spec(id).await? {
+        Some(server) => sidecar::statuses(state.runtime.as_ref(), &server).await?,
+        None => vec![],
+    };
+    let usage = match status {
+        // the server may have stopped since, usage is left out then
+        ServerStatus::Starting | ServerStatus::Running => {
+            state.runtime.stats(&container_name(id)).await.ok()
+        }
+        _ => None,
+    };
+    Ok(ServerStatusReport {
+        status,
+        sidecars,
+        usage,
+    })
+}
@@ -0,0 +1,159 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use common::{agent_types::DriftReport, orch_types::Server};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    power,
+    utils::{
+        container_name, create_container, load_server_spec, should_run, AppError, CONTAINER_PREFIX,
+    },
+    AppState,
+};
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(300);
+
+pub fn reconcile_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(last_report, run))
+}
+
+/// Reconciles once at startup and then periodically.
+pub async fn monitor(state: AppState) {
+    if state.config.node_token.is_none() {
+        tracing::warn!("No node token configured, not reconciling servers");
+        return;
+    }
+    loop {
+        if let Err(e) = reconcile(&state).await {
+            tracing::error!("Failed to reconcile servers: {}", e);
+        }
+        tokio::time::sleep(RECONCILE_INTERVAL).await;
+    }
+}
+
+/// Returns every server container on this host and whether it is running.
+async fn list_containers(state: &AppState) -> Result<HashMap<i32, bool>, AppError> {
+    let containers = state.runtime.list(CONTAINER_PREFIX).await?;
+    Ok(containers
+        .into_iter()
+        .filter_map(|container| {
+            let id = container.names?.iter().find_map(|name| {
+                name.trim_start_matches('/')
+                    .strip_prefix(CONTAINER_PREFIX)?
+                    .parse()
+                    .ok()
+            })?;
+            Some((id, container.state.as_deref() == Some("running")))
+        })
+        .collect())
+}
+
+/// Compares the servers the orchestrator expects with the containers on this
+/// host and fixes what can be fixed without interrupting a running server.
+pub async fn reconcile(state: &AppState) -> Result<DriftReport, AppError> {
+    let mut last = state.drift.lock().await;
+
+    let expected = state.orchestrator.get_servers().await?;
+    let containers = list_containers(state).await?;
+
+    let mut report = DriftReport {
+        checked_at: Utc::now(),
+        ..Default::default()
+    };
+    for server in &expected {
+        let container = containers.get(&server.id).copied();
+        if let Err(e) = reconcile_server(state, server, container, &mut report).await {
+            report.errors.push(format!("server {}: {}", server.id, e));
+        }
+    }
+    report.orphaned = containers
+        .keys()
+        .copied()
+        .filter(|id| !expected.iter().any(|server| server.id == *id))
+        .collect();
+    report.orphaned.sort_unstable();
+
+    if !report.orphaned.is_empty() {
+        tracing::warn!("Found containers of unknown servers: {:?}", report.orphaned);
+    }
+    if !report.outdated.is_empty() {
+        tracing::warn!(
+            "Running servers differ from the orchestrator: {:?}",
+            report.outdated
+        );
+    }
+    tracing::info!(
+        "Reconciled {} servers: {} recreated, {} updated, {} restored, {} errors",
+        expected.len(),
+        report.recreated.len(),
+        report.updated.len(),
+        report.restored.len(),
+        report.errors.len()
+    );
+
+    *last = Some(report.clone());
+    Ok(report)
+}
+
+async fn reconcile_server(
+    state: &AppState,
+    server: &Server,
+    container: Option<bool>,
+    report: &mut DriftReport,
+) -> Result<(), AppError> {
+    let running = match container {
+        None => {
+            create_container(state.runtime.as_ref(), &state.config, server).await?;
+            report.recreated.push(server.id);
+            false
+        }
+        Some(running) => {
+            let changed = match load_server_spec(server.id).await? {
+                Some(spec) => spec.generation != server.generation,
+                None => true,
+            };
+            if changed && running {
+                report.outdated.push(server.id);
+            } else if changed {
+                state
+                    .runtime
+                    .remove(&container_name(server.id), false)
+                    .await?;
+                create_container(state.runtime.as_ref(), &state.config, server).await?;
+                report.updated.push(server.id);
+            }
+            running
+        }
+    };
+
+    // servers the agent has not seen yet were running before it or the host restarted
+    if !running && state.statuses.get(server.id).is_none() && should_run(server.id).await {
+        power::start(state, server.id).await?;
+        report.restored.push(server.id);
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "",
+    responses((status = OK, body = Option<DriftReport>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::RECONCILE_TAG
+)]
+pub async fn last_report(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let report = state.drift.lock().await.clone();
+    Ok((StatusCode::OK, Json(report)))
+}
+
+#[utoipa::path(
+    post,
+    path = "",
+    responses((status = OK, body = DriftReport), (status = BAD_GATEWAY, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::RECONCILE_TAG
+)]
+pub async fn run(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let report = reconcile(&state).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
@@ -1,11 +1,17 @@
 use utoipa::OpenApi;
 
 pub const SERVER_TAG: &str = "server";
+pub const BACKUP_TAG: &str = "backup";
+pub const RECONCILE_TAG: &str = "reconcile";
+pub const TRANSFER_TAG: &str = "transfer";
 
 #[derive(OpenApi)]
 #[openapi(
     tags(
-        (name = SERVER_TAG, description = "Server API endpoints")
+        (name = SERVER_TAG, description = "Server API endpoints"),
+        (name = BACKUP_TAG, description = "Backup API endpoints"),
+        (name = RECONCILE_TAG, description = "Reconciliation API endpoints"),
+        (name = TRANSFER_TAG, description = "Transfer API endpoints")
     )
 )]
 pub struct ApiDoc;
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use bollard::{
+    container::{
+        AttachContainerOptions, AttachContainerResults, Config, CreateContainerOptions,
+        KillContainerOptions, ListContainersOptions, LogOutput, LogsOptions,
+        RemoveContainerOptions, StatsOptions, StopContainerOptions,
+    },
+    errors::Error,
+    exec::{CreateExecOptions, StartExecResults},
+    image::CreateImageOptions,
+    models::{ContainerInspectResponse, ContainerSummary, EventMessage},
+    network::CreateNetworkOptions,
+    system::{EventsOptions, Version},
+    Docker,
+};
+use common::agent_types::ResourceUsage;
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
+
+use super::ContainerRuntime;
+
+pub struct DockerRuntime(Docker);
+
+impl DockerRuntime {
+    pub fn connect() -> Result<Self, Error> {
+        Ok(Self(Docker::connect_with_local_defaults()?))
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for DockerRuntime {
+    async fn version(&self) -> Result<Version, Error> {
+        self.0.version().await
+    }
+
+    async fn pull_image(&self, image: &str) -> Result<(), Error> {
+        self.0
+            .create_image(
+                Some(CreateImageOptions {
+                    from_image: image,
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(())
+    }
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<(), Error> {
+        self.0
+            .create_container(
+                Some(CreateContainerOptions {
+                    name,
+                    platform: None,
+                }),
+                config,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn start(&self, name: &str) -> Result<(), Error> {
+        self.0.start_container::<String>(name, None).await
+    }
+
+    async fn stop(&self, name: &str, timeout: i64) -> Result<(), Error> {
+        self.0
+            .stop_container(name, Some(StopContainerOptions { t: timeout }))
+            .await
+    }
+
+    async fn kill(&self, name: &str, signal: Option<String>) -> Result<(), Error> {
+        self.0
+            .kill_container(name, signal.map(|signal| KillContainerOptions { signal }))
+            .await
+    }
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), Error> {
+        self.0
+            .remove_container(
+                name,
+                Some(RemoveContainerOptions {
+                    force,
+                    ..Default::default()
+                }),
+            )
+            .await
+    }
+
+    async fn inspect(&self, name: &str) -> Result<ContainerInspectResponse, Error> {
+        self.0.inspect_container(name, None).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ContainerSummary>, Error> {
+        let containers = self
+            .0
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters: HashMap::from([("name".to_string(), vec![prefix.to_string()])]),
+                ..Default::default()
+            }))
+            .await?;
+        // the name filter also matches in the middle of names
+        Ok(containers
+            .into_iter()
+            .filter(|container| {
+                container
+                    .names
+                    .iter()
+                    .flatten()
+                    .any(|name| name.trim_start_matches('/').starts_with(prefix))
+            })
+            .collect())
+    }
+
+    async fn attach(&self, name: &str) -> Result<AttachContainerResults, Error> {
+        self.0
+            .attach_container::<String>(
+                name,
+                Some(AttachContainerOptions {
+                    stdin: Some(true),
+                    stdout: Some(true),
+                    stderr: Some(true),
+                    stream: Some(true),
+                    ..Default::default()
+                }),
+            )
+            .await
+    }
+
+    async fn wait(&self, name: &str) -> Result<i64, Error> {
+        match self.0.wait_container::<String>(name, None).next().await {
+            Some(Ok(exit)) => Ok(exit.status_code),
+            // bollard reports non zero exit codes as errors
+            Some(Err(Error::DockerContainerWaitError { code, .. })) => Ok(code),
+            Some(Err(e)) => Err(e),
+            None => Err(Error::DockerStreamError {
+                error: "wait stream ended".to_string(),
+            }),
+        }
+    }
+
+    fn logs(
+        &self,
+        name: &str,
+        options: LogsOptions<String>,
+    ) -> BoxStream<'static, Result<LogOutput, Error>> {
+        self.0.logs(name, Some(options)).boxed()
+    }
+
+    async fn stats(&self, name: &str) -> Result<ResourceUsage, Error> {
+        let stats = self
+            .0
+            .stats(
+                name,
+                Some(StatsOptions {
+                    stream: false,
+                    one_shot: false,
+                }),
+            )
+            .next()
+            .await
+            .ok_or_else(|| Error::DockerStreamError {
+                error: "stats stream ended".to_string(),
+            })??;
+        let cpu_delta = stats.cpu_stats.cpu_usage.total_usage as f64
+            - stats.precpu_stats.cpu_usage.total_usage as f64;
+        let system_delta = stats.cpu_stats.system_cpu_usage.unwrap_or(0) as f64
+            - stats.precpu_stats.system_cpu_usage.unwrap_or(0) as f64;
+        let cpus = stats.cpu_stats.online_cpus.unwrap_or(1) as f64;
+        Ok(ResourceUsage {
+            cpu: if system_delta > 0.0 {
+                cpu_delta / system_delta * cpus * 100.0
+            } else {
+                0.0
+            },
+            memory: stats.memory_stats.usage.unwrap_or(0),
+            memory_limit: stats.memory_stats.limit.unwrap_or(0),
+        })
+    }
+
+    fn events(&self, actions: &[&str]) -> BoxStream<'static, Result<EventMessage, Error>> {
+        self.0
+            .events(Some(EventsOptions::<String> {
+                filters: HashMap::from([
+                    ("type".to_string(), vec!["container".to_string()]),
+                    (
+                        "event".to_string(),
+                        actions.iter().map(|action| action.to_string()).collect(),
+                    ),
+                ]),
+                ..Default::default()
+            }))
+            .boxed()
+    }
+
+    async fn exec(&self, name: &str, cmd: Vec<String>) -> Result<String, Error> {
+        let exec = self
+            .0
+            .create_exec(
+                name,
+                CreateExecOptions {
+                    cmd: Some(cmd),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let mut output = String::new();
+        if let StartExecResults::Attached {
+            output: mut stream, ..
+        } = self.0.start_exec(&exec.id, None).await?
+        {
+            while let Some(chunk) = stream.next().await {
+                output.push_str(&String::from_utf8_lossy(&chunk?.into_bytes()));
+            }
+        }
+        Ok(output)
+    }
+
+    async fn create_network(&self, name: &str) -> Result<(), Error> {
+        match self.0.inspect_network::<String>(name, None).await {
+            Ok(_) => return Ok(()),
+            Err(Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => {}
+            Err(e) => return Err(e),
+        }
+        self.0
+            .create_network(CreateNetworkOptions {
+                name,
+                driver: "bridge",
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<(), Error> {
+        self.0.remove_network(name).await
+    }
+}
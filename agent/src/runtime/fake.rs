@@ -0,0 +1,461 @@
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bollard::{
+    container::{AttachContainerResults, Config, LogOutput, LogsOptions},
+    errors::Error,
+    models::{
+        ContainerInspectResponse, ContainerState, ContainerStateStatusEnum, ContainerSummary,
+        EventActor, EventMessage, EventMessageTypeEnum,
+    },
+    system::Version,
+};
+use common::agent_types::ResourceUsage;
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use tokio::{io::AsyncWrite, sync::broadcast};
+
+use super::ContainerRuntime;
+
+// An in-memory runtime for tests. Containers do not run anything: they only
+// track their state, remember what they were sent and print what a test makes
+// them print. Errors mimic the ones Docker returns.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone)]
+pub struct FakeContainer {
+    pub config: Config<String>,
+    pub running: bool,
+    pub exit_code: i64,
+    /// Everything written to the container's stdin.
+    pub stdin: Arc<Mutex<Vec<u8>>>,
+    pub logs: Vec<String>,
+    /// Signals the container was sent through `kill`.
+    pub signals: Vec<String>,
+    pub execs: Vec<Vec<String>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    containers: HashMap<String, FakeContainer>,
+    networks: HashSet<String>,
+    images: HashSet<String>,
+}
+
+pub struct FakeRuntime {
+    inner: Arc<Mutex<Inner>>,
+    events: broadcast::Sender<EventMessage>,
+}
+
+impl Default for FakeRuntime {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            events: broadcast::channel(64).0,
+        }
+    }
+}
+
+fn error(status_code: u16, message: String) -> Error {
+    Error::DockerResponseServerError {
+        status_code,
+        message,
+    }
+}
+
+fn no_such_container(name: &str) -> Error {
+    error(404, format!("No such container: {}", name))
+}
+
+impl FakeRuntime {
+    fn with<R>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut FakeContainer) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let container = inner
+            .containers
+            .get_mut(name)
+            .ok_or_else(|| no_such_container(name))?;
+        f(container)
+    }
+
+    fn exited(&self, name: &str, exit_code: i64) {
+        let event = EventMessage {
+            typ: Some(EventMessageTypeEnum::CONTAINER),
+            action: Some("die".to_string()),
+            actor: Some(EventActor {
+                id: Some(name.to_string()),
+                attributes: Some(HashMap::from([
+                    ("name".to_string(), name.to_string()),
+                    ("exitCode".to_string(), exit_code.to_string()),
+                ])),
+            }),
+            ..Default::default()
+        };
+        // nobody may be listening
+        let _ = self.events.send(event);
+    }
+
+    pub fn container(&self, name: &str) -> Option<FakeContainer> {
+        self.inner.lock().unwrap().containers.get(name).cloned()
+    }
+
+    pub fn has_network(&self, name: &str) -> bool {
+        self.inner.lock().unwrap().networks.contains(name)
+    }
+
+    pub fn has_image(&self, image: &str) -> bool {
+        self.inner.lock().unwrap().images.contains(image)
+    }
+
+    /// What was written to the container's stdin.
+    pub fn stdin(&self, name: &str) -> String {
+        self.container(name)
+            .map(|container| String::from_utf8_lossy(&container.stdin.lock().unwrap()).into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Makes the container print a line of output.
+    pub fn print(&self, name: &str, line: &str) {
+        self.with(name, |container| {
+            container.logs.push(line.to_string());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Makes the container's process exit on its own, like a crash or an
+    /// installer finishing.
+    pub fn exit(&self, name: &str, exit_code: i64) {
+        self.with(name, |container| {
+            container.running = false;
+            container.exit_code = exit_code;
+            Ok(())
+        })
+        .unwrap();
+        self.exited(name, exit_code);
+    }
+}
+
+struct Stdin(Arc<Mutex<Vec<u8>>>);
+
+impl AsyncWrite for Stdin {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    async fn version(&self) -> Result<Version, Error> {
+        Ok(Version {
+            version: Some("fake".to_string()),
+            ..Default::default()
+        })
+    }
+
+    async fn pull_image(&self, image: &str) -> Result<(), Error> {
+        self.inner.lock().unwrap().images.insert(image.to_string());
+        Ok(())
+    }
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.containers.contains_key(name) {
+            return Err(error(
+                409,
+                format!("The container name \"/{}\" is already in use", name),
+            ));
+        }
+        let network = config
+            .host_config
+            .as_ref()
+            .and_then(|host_config| host_config.network_mode.as_deref());
+        if let Some(network) = network {
+            if !["bridge", "host", "none"].contains(&network) && !inner.networks.contains(network) {
+                return Err(error(404, format!("network {} not found", network)));
+            }
+        }
+        inner.containers.insert(
+            name.to_string(),
+            FakeContainer {
+                config,
+                running: false,
+                exit_code: 0,
+                stdin: Default::default(),
+                logs: vec![],
+                signals: vec![],
+                execs: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    async fn start(&self, name: &str) -> Result<(), Error> {
+        self.with(name, |container| {
+            if container.running {
+                return Err(error(304, "container already started".to_string()));
+            }
+            container.running = true;
+            Ok(())
+        })
+    }
+
+    async fn stop(&self, name: &str, _: i64) -> Result<(), Error> {
+        self.with(name, |container| {
+            if !container.running {
+                return Err(error(304, "container already stopped".to_string()));
+            }
+            container.running = false;
+            container.exit_code = 0;
+            Ok(())
+        })?;
+        self.exited(name, 0);
+        Ok(())
+    }
+
+    async fn kill(&self, name: &str, signal: Option<String>) -> Result<(), Error> {
+        let signal = signal.unwrap_or_else(|| "SIGKILL".to_string());
+        let exit_code = if signal == "SIGKILL" { 137 } else { 0 };
+        self.with(name, |container| {
+            if !container.running {
+                return Err(error(409, format!("Container {} is not running", name)));
+            }
+            container.signals.push(signal);
+            container.running = false;
+            container.exit_code = exit_code;
+            Ok(())
+        })?;
+        self.exited(name, exit_code);
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.containers.get(name) {
+            None => Err(no_such_container(name)),
+            Some(container) if container.running && !force => Err(error(
+                409,
+                "You cannot remove a running container".to_string(),
+            )),
+            Some(_) => {
+                inner.containers.remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    async fn inspect(&self, name: &str) -> Result<ContainerInspectResponse, Error> {
+        self.with(name, |container| {
+            Ok(ContainerInspectResponse {
+                name: Some(format!("/{}", name)),
+                image: container.config.image.clone(),
+                state: Some(ContainerState {
+                    status: Some(if container.running {
+                        ContainerStateStatusEnum::RUNNING
+                    } else {
+                        ContainerStateStatusEnum::EXITED
+                    }),
+                    running: Some(container.running),
+                    exit_code: Some(container.exit_code),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        })
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ContainerSummary>, Error> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .containers
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, container)| ContainerSummary {
+                names: Some(vec![format!("/{}", name)]),
+                image: container.config.image.clone(),
+                state: Some(
+                    if container.running {
+                        "running"
+                    } else {
+                        "exited"
+                    }
+                    .to_string(),
+                ),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    async fn attach(&self, name: &str) -> Result<AttachContainerResults, Error> {
+        let stdin = self.with(name, |container| {
+            if !container.running {
+                return Err(error(
+                    409,
+                    "You cannot attach to a stopped container".to_string(),
+                ));
+            }
+            Ok(container.stdin.clone())
+        })?;
+        Ok(AttachContainerResults {
+            output: Box::pin(stream::empty()),
+            input: Box::pin(Stdin(stdin)),
+        })
+    }
+
+    async fn wait(&self, name: &str) -> Result<i64, Error> {
+        loop {
+            let exit_code = self.with(name, |container| {
+                Ok((!container.running).then_some(container.exit_code))
+            })?;
+            if let Some(exit_code) = exit_code {
+                return Ok(exit_code);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn logs(
+        &self,
+        name: &str,
+        options: LogsOptions<String>,
+    ) -> BoxStream<'static, Result<LogOutput, Error>> {
+        let start = match self.container(name) {
+            Some(container) => match options.tail.parse::<usize>() {
+                Ok(tail) => container.logs.len().saturating_sub(tail),
+                Err(_) => 0,
+            },
+            None => return stream::iter([Err(no_such_container(name))]).boxed(),
+        };
+        let inner = self.inner.clone();
+        let name = name.to_string();
+        // following ends once the container stopped and everything it printed was read
+        stream::unfold(start, move |next| {
+            let inner = inner.clone();
+            let name = name.clone();
+            async move {
+                loop {
+                    let (line, running) = {
+                        let inner = inner.lock().unwrap();
+                        let container = inner.containers.get(&name)?;
+                        (container.logs.get(next).cloned(), container.running)
+                    };
+                    if let Some(line) = line {
+                        let message = format!("{}\n", line).into();
+                        return Some((Ok(LogOutput::StdOut { message }), next + 1));
+                    }
+                    if !options.follow || !running {
+                        return None;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        })
+        .boxed()
+    }
+
+    async fn stats(&self, name: &str) -> Result<ResourceUsage, Error> {
+        self.with(name, |container| {
+            if !container.running {
+                return Err(error(409, format!("Container {} is not running", name)));
+            }
+            let memory_limit = container
+                .config
+                .host_config
+                .as_ref()
+                .and_then(|host_config| host_config.memory)
+                .unwrap_or(0);
+            Ok(ResourceUsage {
+                memory_limit: memory_limit as u64,
+                ..Default::default()
+            })
+        })
+    }
+
+    fn events(&self, actions: &[&str]) -> BoxStream<'static, Result<EventMessage, Error>> {
+        let actions: Vec<String> = actions.iter().map(|action| action.to_string()).collect();
+        stream::unfold(self.events.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter(move |event| {
+            let wanted = event
+                .action
+                .as_ref()
+                .is_some_and(|action| actions.contains(action));
+            async move { wanted }
+        })
+        .map(Ok)
+        .boxed()
+    }
+
+    async fn exec(&self, name: &str, cmd: Vec<String>) -> Result<String, Error> {
+        self.with(name, |container| {
+            if !container.running {
+                return Err(error(409, format!("Container {} is not running", name)));
+            }
+            container.execs.push(cmd);
+            Ok(String::new())
+        })
+    }
+
+    async fn create_network(&self, name: &str) -> Result<(), Error> {
+        self.inner.lock().unwrap().networks.insert(name.to_string());
+        Ok(())
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.networks.contains(name) {
+            return Err(error(404, format!("network {} not found", name)));
+        }
+        let in_use = inner.containers.values().any(|container| {
+            container
+                .config
+                .host_config
+                .as_ref()
+                .and_then(|host_config| host_config.network_mode.as_deref())
+                == Some(name)
+        });
+        if in_use {
+            return Err(error(
+                409,
+                format!(
+                    "error while removing network: network {} has active endpoints",
+                    name
+                ),
+            ));
+        }
+        inner.networks.remove(name);
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use async_trait::async_trait;
+use bollard::{
+    container::{AttachContainerResults, Config, LogOutput, LogsOptions},
+    errors::Error,
+    models::{ContainerInspectResponse, ContainerSummary, EventMessage},
+    system::Version,
+};
+use common::agent_types::ResourceUsage;
+use futures_util::stream::BoxStream;
+
+pub mod docker;
+#[cfg(test)]
+pub mod fake;
+
+// Everything the agent does with containers goes through a runtime, so the
+// routes can be tested without a Docker daemon. Errors keep Docker's shape:
+// callers tell a missing container (404), one that is already in the wanted
+// state (304) and one in the wrong state (409) apart by status code, whatever
+// the runtime.
+
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    async fn version(&self) -> Result<Version, Error>;
+
+    async fn pull_image(&self, image: &str) -> Result<(), Error>;
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<(), Error>;
+
+    async fn start(&self, name: &str) -> Result<(), Error>;
+
+    /// Asks the container to stop and kills it after `timeout` seconds.
+    async fn stop(&self, name: &str, timeout: i64) -> Result<(), Error>;
+
+    /// Sends a signal to the container, `SIGKILL` if left out.
+    async fn kill(&self, name: &str, signal: Option<String>) -> Result<(), Error>;
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), Error>;
+
+    async fn inspect(&self, name: &str) -> Result<ContainerInspectResponse, Error>;
+
+    /// Containers whose name starts with the prefix, stopped ones included.
+    async fn list(&self, prefix: &str) -> Result<Vec<ContainerSummary>, Error>;
+
+    /// Attaches to the container's stdin and output.
+    async fn attach(&self, name: &str) -> Result<AttachContainerResults, Error>;
+
+    /// Waits for the container to exit and returns its exit code.
+    async fn wait(&self, name: &str) -> Result<i64, Error>;
+
+    fn logs(
+        &self,
+        name: &str,
+        options: LogsOptions<String>,
+    ) -> BoxStream<'static, Result<LogOutput, Error>>;
+
+    async fn stats(&self, name: &str) -> Result<ResourceUsage, Error>;
+
+    /// Events of containers, filtered by their action such as `die`.
+    fn events(&self, actions: &[&str]) -> BoxStream<'static, Result<EventMessage, Error>>;
+
+    /// Runs a command in the running container and returns its output.
+    // the agent does not run commands in servers yet
+    #[allow(dead_code)]
+    async fn exec(&self, name: &str, cmd: Vec<String>) -> Result<String, Error>;
+
+    /// Creates a bridge network, unless it exists already.
+    async fn create_network(&self, name: &str) -> Result<(), Error>;
+
+    async fn remove_network(&self, name: &str) -> Result<(), Error>;
+}
@@ -6,13 +6,20 @@ use axum::{
 };
 
 use common::{
-    agent_types::{ServerSignal, ServerStatus},
+    agent_types::{
+        AppliedGeneration, ConsoleCommand, CrashReport, InstallOptions, InstallReport,
+        ServerSignal, ServerStatus, ServerStatusReport,
+    },
     orch_types::Server,
 };
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
-    utils::{container_name, container_options, get_folder, AppError},
+    backup, install, network, power, sidecar,
+    utils::{
+        container_name, create_container, get_folder, list_server_specs, load_server_spec,
+        remove_server_spec, set_should_run, AppError,
+    },
     AppState,
 };
 use tokio::fs;
@@ -20,8 +27,12 @@ use tokio::fs;
 pub fn server_routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(create, status, update, delete))
+        .routes(routes!(report))
         .routes(routes!(signal))
-        .routes(routes!(install))
+        .routes(routes!(command))
+        .routes(routes!(crash))
+        .routes(routes!(generations))
+        .routes(routes!(install_report, install))
 }
 
 #[utoipa::path(
@@ -35,18 +46,25 @@ pub async fn status(
     Path(id): Path<i32>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    let res = state
-        .docker
-        .inspect_container(&container_name(id), None)
-        .await?;
-    let status = if res.state.unwrap().running.unwrap() {
-        ServerStatus::Running
-    } else {
-        ServerStatus::Stopped
-    };
+    let status = power::status(&state, id).await?;
     Ok((StatusCode::OK, Json(status)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}/report",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = ServerStatusReport), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::SERVER_TAG
+)]
+pub async fn report(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let report = power::report(&state, id).await?;
+    Ok((StatusCode::OK, Json(report)))
+}
+
 #[utoipa::path(
     post,
     path = "/{id}/signal",
@@ -59,35 +77,105 @@ pub async fn signal(
     State(state): State<AppState>,
     Json(body): Json<ServerSignal>,
 ) -> Result<impl IntoResponse, AppError> {
+    if matches!(body, ServerSignal::Start | ServerSignal::Restart) {
+        if state.transfers.is_sending(id) {
+            return Err(AppError::Conflict(
+                "server is being transferred".to_string(),
+            ));
+        }
+        if state.statuses.get(id) == Some(ServerStatus::Installing) {
+            return Err(AppError::Conflict("server is being installed".to_string()));
+        }
+    }
     match body {
         ServerSignal::Start => {
-            state
-                .docker
-                .start_container::<String>(&container_name(id), None)
-                .await?;
+            state.crashes.reset(id);
+            power::start(&state, id).await?;
+            // a server that failed to start is not started again by the reconciler
+            set_should_run(id, true).await?;
         }
         ServerSignal::Stop => {
-            state
-                .docker
-                .stop_container(&container_name(id), None)
-                .await?;
+            set_should_run(id, false).await?;
+            // stopping may take a while, progress is reported through the status route
+            if state.statuses.get(id) != Some(ServerStatus::Stopping) {
+                tokio::spawn(async move {
+                    if let Err(e) = power::stop(&state, id).await {
+                        tracing::error!("Failed to stop server {}: {}", id, e);
+                    }
+                });
+            }
         }
         ServerSignal::Restart => {
-            state
-                .docker
-                .restart_container(&container_name(id), None)
-                .await?;
+            tokio::spawn(async move {
+                let restarted = async {
+                    power::stop(&state, id).await?;
+                    power::start(&state, id).await?;
+                    set_should_run(id, true).await
+                };
+                if let Err(e) = restarted.await {
+                    tracing::error!("Failed to restart server {}: {}", id, e);
+                }
+            });
         }
         ServerSignal::Kill => {
-            state
-                .docker
-                .kill_container::<String>(&container_name(id), None)
-                .await?;
+            set_should_run(id, false).await?;
+            power::force_stop(&state, id).await?;
         }
     }
     Ok(StatusCode::OK)
 }
 
+#[utoipa::path(
+    post,
+    path = "/{id}/command",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::SERVER_TAG
+)]
+pub async fn command(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(body): Json<ConsoleCommand>,
+) -> Result<impl IntoResponse, AppError> {
+    if !power::is_running(state.runtime.as_ref(), id).await? {
+        return Err(AppError::Conflict("server is not running".to_string()));
+    }
+    power::send_command(state.runtime.as_ref(), id, &body.command).await?;
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/crash",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Option<CrashReport>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::SERVER_TAG
+)]
+pub async fn crash(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((StatusCode::OK, Json(state.crashes.report(id))))
+}
+
+#[utoipa::path(
+    get,
+    path = "/generations",
+    responses((status = OK, body = [AppliedGeneration]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::SERVER_TAG
+)]
+pub async fn generations() -> Result<impl IntoResponse, AppError> {
+    let generations: Vec<AppliedGeneration> = list_server_specs()
+        .await?
+        .into_iter()
+        .map(|server| AppliedGeneration {
+            id: server.id,
+            generation: server.generation,
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(generations)))
+}
+
 #[utoipa::path(
     post,
     path = "",
@@ -99,15 +187,7 @@ pub async fn create(
     Json(body): Json<Server>,
 ) -> Result<impl IntoResponse, AppError> {
     // TODO pull container image
-
-    let folder_path = get_folder(body.id);
-    fs::create_dir_all(&folder_path).await.unwrap();
-
-    let (options, config) = container_options(&body);
-    state
-        .docker
-        .create_container::<String, String>(options, config)
-        .await?;
+    create_container(state.runtime.as_ref(), &state.config, &body).await?;
 
     Ok(StatusCode::OK)
 }
@@ -123,42 +203,58 @@ pub async fn delete(
     Path(id): Path<i32>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
-    if state
-        .docker
-        .inspect_container(&container_name(id), None)
-        .await?
-        .state
-        .unwrap()
-        .running
-        .unwrap()
-    {
-        state
-            .docker
-            .stop_container(&container_name(id), None)
-            .await?;
+    // deleting is retried by the orchestrator, so the container may already be gone
+    if power::status(&state, id).await? != ServerStatus::Unknown {
+        power::stop(&state, id).await?;
+        state.runtime.remove(&container_name(id), false).await?;
     }
+    sidecar::remove(state.runtime.as_ref(), id).await?;
+    network::remove(state.runtime.as_ref(), id).await?;
+    remove_server_spec(id).await?;
+    set_should_run(id, false).await?;
+    state.statuses.clear(id);
+    state.crashes.clear(id);
+    state.transfers.clear(id);
+    state.installs.clear(id);
 
-    state
-        .docker
-        .remove_container(&container_name(id), None)
-        .await?;
-
-    let folder_path = fs::canonicalize(get_folder(id)).await.unwrap();
-    fs::remove_dir_all(&folder_path).await.unwrap();
+    match fs::remove_dir_all(get_folder(id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    backup::delete_server(&state, id).await?;
 
     Ok(StatusCode::OK)
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}/install",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Option<InstallReport>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::SERVER_TAG
+)]
+pub async fn install_report(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((StatusCode::OK, Json(state.installs.report(id))))
+}
+
 #[utoipa::path(
     post,
     path = "/{id}/install",
     params(("id" = i32, Path, description = "server id")),
-    responses((status = OK, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = ACCEPTED, body = String), (status = BAD_REQUEST, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = crate::routes::SERVER_TAG
 )]
-pub async fn install(Path(_id): Path<i32>) -> Result<impl IntoResponse, AppError> {
-    // TODO
-    Ok(((StatusCode::OK), "OK".to_string()))
+pub async fn install(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(options): Json<InstallOptions>,
+) -> Result<impl IntoResponse, AppError> {
+    // installing may take a while, progress is reported through the status route
+    install::start(&state, id, options).await?;
+    Ok(StatusCode::ACCEPTED)
 }
 
 #[utoipa::path(
@@ -171,31 +267,537 @@ pub async fn update(
     State(state): State<AppState>,
     Json(body): Json<Server>,
 ) -> Result<impl IntoResponse, AppError> {
-    if state
-        .docker
-        .inspect_container(&container_name(body.id), None)
-        .await?
-        .state
-        .unwrap()
-        .running
-        .unwrap()
+    // the orchestrator also uses this to create servers whose creation failed
+    let status = power::status(&state, body.id).await?;
+    let applied = load_server_spec(body.id).await?;
+    if status != ServerStatus::Unknown
+        && applied.is_some_and(|applied| applied.generation == body.generation)
     {
+        // a retried sync, the server already runs this spec
+        return Ok(StatusCode::OK);
+    }
+    let was_running = matches!(status, ServerStatus::Starting | ServerStatus::Running);
+    if status != ServerStatus::Unknown {
+        power::stop(&state, body.id).await?;
         state
-            .docker
-            .stop_container(&container_name(body.id), None)
+            .runtime
+            .remove(&container_name(body.id), false)
             .await?;
     }
+    create_container(state.runtime.as_ref(), &state.config, &body).await?;
+    if body.suspended {
+        // the container was stopped above, keep it from being restored
+        set_should_run(body.id, false).await?;
+    } else if was_running {
+        power::start(&state, body.id).await?;
+    }
 
-    state
-        .docker
-        .remove_container(&container_name(body.id), None)
-        .await?;
+    Ok(StatusCode::OK)
+}
 
-    let (options, config) = container_options(&body);
-    state
-        .docker
-        .create_container::<String, String>(options, config)
-        .await?;
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use common::{
+        agent_types::{
+            AppliedGeneration, ConsoleCommand, CrashReport, InstallOptions, InstallReport,
+            ServerSignal, ServerStatus, ServerStatusReport,
+        },
+        orch_types::{Sidecar, SidecarVolume, StopMethod},
+    };
 
-    Ok(StatusCode::OK)
+    use crate::{
+        crash,
+        test_utils::{eventually, server, TestAgent},
+        utils::{container_name, get_folder, should_run},
+    };
+
+    async fn create(agent: &TestAgent, server: &common::orch_types::Server) {
+        assert_eq!(
+            agent.send(Method::POST, "/server", server).await,
+            StatusCode::OK
+        );
+    }
+
+    async fn signal(agent: &TestAgent, id: i32, signal: ServerSignal) -> StatusCode {
+        agent
+            .send(Method::POST, &format!("/server/{}/signal", id), &signal)
+            .await
+    }
+
+    fn sidecar(name: &str) -> Sidecar {
+        Sidecar {
+            name: name.to_string(),
+            image: "mariadb:11".to_string(),
+            command: None,
+            env_vars: vec![],
+            volumes: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn create_runs_server_hardened_on_its_own_network() {
+        let agent = TestAgent::new();
+        create(&agent, &server(101)).await;
+
+        let container = agent.runtime.container(&container_name(101)).unwrap();
+        let host_config = container.config.host_config.unwrap();
+        assert_eq!(
+            host_config.network_mode.as_deref(),
+            Some("nerdpanel-network-101")
+        );
+        assert!(agent.runtime.has_network("nerdpanel-network-101"));
+        assert_eq!(host_config.cap_drop, Some(vec!["ALL".to_string()]));
+        assert_eq!(host_config.pids_limit, Some(512));
+        assert!(container.config.user.is_some());
+        let status: ServerStatus = agent.get("/server/101").await;
+        assert_eq!(status, ServerStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn unknown_server_has_unknown_status() {
+        let agent = TestAgent::new();
+        let status: ServerStatus = agent.get("/server/102").await;
+        assert_eq!(status, ServerStatus::Unknown);
+    }
+
+    #[tokio::test]
+    async fn start_and_stop() {
+        let agent = TestAgent::new();
+        create(&agent, &server(103)).await;
+
+        assert_eq!(
+            signal(&agent, 103, ServerSignal::Start).await,
+            StatusCode::OK
+        );
+        assert!(
+            agent
+                .runtime
+                .container(&container_name(103))
+                .unwrap()
+                .running
+        );
+        let status: ServerStatus = agent.get("/server/103").await;
+        assert_eq!(status, ServerStatus::Running);
+
+        assert_eq!(
+            signal(&agent, 103, ServerSignal::Stop).await,
+            StatusCode::OK
+        );
+        eventually(|| agent.state.statuses.get(103) == Some(ServerStatus::Stopped)).await;
+        assert!(
+            !agent
+                .runtime
+                .container(&container_name(103))
+                .unwrap()
+                .running
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_start_is_not_retried() {
+        let agent = TestAgent::new();
+        assert_ne!(
+            signal(&agent, 123, ServerSignal::Start).await,
+            StatusCode::OK
+        );
+        assert!(!should_run(123).await);
+    }
+
+    #[tokio::test]
+    async fn start_waits_for_done_output() {
+        let agent = TestAgent::new();
+        let mut server = server(104);
+        server.done_regex = Some(r"^Done \(".to_string());
+        create(&agent, &server).await;
+
+        signal(&agent, 104, ServerSignal::Start).await;
+        assert_eq!(agent.state.statuses.get(104), Some(ServerStatus::Starting));
+        agent.runtime.print(&container_name(104), "Loading world");
+        agent.runtime.print(&container_name(104), "Done (1.5s)!");
+        eventually(|| agent.state.statuses.get(104) == Some(ServerStatus::Running)).await;
+    }
+
+    #[tokio::test]
+    async fn suspended_server_does_not_start() {
+        let agent = TestAgent::new();
+        let mut server = server(105);
+        server.suspended = true;
+        create(&agent, &server).await;
+
+        assert_eq!(
+            signal(&agent, 105, ServerSignal::Start).await,
+            StatusCode::CONFLICT
+        );
+        assert!(
+            !agent
+                .runtime
+                .container(&container_name(105))
+                .unwrap()
+                .running
+        );
+    }
+
+    #[tokio::test]
+    async fn command_is_written_to_stdin() {
+        let agent = TestAgent::new();
+        create(&agent, &server(106)).await;
+        let command = ConsoleCommand {
+            command: "say hi".to_string(),
+        };
+        let uri = "/server/106/command";
+
+        assert_eq!(
+            agent.send(Method::POST, uri, &command).await,
+            StatusCode::CONFLICT
+        );
+        signal(&agent, 106, ServerSignal::Start).await;
+        assert_eq!(
+            agent.send(Method::POST, uri, &command).await,
+            StatusCode::OK
+        );
+        assert_eq!(agent.runtime.stdin(&container_name(106)), "say hi\n");
+    }
+
+    #[tokio::test]
+    async fn stop_command_lets_server_exit() {
+        let agent = TestAgent::new();
+        let mut server = server(107);
+        server.stop_method = StopMethod::Command {
+            command: "stop".to_string(),
+        };
+        server.stop_timeout = 5;
+        create(&agent, &server).await;
+        signal(&agent, 107, ServerSignal::Start).await;
+
+        signal(&agent, 107, ServerSignal::Stop).await;
+        eventually(|| agent.runtime.stdin(&container_name(107)) == "stop\n").await;
+        agent.runtime.exit(&container_name(107), 0);
+        eventually(|| agent.state.statuses.get(107) == Some(ServerStatus::Stopped)).await;
+        assert!(agent
+            .runtime
+            .container(&container_name(107))
+            .unwrap()
+            .signals
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn server_that_ignores_stop_is_killed() {
+        let agent = TestAgent::new();
+        let mut server = server(108);
+        server.stop_method = StopMethod::Command {
+            command: "stop".to_string(),
+        };
+        server.stop_timeout = 0;
+        create(&agent, &server).await;
+        signal(&agent, 108, ServerSignal::Start).await;
+
+        signal(&agent, 108, ServerSignal::Stop).await;
+        eventually(|| agent.state.statuses.get(108) == Some(ServerStatus::Stopped)).await;
+        let container = agent.runtime.container(&container_name(108)).unwrap();
+        assert_eq!(container.signals, vec!["SIGKILL".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn kill() {
+        let agent = TestAgent::new();
+        create(&agent, &server(109)).await;
+        signal(&agent, 109, ServerSignal::Start).await;
+
+        assert_eq!(
+            signal(&agent, 109, ServerSignal::Kill).await,
+            StatusCode::OK
+        );
+        assert_eq!(agent.state.statuses.get(109), Some(ServerStatus::Stopped));
+        let container = agent.runtime.container(&container_name(109)).unwrap();
+        assert!(!container.running);
+        assert_eq!(container.signals, vec!["SIGKILL".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn crash_is_reported_and_stops_sidecars() {
+        let agent = TestAgent::new();
+        tokio::spawn(crash::monitor(agent.state.clone()));
+        let mut server = server(110);
+        server.sidecars = vec![sidecar("db")];
+        create(&agent, &server).await;
+        signal(&agent, 110, ServerSignal::Start).await;
+        assert!(
+            agent
+                .runtime
+                .container("nerdpanel-sidecar-110-db")
+                .unwrap()
+                .running
+        );
+
+        agent
+            .runtime
+            .print(&container_name(110), "Exception in server tick loop");
+        // the monitor subscribes to events once it runs
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        agent.runtime.exit(&container_name(110), 1);
+        eventually(|| agent.state.crashes.report(110).is_some()).await;
+
+        let report: Option<CrashReport> = agent.get("/server/110/crash").await;
+        let report = report.unwrap();
+        assert_eq!(report.exit_code, Some(1));
+        assert_eq!(report.console, vec!["Exception in server tick loop"]);
+        assert_eq!(report.restart_in, None);
+        let status: ServerStatus = agent.get("/server/110").await;
+        assert_eq!(status, ServerStatus::Crashed);
+        eventually(|| {
+            !agent
+                .runtime
+                .container("nerdpanel-sidecar-110-db")
+                .unwrap()
+                .running
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn sidecars_follow_the_server() {
+        let agent = TestAgent::new();
+        let mut server = server(111);
+        server.sidecars = vec![sidecar("db")];
+        create(&agent, &server).await;
+        let sidecar = agent.runtime.container("nerdpanel-sidecar-111-db").unwrap();
+        assert_eq!(
+            sidecar.config.host_config.unwrap().network_mode.as_deref(),
+            Some("nerdpanel-network-111")
+        );
+
+        signal(&agent, 111, ServerSignal::Start).await;
+        let report: ServerStatusReport = agent.get("/server/111/report").await;
+        assert_eq!(report.status, ServerStatus::Running);
+        assert!(report.sidecars[0].running);
+        assert!(report.usage.is_some());
+
+        signal(&agent, 111, ServerSignal::Kill).await;
+        let report: ServerStatusReport = agent.get("/server/111/report").await;
+        assert!(!report.sidecars[0].running);
+        assert!(report.usage.is_none());
+    }
+
+    #[tokio::test]
+    async fn sidecar_volumes_are_not_mounted_through_links() {
+        let agent = TestAgent::new();
+        let outside =
+            std::env::temp_dir().join(format!("nerdpanel-outside-{}", std::process::id()));
+        std::fs::create_dir_all(get_folder(121)).unwrap();
+        std::os::unix::fs::symlink(&outside, format!("{}/data", get_folder(121))).unwrap();
+        let mut server = server(121);
+        let mut db = sidecar("db");
+        db.volumes = vec![SidecarVolume {
+            source: "data/mysql".to_string(),
+            target: "/var/lib/mysql".to_string(),
+        }];
+        server.sidecars = vec![db];
+
+        assert_eq!(
+            agent.send(Method::POST, "/server", &server).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert!(!outside.exists());
+        assert!(agent
+            .runtime
+            .container("nerdpanel-sidecar-121-db")
+            .is_none());
+
+        server.id = 122;
+        assert_eq!(
+            agent.send(Method::POST, "/server", &server).await,
+            StatusCode::OK
+        );
+        let sidecar = agent.runtime.container("nerdpanel-sidecar-122-db").unwrap();
+        let mounts = sidecar.config.host_config.unwrap().mounts.unwrap();
+        let volume = std::fs::canonicalize(get_folder(122)).unwrap();
+        assert_eq!(
+            mounts[0].source.as_deref(),
+            Some(volume.join("data/mysql").to_str().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn update_recreates_the_container() {
+        let agent = TestAgent::new();
+        let mut server = server(112);
+        server.sidecars = vec![sidecar("db")];
+        create(&agent, &server).await;
+        signal(&agent, 112, ServerSignal::Start).await;
+
+        server.generation += 1;
+        server.image = "nerdpanel/test:next".to_string();
+        server.sidecars = vec![sidecar("cache")];
+        assert_eq!(
+            agent.send(Method::PUT, "/server", &server).await,
+            StatusCode::OK
+        );
+        let container = agent.runtime.container(&container_name(112)).unwrap();
+        assert_eq!(
+            container.config.image.as_deref(),
+            Some("nerdpanel/test:next")
+        );
+        // a running server is started again with its new spec
+        assert!(container.running);
+        assert!(agent
+            .runtime
+            .container("nerdpanel-sidecar-112-db")
+            .is_none());
+        assert!(agent
+            .runtime
+            .container("nerdpanel-sidecar-112-cache")
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn update_skips_the_applied_generation() {
+        let agent = TestAgent::new();
+        let mut server = server(120);
+        server.generation = 3;
+        create(&agent, &server).await;
+        signal(&agent, 120, ServerSignal::Start).await;
+
+        server.image = "nerdpanel/test:next".to_string();
+        assert_eq!(
+            agent.send(Method::PUT, "/server", &server).await,
+            StatusCode::OK
+        );
+        let container = agent.runtime.container(&container_name(120)).unwrap();
+        assert_eq!(
+            container.config.image.as_deref(),
+            Some("nerdpanel/test:latest")
+        );
+        assert!(container.running);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_everything() {
+        let agent = TestAgent::new();
+        let mut server = server(113);
+        server.sidecars = vec![sidecar("db")];
+        create(&agent, &server).await;
+        signal(&agent, 113, ServerSignal::Start).await;
+        let volume = std::path::PathBuf::from(get_folder(113));
+        agent.state.backups.create_snapshot(113, &volume).unwrap();
+
+        let (status, _) = agent
+            .request(Method::DELETE, "/server/113", None::<&()>)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(agent.runtime.container(&container_name(113)).is_none());
+        assert!(agent
+            .runtime
+            .container("nerdpanel-sidecar-113-db")
+            .is_none());
+        assert!(!agent.runtime.has_network("nerdpanel-network-113"));
+        assert!(!std::path::Path::new(&get_folder(113)).exists());
+        let status: ServerStatus = agent.get("/server/113").await;
+        assert_eq!(status, ServerStatus::Unknown);
+        assert!(agent.state.backups.manifests(113).unwrap().is_empty());
+
+        // the orchestrator retries deletes
+        let (status, _) = agent
+            .request(Method::DELETE, "/server/113", None::<&()>)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn generations_list_applied_specs() {
+        let agent = TestAgent::new();
+        let mut server = server(114);
+        server.generation = 7;
+        create(&agent, &server).await;
+
+        let generations: Vec<AppliedGeneration> = agent.get("/server/generations").await;
+        assert!(generations
+            .iter()
+            .any(|applied| applied.id == 114 && applied.generation == 7));
+    }
+
+    #[tokio::test]
+    async fn install_runs_the_installer() {
+        let agent = TestAgent::new();
+        let mut server = server(115);
+        server.installer_image = "nerdpanel/installer:latest".to_string();
+        create(&agent, &server).await;
+
+        let status = agent
+            .send(
+                Method::POST,
+                "/server/115/install",
+                &InstallOptions::default(),
+            )
+            .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(
+            agent.state.statuses.get(115),
+            Some(ServerStatus::Installing)
+        );
+        eventually(|| {
+            agent
+                .runtime
+                .container("nerdpanel-installer-115")
+                .is_some_and(|installer| installer.running)
+        })
+        .await;
+        assert!(agent.runtime.has_image("nerdpanel/installer:latest"));
+        assert_eq!(
+            signal(&agent, 115, ServerSignal::Start).await,
+            StatusCode::CONFLICT
+        );
+
+        agent.runtime.print("nerdpanel-installer-115", "installed");
+        agent.runtime.exit("nerdpanel-installer-115", 0);
+        eventually(|| agent.state.statuses.get(115) == Some(ServerStatus::Stopped)).await;
+        let report: Option<InstallReport> = agent.get("/server/115/install").await;
+        let report = report.unwrap();
+        assert_eq!(report.exit_code, Some(0));
+        assert_eq!(report.console, vec!["installed"]);
+        assert!(agent.runtime.container("nerdpanel-installer-115").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_install_is_reported() {
+        let agent = TestAgent::new();
+        let mut server = server(116);
+        server.installer_image = "nerdpanel/installer:latest".to_string();
+        create(&agent, &server).await;
+
+        agent
+            .send(
+                Method::POST,
+                "/server/116/install",
+                &InstallOptions::default(),
+            )
+            .await;
+        eventually(|| {
+            agent
+                .runtime
+                .container("nerdpanel-installer-116")
+                .is_some_and(|installer| installer.running)
+        })
+        .await;
+        agent.runtime.exit("nerdpanel-installer-116", 2);
+        eventually(|| agent.state.statuses.get(116) == Some(ServerStatus::InstallFailed)).await;
+        let report: Option<InstallReport> = agent.get("/server/116/install").await;
+        assert_eq!(report.unwrap().exit_code, Some(2));
+    }
+
+    #[tokio::test]
+    async fn install_needs_an_installer() {
+        let agent = TestAgent::new();
+        create(&agent, &server(117)).await;
+
+        let status = agent
+            .send(
+                Method::POST,
+                "/server/117/install",
+                &InstallOptions::default(),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
 }
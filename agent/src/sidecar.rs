@@ -0,0 +1,208 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use bollard::{
+    container::Config,
+    secret::{HostConfig, Mount, MountTypeEnum},
+};
+use common::{
+    agent_types::SidecarStatus,
+    orch_types::{Server, Sidecar, SidecarVolume},
+    startup,
+};
+
+use crate::{
+    hardening::NO_NEW_PRIVILEGES,
+    network::{network_name, networking_config},
+    runtime::ContainerRuntime,
+    utils::{get_folder, AppError},
+};
+
+// Sidecars run next to a server's container on the server's network, where the
+// server reaches them by their name and they reach the server as `server`. They
+// are started before and stopped after the server.
+
+const SIDECAR_PREFIX: &str = "nerdpanel-sidecar-";
+/// Seconds a sidecar has to exit before it is killed.
+const STOP_TIMEOUT: i64 = 10;
+
+pub fn sidecar_name(id: i32, name: &str) -> String {
+    format!("{}{}-{}", SIDECAR_PREFIX, id, name)
+}
+
+/// The folder of the server's volume that a sidecar volume is mounted from.
+/// Anything in the server's container can write to the volume, so a path
+/// through a link is refused before any folder is created.
+fn volume_source(
+    folder: &Path,
+    sidecar: &Sidecar,
+    volume: &SidecarVolume,
+) -> Result<PathBuf, AppError> {
+    let relative = Path::new(&volume.source);
+    let outside = || {
+        AppError::BadRequest(format!(
+            "volume {} of sidecar {} is outside of the server's volume",
+            volume.source, sidecar.name
+        ))
+    };
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside());
+    }
+    let mut existing = folder.to_path_buf();
+    for component in relative.components() {
+        existing.push(component);
+        match fs::symlink_metadata(&existing) {
+            Ok(metadata) if metadata.is_symlink() => return Err(outside()),
+            Ok(_) => {}
+            // nothing below a missing folder exists either
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let source = folder.join(relative);
+    fs::create_dir_all(&source)?;
+    // the canonical path is mounted, so a link swapped in now is not followed
+    let source = fs::canonicalize(&source)?;
+    if !source.starts_with(folder) {
+        return Err(outside());
+    }
+    Ok(source)
+}
+
+fn sidecar_options(server: &Server, sidecar: &Sidecar) -> Result<Config<String>, AppError> {
+    let folder_path = fs::canonicalize(get_folder(server.id))?;
+    let mut mounts = vec![];
+    for volume in &sidecar.volumes {
+        let source = volume_source(&folder_path, sidecar, volume)?;
+        mounts.push(Mount {
+            target: Some(volume.target.clone()),
+            source: Some(source.to_string_lossy().to_string()),
+            typ: Some(MountTypeEnum::BIND),
+            consistency: Some(String::from("default")),
+            ..Default::default()
+        });
+    }
+
+    let cmd = match &sidecar.command {
+        Some(command) => Some(startup::render_command(command, server).map_err(|e| {
+            AppError::BadRequest(format!("command of sidecar {}: {}", sidecar.name, e))
+        })?),
+        None => None,
+    };
+    let mut env = vec![];
+    for env_var in &sidecar.env_vars {
+        let value = startup::render_value(&env_var.value, server).map_err(|e| {
+            AppError::BadRequest(format!(
+                "{} of sidecar {}: {}",
+                env_var.key, sidecar.name, e
+            ))
+        })?;
+        env.push(format!("{}={}", env_var.key, value));
+    }
+
+    let config = Config {
+        image: Some(sidecar.image.clone()),
+        cmd,
+        env: Some(env),
+        // sidecar images commonly start as root and drop to their own user
+        host_config: Some(HostConfig {
+            mounts: Some(mounts),
+            network_mode: Some(network_name(server.id)),
+            security_opt: server
+                .hardening
+                .no_new_privileges
+                .then(|| vec![NO_NEW_PRIVILEGES.to_string()]),
+            pids_limit: server.hardening.pid_limit,
+            ..Default::default()
+        }),
+        networking_config: Some(networking_config(server.id, &sidecar.name)),
+        ..Default::default()
+    };
+    Ok(config)
+}
+
+/// Creates the sidecars of a server on its network, which has to exist.
+pub async fn create(runtime: &dyn ContainerRuntime, server: &Server) -> Result<(), AppError> {
+    for sidecar in &server.sidecars {
+        let config = sidecar_options(server, sidecar)?;
+        runtime
+            .create(&sidecar_name(server.id, &sidecar.name), config)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The names of the sidecar containers of a server that exist on this host,
+/// and whether they are running.
+async fn list(runtime: &dyn ContainerRuntime, id: i32) -> Result<Vec<(String, bool)>, AppError> {
+    let prefix = format!("{}{}-", SIDECAR_PREFIX, id);
+    let containers = runtime.list(&prefix).await?;
+    Ok(containers
+        .into_iter()
+        .filter_map(|container| {
+            let name = container.names?.iter().find_map(|name| {
+                name.trim_start_matches('/')
+                    .strip_prefix(&prefix)
+                    .map(str::to_string)
+            })?;
+            Some((name, container.state.as_deref() == Some("running")))
+        })
+        .collect())
+}
+
+/// Removes the sidecars of a server, whatever sidecars it has now.
+pub async fn remove(runtime: &dyn ContainerRuntime, id: i32) -> Result<(), AppError> {
+    for (name, _) in list(runtime, id).await? {
+        runtime.remove(&sidecar_name(id, &name), true).await?;
+    }
+    Ok(())
+}
+
+pub async fn start(runtime: &dyn ContainerRuntime, server: &Server) -> Result<(), AppError> {
+    for sidecar in &server.sidecars {
+        match runtime.start(&sidecar_name(server.id, &sidecar.name)).await {
+            // already running, e.g. after the server crashed
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 304, ..
+            }) => {}
+            Err(e) => return Err(e.into()),
+            Ok(()) => {}
+        }
+    }
+    Ok(())
+}
+
+pub async fn stop(runtime: &dyn ContainerRuntime, id: i32) -> Result<(), AppError> {
+    for (name, running) in list(runtime, id).await? {
+        if running {
+            runtime.stop(&sidecar_name(id, &name), STOP_TIMEOUT).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Status of each sidecar the server should have.
+pub async fn statuses(
+    runtime: &dyn ContainerRuntime,
+    server: &Server,
+) -> Result<Vec<SidecarStatus>, AppError> {
+    let containers = list(runtime, server.id).await?;
+    Ok(server
+        .sidecars
+        .iter()
+        .map(|sidecar| {
+            let container = containers.iter().find(|(name, _)| *name == sidecar.name);
+            SidecarStatus {
+                name: sidecar.name.clone(),
+                running: container.is_some_and(|(_, running)| *running),
+                missing: container.is_none(),
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,44 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use common::agent_types::ServerStatus;
+
+/// Lifecycle status of every server as tracked by the agent. Docker only knows
+/// whether a container is running, not whether the game finished loading or is
+/// being shut down.
+#[derive(Clone, Default)]
+pub struct ServerStates(Arc<RwLock<HashMap<i32, ServerStatus>>>);
+
+impl ServerStates {
+    pub fn get(&self, id: i32) -> Option<ServerStatus> {
+        self.0.read().unwrap().get(&id).copied()
+    }
+
+    pub fn set(&self, id: i32, status: ServerStatus) {
+        let previous = self.0.write().unwrap().insert(id, status);
+        if previous != Some(status) {
+            tracing::info!("Server {} is now {:?}", id, status);
+        }
+    }
+
+    /// Sets `to` only if the server currently is in one of the `from` states.
+    /// Returns whether the transition happened.
+    pub fn transition(&self, id: i32, from: &[ServerStatus], to: ServerStatus) -> bool {
+        let mut states = self.0.write().unwrap();
+        match states.get(&id) {
+            Some(current) if from.contains(current) => {
+                states.insert(id, to);
+                drop(states);
+                tracing::info!("Server {} is now {:?}", id, to);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear(&self, id: i32) {
+        self.0.write().unwrap().remove(&id);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    os::unix::fs::MetadataExt,
+    sync::{Arc, Once},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use common::orch_types::Server;
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+use crate::{
+    api,
+    backup::repository::Repository,
+    config::Config,
+    orchestrator::OrchestratorClient,
+    runtime::fake::FakeRuntime,
+    utils::{get_backup_repository, get_volume_root},
+    AppState,
+};
+
+// Tests run in one process and share the run folder, so each test uses server
+// ids of its own.
+
+static RUN_FOLDER: Once = Once::new();
+
+/// Token of the test agent's node.
+pub const NODE_TOKEN: &str = "test-token";
+
+/// An agent on a fake runtime.
+pub struct TestAgent {
+    pub state: AppState,
+    pub runtime: Arc<FakeRuntime>,
+    pub app: Router,
+}
+
+impl TestAgent {
+    pub fn new() -> Self {
+        RUN_FOLDER.call_once(|| {
+            let folder =
+                std::env::temp_dir().join(format!("nerdpanel-agent-{}", std::process::id()));
+            std::env::set_var("NERDPANEL_RUN_DIR", folder);
+        });
+        std::fs::create_dir_all(get_volume_root()).unwrap();
+        // volumes are handed to the user the tests run as, which always works
+        let owner = std::fs::metadata(get_volume_root()).unwrap();
+        let runtime = Arc::new(FakeRuntime::default());
+        let config = Config {
+            orchestrator_url: "http://localhost:3000".to_string(),
+            node_token: Some(NODE_TOKEN.to_string()),
+            orchestrator_ca: None,
+            container_uid: owner.uid(),
+            container_gid: owner.gid(),
+        };
+        let state = AppState {
+            orchestrator: OrchestratorClient::new(&config).unwrap(),
+            config,
+            runtime: runtime.clone(),
+            backups: Arc::new(Repository::open(get_backup_repository()).unwrap()),
+            statuses: Default::default(),
+            crashes: Default::default(),
+            drift: Default::default(),
+            transfers: Default::default(),
+            installs: Default::default(),
+        };
+        let app = api(state.clone())
+            .split_for_parts()
+            .0
+            .with_state(state.clone());
+        Self {
+            state,
+            runtime,
+            app,
+        }
+    }
+
+    /// Sends a request with an optional JSON body as the orchestrator and
+    /// returns the response's status and body.
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<&impl Serialize>,
+    ) -> (StatusCode, Vec<u8>) {
+        self.request_as(Some(NODE_TOKEN), method, uri, body).await
+    }
+
+    /// Sends a request authenticated with `bearer`, if any.
+    pub async fn request_as(
+        &self,
+        bearer: Option<&str>,
+        method: Method,
+        uri: &str,
+        body: Option<&impl Serialize>,
+    ) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(bearer) = bearer {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", bearer));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(body).unwrap())),
+            None => request.body(Body::empty()),
+        };
+        let response = self.app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    pub async fn send(&self, method: Method, uri: &str, body: &impl Serialize) -> StatusCode {
+        self.request(method, uri, Some(body)).await.0
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, uri: &str) -> T {
+        let (status, body) = self.request(Method::GET, uri, None::<&()>).await;
+        assert_eq!(
+            status,
+            StatusCode::OK,
+            "GET {}: {}",
+            uri,
+            String::from_utf8_lossy(&body)
+        );
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+/// A server with nothing but the required settings.
+pub fn server(id: i32) -> Server {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "name": format!("server {}", id),
+        "owner_id": 1,
+        "node_id": 1,
+        "cpu_limit": null,
+        "memory_limit": 1024,
+        "disk_limit": null,
+        "primary_port": {"id": 1, "ip": "0.0.0.0", "port": 25565},
+        "additional_ports": [],
+        "pod_id": 1,
+        "image": "nerdpanel/test:latest",
+        "startup_command": "./start",
+        "env_vars": [],
+        "restart_on_crash": false,
+        "stop_method": {"type": "docker"},
+        "stop_timeout": 1,
+        "done_regex": null,
+    }))
+    .unwrap()
+}
+
+/// Waits for background work to make the condition true.
+pub async fn eventually(condition: impl Fn() -> bool) {
+    for _ in 0..300 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("condition not met in time");
+}
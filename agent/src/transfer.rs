@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path as FsPath, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    middleware,
+    response::IntoResponse,
+    Json,
+};
+use common::{
+    agent_client::{AgentClient, AgentClientConfig},
+    agent_types::{
+        IncomingTransfer, ServerStatus, TransferProgress, TransferState, TransferTarget,
+    },
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures_util::TryStreamExt;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+use utoipa_axum::{router::OpenApiRouter, routes};
+use walkdir::WalkDir;
+
+use crate::{
+    auth, power,
+    utils::{get_folder, set_should_run, AppError},
+    AppState,
+};
+
+/// Size of the pipe between the archiver and the upload.
+const PIPE_SIZE: usize = 256 * 1024;
+
+/// Transfers of server volumes between agents. The orchestrator registers a
+/// one time token with the destination and hands it to the source, which
+/// stops the server and streams its volume to the destination.
+#[derive(Clone, Default)]
+pub struct Transfers {
+    outgoing: Arc<Mutex<HashMap<i32, TransferProgress>>>,
+    incoming: Arc<Mutex<HashMap<i32, String>>>,
+}
+
+impl Transfers {
+    pub fn progress(&self, id: i32) -> Option<TransferProgress> {
+        self.outgoing.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Whether the server's volume is being sent away right now.
+    pub fn is_sending(&self, id: i32) -> bool {
+        self.progress(id).as_ref().is_some_and(in_progress)
+    }
+
+    fn update(&self, id: i32, update: impl FnOnce(&mut TransferProgress)) {
+        if let Some(progress) = self.outgoing.lock().unwrap().get_mut(&id) {
+            update(progress);
+        }
+    }
+
+    pub fn clear(&self, id: i32) {
+        self.outgoing.lock().unwrap().remove(&id);
+        self.incoming.lock().unwrap().remove(&id);
+    }
+}
+
+fn in_progress(progress: &TransferProgress) -> bool {
+    matches!(
+        progress.state,
+        TransferState::Stopping | TransferState::Sending
+    )
+}
+
+pub fn transfer_routes(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(progress, send))
+        .routes(routes!(expect))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            auth::require_orchestrator,
+        ))
+        // the source agent authenticates with the transfer's token instead
+        .routes(routes!(receive))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Option<TransferProgress>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::TRANSFER_TAG
+)]
+pub async fn progress(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((StatusCode::OK, Json(state.transfers.progress(id))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = ACCEPTED, body = TransferProgress), (status = NOT_FOUND, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::TRANSFER_TAG
+)]
+pub async fn send(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(target): Json<TransferTarget>,
+) -> Result<impl IntoResponse, AppError> {
+    if power::status(&state, id).await? == ServerStatus::Unknown {
+        return Err(AppError::NotFound);
+    }
+    let progress = TransferProgress {
+        state: TransferState::Stopping,
+        bytes_done: 0,
+        bytes_total: 0,
+        error: None,
+    };
+    {
+        let mut outgoing = state.transfers.outgoing.lock().unwrap();
+        if outgoing.get(&id).is_some_and(in_progress) {
+            return Err(AppError::Conflict(
+                "server is already being transferred".to_string(),
+            ));
+        }
+        outgoing.insert(id, progress.clone());
+    }
+
+    // sending may take a long time, progress is reported through the progress route
+    tokio::spawn(async move {
+        let result = send_volume(&state, id, target).await;
+        state.transfers.update(id, |progress| match result {
+            Ok(()) => progress.state = TransferState::Done,
+            Err(e) => {
+                tracing::error!("Failed to transfer server {}: {}", id, e);
+                progress.state = TransferState::Failed;
+                progress.error = Some(e.to_string());
+            }
+        });
+    });
+    Ok((StatusCode::ACCEPTED, Json(progress)))
+}
+
+async fn send_volume(state: &AppState, id: i32, target: TransferTarget) -> Result<(), AppError> {
+    // the server stays stopped here, the orchestrator restarts it if the transfer fails
+    set_should_run(id, false).await?;
+    power::stop(state, id).await?;
+
+    let folder = PathBuf::from(get_folder(id));
+    let bytes_total = {
+        let folder = folder.clone();
+        tokio::task::spawn_blocking(move || volume_size(&folder))
+            .await
+            .map_err(|_| AppError::InternalError)?
+    };
+    state.transfers.update(id, |progress| {
+        progress.state = TransferState::Sending;
+        progress.bytes_total = bytes_total;
+    });
+    tracing::info!(
+        "Sending {} bytes of server {} to {}",
+        bytes_total,
+        id,
+        target.url
+    );
+
+    let (writer, reader) = tokio::io::duplex(PIPE_SIZE);
+    let transfers = state.transfers.clone();
+    let archiver = tokio::task::spawn_blocking(move || {
+        write_archive(&folder, SyncIoBridge::new(writer), |bytes| {
+            transfers.update(id, |progress| progress.bytes_done += bytes)
+        })
+    });
+
+    let archive = reqwest::Body::wrap_stream(ReaderStream::new(reader));
+    let res = AgentClient::new(AgentClientConfig::default())
+        .map_err(|e| AppError::TransferError(e.to_string()))?
+        .agent(&target.url, None)
+        .receive_transfer(id, &target.token, archive)
+        .await;
+    let archived = archiver.await.map_err(|_| AppError::InternalError)?;
+
+    res.map_err(|e| AppError::TransferError(e.to_string()))?;
+    archived?;
+    Ok(())
+}
+
+fn volume_size(folder: &FsPath) -> u64 {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Writes the folder as a gzipped tar archive, reporting the size of every file
+/// once it was written.
+fn write_archive(
+    folder: &FsPath,
+    writer: impl io::Write,
+    mut on_progress: impl FnMut(u64),
+) -> io::Result<()> {
+    let mut archive = tar::Builder::new(GzEncoder::new(writer, Compression::fast()));
+    archive.follow_symlinks(false);
+    for entry in WalkDir::new(folder).min_depth(1) {
+        let entry = entry?;
+        let name = entry
+            .path()
+            .strip_prefix(folder)
+            .map_err(|_| io::Error::other("file outside of the volume"))?;
+        archive.append_path_with_name(entry.path(), name)?;
+        if entry.file_type().is_file() {
+            on_progress(entry.metadata()?.len());
+        }
+    }
+    archive.into_inner()?.finish()?.flush()
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/incoming",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::TRANSFER_TAG
+)]
+pub async fn expect(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(incoming): Json<IncomingTransfer>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .transfers
+        .incoming
+        .lock()
+        .unwrap()
+        .insert(id, incoming.token);
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/archive",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = String), (status = UNAUTHORIZED, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = crate::routes::TRANSFER_TAG
+)]
+pub async fn receive(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "));
+    let expected = state.transfers.incoming.lock().unwrap().remove(&id);
+    let (Some(token), Some(expected)) = (token, expected) else {
+        return Err(AppError::Unauthorized);
+    };
+    // compared like the node token, in the same time however much of it matches
+    let matches = Sha256::digest(token.as_bytes()).ct_eq(&Sha256::digest(expected.as_bytes()));
+    if !bool::from(matches) {
+        return Err(AppError::Unauthorized);
+    }
+    if power::is_running(state.runtime.as_ref(), id).await? {
+        return Err(AppError::Conflict("server is running".to_string()));
+    }
+
+    let folder = get_folder(id);
+    match tokio::fs::remove_dir_all(&folder).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    tokio::fs::create_dir_all(&folder).await?;
+
+    let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    tokio::task::spawn_blocking(move || {
+        let mut archive = tar::Archive::new(GzDecoder::new(SyncIoBridge::new(reader)));
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_ownerships(true);
+        archive.unpack(&folder)
+    })
+    .await
+    .map_err(|_| AppError::InternalError)??;
+    tracing::info!("Received the volume of server {}", id);
+
+    Ok(StatusCode::OK)
+}
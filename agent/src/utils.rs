@@ -3,44 +3,157 @@ use std::fs;
 use axum::http::StatusCode;
 use axum_thiserror::ErrorStatus;
 use bollard::{
-    container::{Config, CreateContainerOptions},
+    container::Config,
     secret::{HostConfig, Mount, MountTypeEnum, PortBinding},
 };
-use common::orch_types::Server;
+use common::{
+    orch_types::Server,
+    startup::{self, TemplateError},
+};
 use thiserror::Error;
 
+use crate::{
+    config, hardening,
+    network::{self, network_name, networking_config, SERVER_ALIAS},
+    runtime::ContainerRuntime,
+    sidecar,
+};
+
+pub const CONTAINER_PREFIX: &str = "nerdpanel-server-";
+
 pub fn container_name(id: i32) -> String {
-    format!("nerdpanel-server-{}", id)
+    format!("{}{}", CONTAINER_PREFIX, id)
+}
+
+/// Folder the agent keeps its state in, `NERDPANEL_RUN_DIR`.
+fn get_run_folder() -> String {
+    std::env::var("NERDPANEL_RUN_DIR").unwrap_or_else(|_| "run/nerdpanel".to_string())
+}
+
+pub fn get_volume_root() -> String {
+    format!("{}/volumes", get_run_folder())
 }
 
 pub fn get_folder(id: i32) -> String {
-    // TODO get from env
-    format!("run/nerdpanel/volumes/{}", container_name(id))
+    format!("{}/{}", get_volume_root(), container_name(id))
+}
+
+/// Holds the script of a running installer.
+pub fn get_installer_folder(id: i32) -> String {
+    format!("{}/installers/{}", get_run_folder(), id)
+}
+
+fn get_spec_folder() -> String {
+    format!("{}/servers", get_run_folder())
+}
+
+fn get_spec_path(id: i32) -> String {
+    format!("{}/{}.json", get_spec_folder(), id)
+}
+
+/// Keeps the last server definition received from the orchestrator, so
+/// lifecycle operations like stopping know the pod's settings.
+pub async fn save_server_spec(server: &Server) -> Result<(), AppError> {
+    let path = get_spec_path(server.id);
+    tokio::fs::create_dir_all(std::path::Path::new(&path).parent().unwrap()).await?;
+    let json = serde_json::to_vec(server).map_err(std::io::Error::other)?;
+    tokio::fs::write(path, json).await?;
+    Ok(())
+}
+
+pub async fn load_server_spec(id: i32) -> Result<Option<Server>, AppError> {
+    match tokio::fs::read(get_spec_path(id)).await {
+        Ok(json) => Ok(Some(
+            serde_json::from_slice(&json).map_err(std::io::Error::other)?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Every server spec saved on this node.
+pub async fn list_server_specs() -> Result<Vec<Server>, AppError> {
+    let mut entries = match tokio::fs::read_dir(get_spec_folder()).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut specs = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let id = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|id| id.parse().ok());
+        if let Some(spec) = match id {
+            Some(id) => load_server_spec(id).await?,
+            None => None,
+        } {
+            specs.push(spec);
+        }
+    }
+    Ok(specs)
+}
+
+pub async fn remove_server_spec(id: i32) -> Result<(), AppError> {
+    match tokio::fs::remove_file(get_spec_path(id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn get_should_run_path(id: i32) -> String {
+    format!("{}/{}.running", get_spec_folder(), id)
+}
+
+/// Remembers whether a server was last started or stopped by a user, so it
+/// can be brought back up after the host restarts.
+pub async fn set_should_run(id: i32, should_run: bool) -> Result<(), AppError> {
+    let path = get_should_run_path(id);
+    if should_run {
+        tokio::fs::create_dir_all(std::path::Path::new(&path).parent().unwrap()).await?;
+        tokio::fs::write(path, b"").await?;
+        return Ok(());
+    }
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+pub async fn should_run(id: i32) -> bool {
+    tokio::fs::try_exists(get_should_run_path(id))
+        .await
+        .unwrap_or(false)
+}
+
+pub fn get_backup_repository() -> String {
+    format!("{}/backups", get_run_folder())
 }
 
 pub fn container_options(
+    agent_config: &config::Config,
     server: &Server,
-) -> (Option<CreateContainerOptions<String>>, Config<String>) {
+) -> Result<Config<String>, AppError> {
     let folder_path = fs::canonicalize(get_folder(server.id)).unwrap();
 
+    let ports = std::iter::once(&server.primary_port).chain(&server.additional_ports);
     let mut port_bindings = ::std::collections::HashMap::new();
-    port_bindings.insert(
-        format!("{}/tcp", server.primary_port.port),
-        Some(vec![PortBinding {
-            host_ip: Some(server.primary_port.ip.clone()),
-            host_port: Some(server.primary_port.port.to_string()),
-        }]),
-    );
-    for port_binding in &server.additional_ports {
-        port_bindings.insert(
-            format!("{}/tcp", port_binding.port),
-            Some(vec![PortBinding {
-                host_ip: Some(port_binding.ip.clone()),
-                host_port: Some(port_binding.port.to_string()),
-            }]),
-        );
+    let mut exposed_ports = ::std::collections::HashMap::new();
+    for port_binding in ports {
+        for protocol in port_binding.protocol.docker_protocols() {
+            let container_port = format!("{}/{}", port_binding.port, protocol);
+            port_bindings.insert(
+                container_port.clone(),
+                Some(vec![PortBinding {
+                    host_ip: Some(port_binding.ip.clone()),
+                    host_port: Some(port_binding.port.to_string()),
+                }]),
+            );
+            exposed_ports.insert(container_port, ::std::collections::HashMap::new());
+        }
     }
-    let host_config = HostConfig {
+    let mut host_config = HostConfig {
         mounts: Some(vec![Mount {
             target: Some(String::from("/data")),
             source: Some(folder_path.to_string_lossy().to_string()),
@@ -49,23 +162,16 @@ pub fn container_options(
             ..Default::default()
         }]),
         port_bindings: Some(port_bindings),
+        network_mode: Some(network_name(server.id)),
         ..Default::default()
     };
+    hardening::apply(&mut host_config, server);
 
     let config = Config {
         image: Some(server.image.clone()),
         tty: Some(true),
         open_stdin: Some(true),
-        cmd: Some({
-            let mut cmd = vec![];
-            server
-                .startup_command
-                .split_ascii_whitespace()
-                .for_each(|s| {
-                    cmd.push(s.to_string());
-                });
-            cmd
-        }),
+        cmd: Some(startup::render(server)?),
         env: Some({
             let mut env = vec![];
             for env_var in &server.env_vars {
@@ -74,36 +180,60 @@ pub fn container_options(
             env
         }),
         host_config: Some(host_config),
-        exposed_ports: {
-            let mut map = ::std::collections::HashMap::new();
-            map.insert(
-                format!("{}/tcp", server.primary_port.port),
-                ::std::collections::HashMap::new(),
-            );
-            for port_binding in &server.additional_ports {
-                map.insert(
-                    format!("{}/tcp", port_binding.port),
-                    ::std::collections::HashMap::new(),
-                );
-            }
-            Some(map)
-        },
+        exposed_ports: Some(exposed_ports),
+        networking_config: Some(networking_config(server.id, SERVER_ALIAS)),
+        user: hardening::user(agent_config, server),
         ..Default::default()
     };
 
-    let options = Some(CreateContainerOptions {
-        name: container_name(server.id),
-        platform: None,
-    });
+    Ok(config)
+}
 
-    (options, config)
+/// Creates the volume folder, network, container and sidecars of a server and
+/// saves its spec. Sidecars of a previous container of the server are replaced.
+pub async fn create_container(
+    runtime: &dyn ContainerRuntime,
+    agent_config: &config::Config,
+    server: &Server,
+) -> Result<(), AppError> {
+    tokio::fs::create_dir_all(get_folder(server.id)).await?;
+    sidecar::remove(runtime, server.id).await?;
+    network::create(runtime, server.id).await?;
+    let config = container_options(agent_config, server)?;
+    runtime.create(&container_name(server.id), config).await?;
+    sidecar::create(runtime, server).await?;
+    save_server_spec(server).await
 }
 
 #[derive(Error, Debug, ErrorStatus)]
 pub enum AppError {
-    #[error("Docker error")]
+    #[error("Docker error: {0}")]
     #[status(StatusCode::INTERNAL_SERVER_ERROR)]
     DockerError(bollard::errors::Error),
+    #[error("IO error")]
+    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
+    IoError(std::io::Error),
+    #[error("not found")]
+    #[status(StatusCode::NOT_FOUND)]
+    NotFound,
+    #[error("{0}")]
+    #[status(StatusCode::BAD_REQUEST)]
+    BadRequest(String),
+    #[error("{0}")]
+    #[status(StatusCode::CONFLICT)]
+    Conflict(String),
+    #[error("unauthorized")]
+    #[status(StatusCode::UNAUTHORIZED)]
+    Unauthorized,
+    #[error("Orchestrator error: {0}")]
+    #[status(StatusCode::BAD_GATEWAY)]
+    OrchestratorError(String),
+    #[error("Transfer error: {0}")]
+    #[status(StatusCode::BAD_GATEWAY)]
+    TransferError(String),
+    #[error("Internal Server Error")]
+    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
+    InternalError,
 }
 
 impl From<bollard::errors::Error> for AppError {
@@ -112,3 +242,29 @@ impl From<bollard::errors::Error> for AppError {
         Self::DockerError(e)
     }
 }
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            std::io::ErrorKind::InvalidInput => Self::BadRequest(e.to_string()),
+            _ => {
+                tracing::error!("IO error: {:?}", e);
+                Self::IoError(e)
+            }
+        }
+    }
+}
+
+impl From<TemplateError> for AppError {
+    fn from(e: TemplateError) -> Self {
+        Self::BadRequest(format!("startup command: {}", e))
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        tracing::error!("Error connecting to orchestrator: {:?}", e);
+        Self::OrchestratorError(e.to_string())
+    }
+}
@@ -0,0 +1,437 @@
+//! Client of the agent's API, used by the orchestrator and by agents sending
+//! volumes to each other.
+//!
+//! One [`AgentClient`] holds the connection pool for every node. Calls are
+//! retried with backoff while no connection to the agent can be made, and error
+//! responses are decoded into [`AgentError`].
+
+use std::{fmt::Display, time::Duration};
+
+use reqwest::{Certificate, Method, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::{
+    agent_types::{
+        AppliedGeneration, ConsoleCommand, CrashReport, DriftReport, IncomingTransfer,
+        InstallOptions, InstallReport, PruneResult, RestoreSnapshot, RetentionPolicy, ServerSignal,
+        ServerStatus, ServerStatusReport, Snapshot, TransferProgress, TransferTarget, VerifyReport,
+    },
+    orch_types::Server,
+};
+
+/// Applying or deleting a server stops it first, which may take the pod's stop
+/// timeout.
+const PROVISION_TIMEOUT: Duration = Duration::from_secs(120);
+/// Snapshots read or write the whole volume.
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// An endpoint of the agent's API. Path parameters are written `{name}`, like
+/// in the agent's OpenAPI document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Operation {
+    pub method: Method,
+    pub path: &'static str,
+}
+
+impl Operation {
+    const fn new(method: Method, path: &'static str) -> Self {
+        Self { method, path }
+    }
+
+    fn url(&self, base: &str, params: &[&(dyn Display + Sync)]) -> String {
+        let mut params = params.iter();
+        let path: Vec<String> = self
+            .path
+            .split('/')
+            .map(|segment| match segment.starts_with('{') {
+                true => params.next().expect("missing path parameter").to_string(),
+                false => segment.to_string(),
+            })
+            .collect();
+        format!("{}{}", base, path.join("/"))
+    }
+}
+
+pub const CREATE_SERVER: Operation = Operation::new(Method::POST, "/server");
+pub const UPDATE_SERVER: Operation = Operation::new(Method::PUT, "/server");
+pub const SERVER_STATUS: Operation = Operation::new(Method::GET, "/server/{id}");
+pub const DELETE_SERVER: Operation = Operation::new(Method::DELETE, "/server/{id}");
+pub const SERVER_REPORT: Operation = Operation::new(Method::GET, "/server/{id}/report");
+pub const SERVER_SIGNAL: Operation = Operation::new(Method::POST, "/server/{id}/signal");
+pub const SERVER_COMMAND: Operation = Operation::new(Method::POST, "/server/{id}/command");
+pub const CRASH_REPORT: Operation = Operation::new(Method::GET, "/server/{id}/crash");
+pub const GENERATIONS: Operation = Operation::new(Method::GET, "/server/generations");
+pub const INSTALL_REPORT: Operation = Operation::new(Method::GET, "/server/{id}/install");
+pub const INSTALL: Operation = Operation::new(Method::POST, "/server/{id}/install");
+pub const LIST_SNAPSHOTS: Operation = Operation::new(Method::GET, "/backup/{id}");
+pub const CREATE_SNAPSHOT: Operation = Operation::new(Method::POST, "/backup/{id}");
+pub const DELETE_SNAPSHOT: Operation = Operation::new(Method::DELETE, "/backup/{id}/{snapshot}");
+pub const RESTORE_SNAPSHOT: Operation =
+    Operation::new(Method::POST, "/backup/{id}/{snapshot}/restore");
+pub const PRUNE_SNAPSHOTS: Operation = Operation::new(Method::POST, "/backup/{id}/prune");
+pub const VERIFY_BACKUPS: Operation = Operation::new(Method::POST, "/backup/verify");
+pub const DRIFT_REPORT: Operation = Operation::new(Method::GET, "/reconcile");
+pub const RECONCILE: Operation = Operation::new(Method::POST, "/reconcile");
+pub const TRANSFER_PROGRESS: Operation = Operation::new(Method::GET, "/transfer/{id}");
+pub const SEND_TRANSFER: Operation = Operation::new(Method::POST, "/transfer/{id}");
+pub const EXPECT_TRANSFER: Operation = Operation::new(Method::PUT, "/transfer/{id}/incoming");
+pub const RECEIVE_TRANSFER: Operation = Operation::new(Method::PUT, "/transfer/{id}/archive");
+
+/// Every endpoint the client calls.
+pub const OPERATIONS: &[Operation] = &[
+    CREATE_SERVER,
+    UPDATE_SERVER,
+    SERVER_STATUS,
+    DELETE_SERVER,
+    SERVER_REPORT,
+    SERVER_SIGNAL,
+    SERVER_COMMAND,
+    CRASH_REPORT,
+    GENERATIONS,
+    INSTALL_REPORT,
+    INSTALL,
+    LIST_SNAPSHOTS,
+    CREATE_SNAPSHOT,
+    DELETE_SNAPSHOT,
+    RESTORE_SNAPSHOT,
+    PRUNE_SNAPSHOTS,
+    VERIFY_BACKUPS,
+    DRIFT_REPORT,
+    RECONCILE,
+    TRANSFER_PROGRESS,
+    SEND_TRANSFER,
+    EXPECT_TRANSFER,
+    RECEIVE_TRANSFER,
+];
+
+#[derive(Error, Debug)]
+pub enum AgentError {
+    #[error("error connecting to agent: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("agent rejected the credentials")]
+    Unauthorized,
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("agent error ({status}): {message}")]
+    Agent { status: StatusCode, message: String },
+}
+
+impl AgentError {
+    async fn decode(res: Response) -> Self {
+        let status = res.status();
+        let message = res.text().await.unwrap_or_default();
+        match status {
+            StatusCode::BAD_REQUEST => Self::BadRequest(message),
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::NOT_FOUND => Self::NotFound(message),
+            StatusCode::CONFLICT => Self::Conflict(message),
+            status => Self::Agent { status, message },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AgentClientConfig {
+    /// Reach agents over HTTPS.
+    pub tls: bool,
+    /// PEM certificate to trust besides the system's, for agents with a self
+    /// signed certificate.
+    pub root_certificate: Option<Vec<u8>>,
+    pub connect_timeout: Duration,
+    /// Timeout of calls that do not need longer.
+    pub timeout: Duration,
+    /// How often a call is retried while no connection to the agent can be made.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for every further one.
+    pub backoff: Duration,
+}
+
+impl Default for AgentClientConfig {
+    fn default() -> Self {
+        Self {
+            tls: false,
+            root_certificate: None,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Client of every agent, sharing one connection pool.
+#[derive(Clone)]
+pub struct AgentClient {
+    http: reqwest::Client,
+    config: AgentClientConfig,
+}
+
+impl AgentClient {
+    pub fn new(config: AgentClientConfig) -> Result<Self, AgentError> {
+        let mut builder = reqwest::Client::builder().connect_timeout(config.connect_timeout);
+        if let Some(pem) = &config.root_certificate {
+            builder = builder.add_root_certificate(Certificate::from_pem(pem)?);
+        }
+        Ok(Self {
+            http: builder.build()?,
+            config,
+        })
+    }
+
+    /// URL of the agent listening at `host`.
+    pub fn url(&self, host: &str) -> String {
+        let scheme = if self.config.tls { "https" } else { "http" };
+        format!("{}://{}", scheme, host)
+    }
+
+    /// The agent at `url`, authenticated with `token` if there is one.
+    pub fn agent(&self, url: &str, token: Option<&str>) -> Agent {
+        Agent {
+            client: self.clone(),
+            url: url.trim_end_matches('/').to_string(),
+            token: token.map(str::to_string),
+        }
+    }
+}
+
+/// The API of one agent.
+pub struct Agent {
+    client: AgentClient,
+    url: String,
+    token: Option<String>,
+}
+
+impl Agent {
+    async fn send<B: Serialize + ?Sized>(
+        &self,
+        operation: &Operation,
+        params: &[&(dyn Display + Sync)],
+        body: Option<&B>,
+        timeout: Duration,
+    ) -> Result<Response, AgentError> {
+        let url = operation.url(&self.url, params);
+        let config = &self.client.config;
+        let mut delay = config.backoff;
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .http
+                .request(operation.method.clone(), &url)
+                .timeout(timeout);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let res = request.send().await;
+            // only a request that never reached the agent is safe to send again,
+            // one that timed out may still be running there
+            let unreachable = res.as_ref().is_err_and(|e| e.is_connect());
+            if !unreachable || attempt == config.retries {
+                let res = res?;
+                if !res.status().is_success() {
+                    return Err(AgentError::decode(res).await);
+                }
+                return Ok(res);
+            }
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    async fn call<B: Serialize + ?Sized>(
+        &self,
+        operation: &Operation,
+        params: &[&(dyn Display + Sync)],
+        body: Option<&B>,
+    ) -> Result<(), AgentError> {
+        let timeout = self.client.config.timeout;
+        self.send(operation, params, body, timeout).await?;
+        Ok(())
+    }
+
+    async fn fetch<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        operation: &Operation,
+        params: &[&(dyn Display + Sync)],
+        body: Option<&B>,
+    ) -> Result<T, AgentError> {
+        let timeout = self.client.config.timeout;
+        Ok(self
+            .send(operation, params, body, timeout)
+            .await?
+            .json()
+            .await?)
+    }
+
+    pub async fn create_server(&self, server: &Server) -> Result<(), AgentError> {
+        self.send(&CREATE_SERVER, &[], Some(server), PROVISION_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates or updates a server, the agent handles both the same way.
+    pub async fn apply_server(&self, server: &Server) -> Result<(), AgentError> {
+        self.send(&UPDATE_SERVER, &[], Some(server), PROVISION_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_server(&self, id: i32) -> Result<(), AgentError> {
+        self.send(&DELETE_SERVER, &[&id], None::<&()>, PROVISION_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_status(&self, id: i32) -> Result<ServerStatus, AgentError> {
+        self.fetch(&SERVER_STATUS, &[&id], None::<&()>).await
+    }
+
+    /// Status of a server together with its sidecars.
+    pub async fn get_status_report(&self, id: i32) -> Result<ServerStatusReport, AgentError> {
+        self.fetch(&SERVER_REPORT, &[&id], None::<&()>).await
+    }
+
+    pub async fn send_signal(&self, id: i32, signal: ServerSignal) -> Result<(), AgentError> {
+        self.call(&SERVER_SIGNAL, &[&id], Some(&signal)).await
+    }
+
+    pub async fn send_command(&self, id: i32, command: &str) -> Result<(), AgentError> {
+        let command = ConsoleCommand {
+            command: command.to_string(),
+        };
+        self.call(&SERVER_COMMAND, &[&id], Some(&command)).await
+    }
+
+    pub async fn get_crash_report(&self, id: i32) -> Result<Option<CrashReport>, AgentError> {
+        self.fetch(&CRASH_REPORT, &[&id], None::<&()>).await
+    }
+
+    pub async fn get_applied_generations(&self) -> Result<Vec<AppliedGeneration>, AgentError> {
+        self.fetch(&GENERATIONS, &[], None::<&()>).await
+    }
+
+    /// Starts the pod's installer, progress is reported through the server status.
+    pub async fn install_server(&self, id: i32, options: InstallOptions) -> Result<(), AgentError> {
+        self.call(&INSTALL, &[&id], Some(&options)).await
+    }
+
+    pub async fn get_install_report(&self, id: i32) -> Result<Option<InstallReport>, AgentError> {
+        self.fetch(&INSTALL_REPORT, &[&id], None::<&()>).await
+    }
+
+    pub async fn list_snapshots(&self, id: i32) -> Result<Vec<Snapshot>, AgentError> {
+        self.fetch(&LIST_SNAPSHOTS, &[&id], None::<&()>).await
+    }
+
+    pub async fn create_snapshot(&self, id: i32) -> Result<Snapshot, AgentError> {
+        let res = self
+            .send(&CREATE_SNAPSHOT, &[&id], None::<&()>, BACKUP_TIMEOUT)
+            .await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn delete_snapshot(&self, id: i32, snapshot: &str) -> Result<(), AgentError> {
+        self.call(&DELETE_SNAPSHOT, &[&id, &snapshot], None::<&()>)
+            .await
+    }
+
+    pub async fn restore_snapshot(
+        &self,
+        id: i32,
+        snapshot: &str,
+        options: &RestoreSnapshot,
+    ) -> Result<(), AgentError> {
+        self.send(
+            &RESTORE_SNAPSHOT,
+            &[&id, &snapshot],
+            Some(options),
+            BACKUP_TIMEOUT,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn prune_snapshots(
+        &self,
+        id: i32,
+        policy: &RetentionPolicy,
+    ) -> Result<PruneResult, AgentError> {
+        self.fetch(&PRUNE_SNAPSHOTS, &[&id], Some(policy)).await
+    }
+
+    pub async fn verify_backups(&self) -> Result<VerifyReport, AgentError> {
+        let res = self
+            .send(&VERIFY_BACKUPS, &[], None::<&()>, BACKUP_TIMEOUT)
+            .await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn get_drift_report(&self) -> Result<Option<DriftReport>, AgentError> {
+        self.fetch(&DRIFT_REPORT, &[], None::<&()>).await
+    }
+
+    pub async fn reconcile(&self) -> Result<DriftReport, AgentError> {
+        self.fetch(&RECONCILE, &[], None::<&()>).await
+    }
+
+    pub async fn get_transfer_progress(
+        &self,
+        id: i32,
+    ) -> Result<Option<TransferProgress>, AgentError> {
+        self.fetch(&TRANSFER_PROGRESS, &[&id], None::<&()>).await
+    }
+
+    /// Has the agent stop the server and send its volume to the target,
+    /// progress is polled with `get_transfer_progress`.
+    pub async fn send_transfer(
+        &self,
+        id: i32,
+        target: &TransferTarget,
+    ) -> Result<TransferProgress, AgentError> {
+        self.fetch(&SEND_TRANSFER, &[&id], Some(target)).await
+    }
+
+    /// Lets the agent accept the volume sent with `token`.
+    pub async fn expect_transfer(&self, id: i32, token: &str) -> Result<(), AgentError> {
+        let incoming = IncomingTransfer {
+            token: token.to_string(),
+        };
+        self.call(&EXPECT_TRANSFER, &[&id], Some(&incoming)).await
+    }
+
+    /// Streams a volume archive to the destination of a transfer, which
+    /// authenticates it by the transfer's token rather than the node's. The
+    /// body can not be sent twice and takes as long as the volume needs, so
+    /// there is neither a retry nor a timeout.
+    pub async fn receive_transfer(
+        &self,
+        id: i32,
+        token: &str,
+        archive: reqwest::Body,
+    ) -> Result<(), AgentError> {
+        let res = self
+            .client
+            .http
+            .request(
+                RECEIVE_TRANSFER.method.clone(),
+                RECEIVE_TRANSFER.url(&self.url, &[&id]),
+            )
+            .bearer_auth(token)
+            .body(archive)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(AgentError::decode(res).await);
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, ToSchema)]
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug)]
 pub enum ServerSignal {
     Start,
     Stop,
@@ -9,9 +10,202 @@ pub enum ServerSignal {
     Kill,
 }
 
-#[derive(Serialize, Deserialize, ToSchema)]
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ServerStatus {
+    /// The process runs but the pod's startup done pattern was not seen yet.
+    Starting,
     Running,
+    Stopping,
     Stopped,
+    /// The process exited without being asked to.
+    Crashed,
     Installing,
+    InstallFailed,
+    /// The agent has no container for the server.
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SidecarStatus {
+    pub name: String,
+    pub running: bool,
+    /// The sidecar's container is missing.
+    pub missing: bool,
+}
+
+/// Resource usage of a running container.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Default)]
+pub struct ResourceUsage {
+    /// Percent of one CPU core.
+    pub cpu: f64,
+    /// Bytes.
+    pub memory: u64,
+    /// Bytes, 0 without a limit.
+    pub memory_limit: u64,
+}
+
+/// Status of a server together with its sidecars.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ServerStatusReport {
+    pub status: ServerStatus,
+    pub sidecars: Vec<SidecarStatus>,
+    /// Left out while the server is not running.
+    #[serde(default)]
+    pub usage: Option<ResourceUsage>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ConsoleCommand {
+    pub command: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Snapshot {
+    pub id: String,
+    pub server_id: i32,
+    pub parent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub file_count: i64,
+    pub total_size: i64,
+    pub added_size: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RestoreSnapshot {
+    pub wipe: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Default)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PruneResult {
+    pub kept: Vec<String>,
+    pub removed: Vec<String>,
+    pub freed_chunks: i64,
+    pub freed_size: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VerifyReport {
+    pub snapshots: i64,
+    pub chunks: i64,
+    pub missing_chunks: Vec<String>,
+    pub corrupt_chunks: Vec<String>,
+    pub unreferenced_chunks: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct CrashReport {
+    pub crashed_at: DateTime<Utc>,
+    pub exit_code: Option<i64>,
+    /// The last lines the server printed before it died.
+    pub console: Vec<String>,
+    /// Crashes within the crash loop window, including this one.
+    pub recent_crashes: u32,
+    /// Seconds until the automatic restart, if one is scheduled.
+    pub restart_in: Option<u64>,
+    /// Set when the server crashed too often and is no longer restarted.
+    pub restart_disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Default)]
+pub struct InstallOptions {
+    /// Deletes every file of the server before the installer runs.
+    #[serde(default)]
+    pub wipe: bool,
+}
+
+/// Outcome of the last run of a server's installer.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct InstallReport {
+    pub started_at: DateTime<Utc>,
+    /// Empty while the installer is running.
+    pub finished_at: Option<DateTime<Utc>>,
+    pub wiped: bool,
+    pub exit_code: Option<i64>,
+    pub error: Option<String>,
+    /// The last lines the installer printed.
+    pub console: Vec<String>,
+}
+
+/// Differences between the servers the orchestrator expects on a node and the
+/// containers the agent found, and what the agent did about them.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Default)]
+pub struct DriftReport {
+    pub checked_at: DateTime<Utc>,
+    /// Servers whose container was missing and has been recreated.
+    pub recreated: Vec<i32>,
+    /// Servers whose container was recreated because the server changed.
+    pub updated: Vec<i32>,
+    /// Running servers that changed, they are updated once they are stopped.
+    pub outdated: Vec<i32>,
+    /// Containers of servers the orchestrator does not know about.
+    pub orphaned: Vec<i32>,
+    /// Servers that were running before the agent or host restarted.
+    pub restored: Vec<i32>,
+    pub errors: Vec<String>,
+}
+
+/// Generation of a server spec the agent has applied.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AppliedGeneration {
+    pub id: i32,
+    pub generation: i64,
+}
+
+/// Sent by agents to the orchestrator periodically.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Heartbeat {
+    pub agent_version: String,
+    /// Empty when the agent can not reach docker.
+    pub docker_version: Option<String>,
+    pub resources: HostResources,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct HostResources {
+    pub cpu_count: u32,
+    pub load_average: f64,
+    pub memory_total: u64,
+    pub memory_available: u64,
+    /// Space on the disk holding the server volumes.
+    pub disk_total: u64,
+    pub disk_available: u64,
+}
+
+/// Tells a source agent to send a server's volume to another agent.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TransferTarget {
+    /// Base url of the destination agent.
+    pub url: String,
+    pub token: String,
+}
+
+/// Tells a destination agent to accept a server's volume from another agent.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct IncomingTransfer {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct TransferProgress {
+    pub state: TransferState,
+    /// Uncompressed bytes of the volume sent so far.
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    Stopping,
+    Sending,
+    Done,
+    Failed,
 }
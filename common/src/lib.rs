@@ -1,2 +1,4 @@
+pub mod agent_client;
 pub mod agent_types;
 pub mod orch_types;
+pub mod startup;
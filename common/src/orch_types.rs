@@ -1,12 +1,68 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::agent_types::{HostResources, RetentionPolicy, ServerSignal};
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct Node {
     pub id: i32,
     pub name: String,
     pub fqdn: String,
     pub ports: Vec<NodePort>,
+
+    pub status: NodeStatus,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub agent_version: Option<String>,
+    pub docker_version: Option<String>,
+    pub resources: Option<HostResources>,
+
+    /// No new servers are deployed to a node in maintenance.
+    pub maintenance: bool,
+    pub maintenance_message: Option<String>,
+
+    pub capacity: NodeCapacity,
+    /// Location tags servers can be placed by.
+    pub tags: Vec<String>,
+}
+
+/// Memory and disk are in MiB, cpu in percent of a core. A resource without a
+/// total is not limited.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct NodeCapacity {
+    pub memory_total: Option<i32>,
+    pub cpu_total: Option<i32>,
+    pub disk_total: Option<i32>,
+    /// Percentage the totals may be exceeded by.
+    pub memory_overallocate: i32,
+    pub cpu_overallocate: i32,
+    pub disk_overallocate: i32,
+
+    /// Sum of the limits of the node's servers.
+    pub memory_allocated: i64,
+    pub cpu_allocated: i64,
+    pub disk_allocated: i64,
+}
+
+/// How a node is picked for a server that was not given one.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementStrategy {
+    /// Fill the fullest node that still fits the server.
+    #[default]
+    BinPack,
+    /// Use the emptiest node.
+    Spread,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+    Online,
+    /// The agent is up but docker is unreachable or the host is running out of
+    /// memory or disk space.
+    Degraded,
+    Offline,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -16,9 +72,35 @@ pub struct NodePort {
     pub is_primary: bool,
     pub ip: String,
     pub port: i32,
+    pub protocol: PortProtocol,
+    /// Address shown to users instead of the bind ip, e.g. a public hostname.
+    pub alias: Option<String>,
 }
 
-#[derive(sqlx::Type, Serialize, Deserialize, ToSchema)]
+#[derive(
+    sqlx::Type, Serialize, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[sqlx(type_name = "port_protocol", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PortProtocol {
+    #[default]
+    Tcp,
+    Udp,
+    Both,
+}
+
+impl PortProtocol {
+    /// Docker protocol names to publish the port with.
+    pub fn docker_protocols(self) -> &'static [&'static str] {
+        match self {
+            PortProtocol::Tcp => &["tcp"],
+            PortProtocol::Udp => &["udp"],
+            PortProtocol::Both => &["tcp", "udp"],
+        }
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, ToSchema, Clone)]
 #[sqlx(type_name = "env_var_type")]
 pub struct EnvVar {
     pub key: String,
@@ -32,6 +114,71 @@ pub struct Image {
     pub tag: String,
 }
 
+/// How the agent asks a server process to shut down.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StopMethod {
+    /// Write a console command such as `stop` to the server's stdin.
+    Command { command: String },
+    /// Send a signal such as `SIGINT` to the server process.
+    Signal { signal: String },
+    /// Let docker stop the container (SIGTERM).
+    #[default]
+    Docker,
+}
+
+/// Type of a pod variable's value.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VariableType {
+    #[default]
+    String,
+    Number,
+    /// `true` or `false`.
+    Boolean,
+}
+
+/// A rule the value of a pod variable has to satisfy.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VariableRule {
+    /// The whole value matches the pattern.
+    Regex { pattern: String },
+    /// The value is a number within the bounds.
+    Range { min: Option<f64>, max: Option<f64> },
+    /// The value is one of the given values.
+    Enum { values: Vec<String> },
+}
+
+/// An environment variable a pod's servers are configured with.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct PodVariable {
+    pub key: String,
+    /// Name shown instead of the key.
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub default: String,
+    #[serde(default, rename = "type")]
+    pub kind: VariableType,
+    /// Rejects empty values.
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub rules: Vec<VariableRule>,
+    /// Whether owners see the value, staff always do.
+    #[serde(default = "default_true")]
+    pub user_viewable: bool,
+    /// Whether owners can change the value.
+    #[serde(default)]
+    pub user_editable: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
 #[derive(sqlx::FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Pod {
     pub id: i32,
@@ -39,7 +186,222 @@ pub struct Pod {
     pub images: Vec<Image>,
     pub startup_command: String,
     pub installer_image: String,
+    /// Script the installer runs, instead of the installer image's own command.
+    pub installer_script: Option<String>,
+    /// Program that runs the installer script.
+    pub installer_entrypoint: String,
+    #[sqlx(json)]
+    pub variables: Vec<PodVariable>,
+    #[sqlx(json)]
+    pub config_files: Vec<ConfigFile>,
+    #[sqlx(json)]
+    pub sidecars: Vec<Sidecar>,
+    #[sqlx(json)]
+    pub hardening: Hardening,
+    #[sqlx(json)]
+    pub stop_method: StopMethod,
+    pub stop_timeout: i32,
+    /// Regex matched against console output to tell when the server finished starting.
+    pub done_regex: Option<String>,
+    /// Bumped on every change of the pod.
+    #[serde(default)]
+    pub revision: i32,
+    #[serde(default)]
+    pub category_id: Option<i32>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub node_restrictions: NodeRestrictions,
+}
+
+/// Groups pods in the pod list, e.g. "Minecraft".
+#[derive(sqlx::FromRow, Serialize, Deserialize, ToSchema)]
+pub struct PodCategory {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    /// URL or name of the icon shown next to the category.
+    pub icon: Option<String>,
+}
+
+/// Nodes servers of a pod may be deployed to. A node has to meet all of them.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug, Default)]
+pub struct NodeRestrictions {
+    /// Any node if empty.
+    #[serde(default)]
+    pub node_ids: Vec<i32>,
+    /// Tags the node must carry, e.g. its location.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Least CPU capacity of the node. Nodes without a set capacity pass.
+    #[serde(default)]
+    pub min_cpu: Option<i32>,
+    /// Least memory capacity of the node in MiB. Nodes without a set capacity pass.
+    #[serde(default)]
+    pub min_memory: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigFormat {
+    /// Java `.properties` files such as `server.properties`.
+    Properties,
+    Ini,
+    Yaml,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct ConfigValue {
+    /// Nested keys of YAML and JSON files are joined with dots, as are INI
+    /// sections and their keys.
+    pub key: String,
+    /// Template of the value, e.g. `{{SERVER_PORT}}`.
+    pub value: String,
+}
+
+/// A file in a server's volume whose settings are set before every start.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct ConfigFile {
+    /// Relative to the server's volume.
+    pub path: String,
+    pub format: ConfigFormat,
+    pub values: Vec<ConfigValue>,
+}
+
+/// A container run alongside a server's own, e.g. a database or a map renderer.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct Sidecar {
+    /// Also the host name the server reaches the sidecar by.
+    pub name: String,
+    /// `name:tag` of the image.
+    pub image: String,
+    /// Template like the startup command. The image's own command runs if left out.
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Values are templates like config file values.
+    #[serde(default)]
     pub env_vars: Vec<EnvVar>,
+    #[serde(default)]
+    pub volumes: Vec<SidecarVolume>,
+}
+
+/// A folder of the server's volume shared with a sidecar.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct SidecarVolume {
+    /// Relative to the server's volume.
+    pub source: String,
+    /// Absolute path in the sidecar.
+    pub target: String,
+}
+
+/// Restrictions of the containers of a pod's servers. Pods whose images need
+/// more can turn single settings off.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct Hardening {
+    /// Drops all Linux capabilities.
+    #[serde(default = "default_true")]
+    pub drop_capabilities: bool,
+    /// Keeps processes from gaining privileges, e.g. through setuid binaries.
+    #[serde(default = "default_true")]
+    pub no_new_privileges: bool,
+    /// Runs the server as the node's container user, which owns the server's
+    /// volume, rather than as the image's user.
+    #[serde(default = "default_true")]
+    pub non_root_user: bool,
+    /// Most processes the container may run, unlimited if left out.
+    #[serde(default = "default_pid_limit")]
+    pub pid_limit: Option<i64>,
+    /// Mounts the root filesystem read only. Off unless the pod's image works
+    /// with it.
+    #[serde(default)]
+    pub read_only_root: bool,
+    /// Writable tmpfs mounts of a read only root filesystem.
+    #[serde(default = "default_tmpfs")]
+    pub tmpfs: Vec<String>,
+}
+
+fn default_pid_limit() -> Option<i64> {
+    Some(512)
+}
+
+fn default_tmpfs() -> Vec<String> {
+    vec!["/tmp".to_string()]
+}
+
+impl Default for Hardening {
+    fn default() -> Self {
+        Self {
+            drop_capabilities: true,
+            no_new_privileges: true,
+            non_root_user: true,
+            pid_limit: default_pid_limit(),
+            read_only_root: false,
+            tmpfs: default_tmpfs(),
+        }
+    }
+}
+
+/// A pod as it was after one of its changes.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PodRevision {
+    pub pod_id: i32,
+    pub revision: i32,
+    pub document: PodDocument,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A setting of a server that rolling out a pod revision changes.
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+pub struct RolloutChange {
+    /// `image`, `startup_command` or `env_vars.<KEY>`.
+    pub setting: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// What rolling out the current revision of its pod does to a server.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ServerRollout {
+    pub server_id: i32,
+    pub from_revision: i32,
+    pub to_revision: i32,
+    pub changes: Vec<RolloutChange>,
+    /// Settings the server overrides, which keep their value.
+    pub kept: Vec<String>,
+    /// Why the revision can not be rolled out to the server.
+    pub error: Option<String>,
+    pub applied: bool,
+}
+
+/// Version of [`PodDocument`] written by this panel.
+pub const POD_FORMAT_VERSION: u32 = 1;
+
+/// A pod as it is exported to share it between panels, see `docs/pod-format.md`.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PodDocument {
+    pub version: u32,
+    pub name: String,
+    pub images: Vec<Image>,
+    pub startup_command: String,
+    pub installer_image: String,
+    #[serde(default)]
+    pub installer_script: Option<String>,
+    #[serde(default)]
+    pub installer_entrypoint: Option<String>,
+    #[serde(default)]
+    pub variables: Vec<PodVariable>,
+    #[serde(default)]
+    pub config_files: Vec<ConfigFile>,
+    #[serde(default)]
+    pub sidecars: Vec<Sidecar>,
+    #[serde(default)]
+    pub hardening: Hardening,
+    #[serde(default)]
+    pub stop_method: StopMethod,
+    #[serde(default)]
+    pub stop_timeout: Option<i32>,
+    #[serde(default)]
+    pub done_regex: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -57,9 +419,62 @@ pub struct Server {
     pub additional_ports: Vec<ServerNodePort>,
 
     pub pod_id: i32,
+    /// Revision of the pod the server's image, startup command and env vars
+    /// were taken from.
+    #[serde(default)]
+    pub pod_revision: i32,
     pub image: String,
     pub startup_command: String,
     pub env_vars: Vec<EnvVar>,
+    pub restart_on_crash: bool,
+    /// Image of the pod's installer, run against the server's files.
+    #[serde(default)]
+    pub installer_image: String,
+    #[serde(default)]
+    pub installer_script: Option<String>,
+    #[serde(default)]
+    pub installer_entrypoint: String,
+    /// Patched with the server's values before every start.
+    #[serde(default)]
+    pub config_files: Vec<ConfigFile>,
+    /// Run on a network of their own with the server's container.
+    #[serde(default)]
+    pub sidecars: Vec<Sidecar>,
+    #[serde(default)]
+    pub hardening: Hardening,
+    /// Notice for users while the server's node is in maintenance.
+    #[serde(default)]
+    pub node_maintenance: Option<String>,
+    /// Suspended servers are kept stopped and can not be started by their owner.
+    #[serde(default)]
+    pub suspended: bool,
+    #[serde(default)]
+    pub suspended_reason: Option<String>,
+
+    pub stop_method: StopMethod,
+    /// Seconds to wait for the process to exit before it is killed.
+    pub stop_timeout: i32,
+    pub done_regex: Option<String>,
+
+    /// Bumped on every change, the node is in sync once it applied it.
+    #[serde(default)]
+    pub generation: i64,
+    #[serde(default)]
+    pub applied_generation: i64,
+    #[serde(default)]
+    pub sync_status: SyncStatus,
+    #[serde(default)]
+    pub sync_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    Synced,
+    #[default]
+    Pending,
+    /// The last attempt failed, it is retried with backoff.
+    Failed,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -67,4 +482,102 @@ pub struct ServerNodePort {
     pub id: i32,
     pub ip: String,
     pub port: i32,
+    #[serde(default)]
+    pub protocol: PortProtocol,
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Backup {
+    pub id: i32,
+    pub server_id: i32,
+    pub snapshot_id: String,
+    pub parent_snapshot_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub file_count: i64,
+    pub total_size: i64,
+    pub added_size: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleTask {
+    Power { signal: ServerSignal },
+    Command { command: String },
+    Backup { retention: Option<RetentionPolicy> },
+    Wait { seconds: u64 },
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Schedule {
+    pub id: i32,
+    pub server_id: i32,
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub tasks: Vec<ScheduleTask>,
+    pub only_when_online: bool,
+    pub enabled: bool,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[sqlx(type_name = "schedule_run_result", rename_all = "lowercase")]
+pub enum ScheduleRunResult {
+    Running,
+    Success,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ScheduleRun {
+    pub id: i32,
+    pub schedule_id: i32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result: ScheduleRunResult,
+    pub logs: String,
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[sqlx(type_name = "transfer_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TransferStatus {
+    Pending,
+    Transferring,
+    Completed,
+    /// The server was rolled back to its source node.
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ServerTransfer {
+    pub id: i32,
+    pub server_id: i32,
+    pub source_node_id: i32,
+    pub destination_node_id: i32,
+    pub status: TransferStatus,
+    /// Uncompressed bytes of the volume sent so far.
+    pub bytes_done: i64,
+    pub bytes_total: i64,
+    pub error: Option<String>,
+    /// Backups deleted by the transfer, their snapshots were kept on the source node.
+    pub removed_backups: i32,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ServerSuspension {
+    pub id: i32,
+    pub server_id: i32,
+    /// Whether the server was suspended or unsuspended.
+    pub suspended: bool,
+    pub reason: Option<String>,
+    /// Staff member who changed the suspension.
+    pub user_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
 }
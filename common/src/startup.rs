@@ -0,0 +1,318 @@
+//! Templates of startup commands and config file values, such as
+//! `java -Xmx{{SERVER_MEMORY}}M -jar {{SERVER_JARFILE}} --port {{SERVER_PORT}}`.
+//!
+//! A startup command is split into arguments like a shell would, honoring quotes
+//! and backslash escapes, before placeholders are filled in. A value is therefore
+//! always part of a single argument, however many spaces or quotes it contains.
+//! Placeholders may be written with spaces, as in `{{ SERVER_PORT }}`.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::orch_types::Server;
+
+/// Placeholders filled from the server itself rather than the pod's variables.
+pub const BUILTIN_VARIABLES: &[&str] = &["SERVER_ID", "SERVER_MEMORY", "SERVER_IP", "SERVER_PORT"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnclosedQuote,
+    UnclosedPlaceholder,
+    UnknownVariable(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnclosedQuote => write!(f, "unclosed quote"),
+            TemplateError::UnclosedPlaceholder => write!(f, "unclosed placeholder"),
+            TemplateError::UnknownVariable(name) => write!(f, "unknown variable {}", name),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A server's startup command as it is run.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct StartupPreview {
+    pub template: String,
+    /// The arguments the container is started with.
+    pub args: Vec<String>,
+    /// The arguments joined and quoted for display.
+    pub command: String,
+}
+
+/// Splits a template into arguments, leaving placeholders in place.
+fn split(template: &str) -> Result<Vec<String>, TemplateError> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err(TemplateError::UnclosedQuote),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => return Err(TemplateError::UnclosedQuote),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err(TemplateError::UnclosedQuote),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            }
+            // a placeholder is kept whole, spaces inside it included
+            '{' if chars.as_str().starts_with('{') => {
+                in_arg = true;
+                let rest = chars.as_str();
+                let end = rest.find("}}").ok_or(TemplateError::UnclosedPlaceholder)? + 2;
+                current.push('{');
+                current.push_str(&rest[..end]);
+                chars = rest[end..].chars();
+            }
+            c => {
+                in_arg = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Replaces the `{{NAME}}` placeholders of an argument.
+fn substitute(
+    arg: &str,
+    mut value: impl FnMut(&str) -> Option<String>,
+) -> Result<String, TemplateError> {
+    let mut rendered = String::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .ok_or(TemplateError::UnclosedPlaceholder)?;
+        let name = rest[start + 2..start + end].trim();
+        rendered.push_str(
+            &value(name).ok_or_else(|| TemplateError::UnknownVariable(name.to_string()))?,
+        );
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Checks that a template is well formed and only uses built-in variables and
+/// the given ones.
+pub fn validate(template: &str, variables: &[&str]) -> Result<(), TemplateError> {
+    for arg in split(template)? {
+        substitute(&arg, |name| {
+            (BUILTIN_VARIABLES.contains(&name) || variables.contains(&name)).then(String::new)
+        })?;
+    }
+    Ok(())
+}
+
+fn builtin_value(server: &Server, name: &str) -> Option<String> {
+    let value = match name {
+        "SERVER_ID" => server.id.to_string(),
+        // 0 tells the server there is no limit
+        "SERVER_MEMORY" => server.memory_limit.unwrap_or(0).to_string(),
+        "SERVER_IP" => server.primary_port.ip.clone(),
+        "SERVER_PORT" => server.primary_port.port.to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn value_of(server: &Server, name: &str) -> Option<String> {
+    builtin_value(server, name).or_else(|| {
+        server
+            .env_vars
+            .iter()
+            .find(|env_var| env_var.key == name)
+            .map(|env_var| env_var.value.clone())
+    })
+}
+
+/// The arguments a server is started with.
+pub fn render(server: &Server) -> Result<Vec<String>, TemplateError> {
+    render_command(&server.startup_command, server)
+}
+
+/// Fills in a command template of the server's pod, such as a sidecar's.
+pub fn render_command(template: &str, server: &Server) -> Result<Vec<String>, TemplateError> {
+    split(template)?
+        .iter()
+        .map(|arg| substitute(arg, |name| value_of(server, name)))
+        .collect()
+}
+
+/// Checks a single value template, such as the value of a config file setting.
+pub fn validate_value(template: &str, variables: &[&str]) -> Result<(), TemplateError> {
+    substitute(template, |name| {
+        (BUILTIN_VARIABLES.contains(&name) || variables.contains(&name)).then(String::new)
+    })?;
+    Ok(())
+}
+
+/// Fills in a single value template. Unlike a startup command it is not split.
+pub fn render_value(template: &str, server: &Server) -> Result<String, TemplateError> {
+    substitute(template, |name| value_of(server, name))
+}
+
+/// Joins arguments into a command line that splits back into the same arguments.
+pub fn join(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(template: &str) -> Vec<String> {
+        split(template).unwrap()
+    }
+
+    fn render_with(template: &str, values: &[(&str, &str)]) -> Result<Vec<String>, TemplateError> {
+        split(template)?
+            .iter()
+            .map(|arg| {
+                substitute(arg, |name| {
+                    values
+                        .iter()
+                        .find(|(key, _)| *key == name)
+                        .map(|(_, value)| value.to_string())
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(
+            args("  java  -jar\tserver.jar \n"),
+            ["java", "-jar", "server.jar"]
+        );
+        assert!(args("   ").is_empty());
+    }
+
+    #[test]
+    fn splits_quotes_and_escapes_like_a_shell() {
+        assert_eq!(
+            args(r#"sh -c 'echo "a  b"' "x \"y\" \z" a\ b '' 'it'\''s'"#),
+            [
+                "sh",
+                "-c",
+                r#"echo "a  b""#,
+                r#"x "y" \z"#,
+                "a b",
+                "",
+                "it's"
+            ]
+        );
+        assert_eq!(split("echo 'a"), Err(TemplateError::UnclosedQuote));
+        assert_eq!(split("echo \"a\\"), Err(TemplateError::UnclosedQuote));
+    }
+
+    #[test]
+    fn keeps_placeholders_whole() {
+        assert_eq!(
+            args("--port {{ SERVER_PORT }} -Xmx{{SERVER_MEMORY}}M"),
+            ["--port", "{{ SERVER_PORT }}", "-Xmx{{SERVER_MEMORY}}M"]
+        );
+        assert_eq!(args("'{{ A }}'b{{ B }}"), ["{{ A }}b{{ B }}"]);
+        assert_eq!(
+            split("--port {{ SERVER_PORT"),
+            Err(TemplateError::UnclosedPlaceholder)
+        );
+    }
+
+    #[test]
+    fn substitutes_placeholders_inside_arguments() {
+        let values = [("SERVER_PORT", "25565"), ("MOTD", "a 'b' c")];
+        assert_eq!(
+            render_with("run --port={{ SERVER_PORT }} {{MOTD}}", &values).unwrap(),
+            ["run", "--port=25565", "a 'b' c"]
+        );
+        assert_eq!(
+            render_with("run {{ OTHER }}", &values),
+            Err(TemplateError::UnknownVariable("OTHER".to_string()))
+        );
+        assert_eq!(
+            substitute("a{{B", |_| None),
+            Err(TemplateError::UnclosedPlaceholder)
+        );
+    }
+
+    #[test]
+    fn validates_variables() {
+        assert!(validate("java -Dport={{ SERVER_PORT }} {{ JAR }}", &["JAR"]).is_ok());
+        assert_eq!(
+            validate("java {{ JAR }}", &[]),
+            Err(TemplateError::UnknownVariable("JAR".to_string()))
+        );
+        assert!(validate_value("{{SERVER_ID}}-{{ NAME }}", &["NAME"]).is_ok());
+    }
+
+    #[test]
+    fn joined_arguments_split_back() {
+        let cases: &[&[&str]] = &[
+            &["java", "-jar", "server.jar"],
+            &["sh", "-c", "echo 'hi' && exit \"1\""],
+            &["", "a b", "it's", "back\\slash", "tab\there", "100%"],
+        ];
+        for case in cases {
+            let args: Vec<String> = case.iter().map(|arg| arg.to_string()).collect();
+            assert_eq!(split(&join(&args)).unwrap(), args);
+        }
+        assert_eq!(join(&["a".to_string(), "b c".to_string()]), "a 'b c'");
+    }
+}
@@ -0,0 +1,12 @@
+-- Backup
+CREATE TABLE backup (
+    id SERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES server(id) ON DELETE CASCADE,
+    snapshot_id VARCHAR(64) NOT NULL,
+    parent_snapshot_id VARCHAR(64),
+    created_at TIMESTAMPTZ NOT NULL,
+    file_count BIGINT NOT NULL,
+    total_size BIGINT NOT NULL,
+    added_size BIGINT NOT NULL,
+    UNIQUE (server_id, snapshot_id)
+);
@@ -0,0 +1,28 @@
+-- Schedule
+CREATE TABLE schedule (
+    id SERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES server(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    cron VARCHAR(255) NOT NULL,
+    timezone VARCHAR(64) NOT NULL,
+    tasks JSONB NOT NULL,
+    only_when_online BOOLEAN NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    next_run_at TIMESTAMPTZ,
+    last_run_at TIMESTAMPTZ,
+    running BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX schedule_next_run_at_idx ON schedule (next_run_at) WHERE enabled;
+
+-- ScheduleRun
+CREATE TYPE schedule_run_result AS ENUM ('running', 'success', 'failed', 'skipped');
+
+CREATE TABLE schedule_run (
+    id SERIAL PRIMARY KEY,
+    schedule_id INTEGER NOT NULL REFERENCES schedule(id) ON DELETE CASCADE,
+    started_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ,
+    result schedule_run_result NOT NULL,
+    logs TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Pod stop method
+ALTER TABLE pod ADD COLUMN stop_method JSONB NOT NULL DEFAULT '{"type": "docker"}';
+ALTER TABLE pod ADD COLUMN stop_timeout INTEGER NOT NULL DEFAULT 30;
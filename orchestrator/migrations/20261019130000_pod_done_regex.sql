@@ -0,0 +1,2 @@
+-- Pod startup done detection
+ALTER TABLE pod ADD COLUMN done_regex TEXT;
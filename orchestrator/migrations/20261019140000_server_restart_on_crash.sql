@@ -0,0 +1,2 @@
+-- Server crash restarts
+ALTER TABLE server ADD COLUMN restart_on_crash BOOLEAN NOT NULL DEFAULT TRUE;
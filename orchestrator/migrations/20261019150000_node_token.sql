@@ -0,0 +1,2 @@
+-- Node tokens, only the hash is stored
+ALTER TABLE node ADD COLUMN token_hash VARCHAR(64) UNIQUE;
@@ -0,0 +1,12 @@
+-- Desired state of servers and what their node has applied
+ALTER TABLE server ADD COLUMN generation BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE server ADD COLUMN applied_generation BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE server ADD COLUMN syncing BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE server ADD COLUMN sync_error TEXT;
+ALTER TABLE server ADD COLUMN sync_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE server ADD COLUMN next_sync_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+-- Deleted servers are kept until their node removed them
+ALTER TABLE server ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Existing servers were created on their node right away
+UPDATE server SET applied_generation = generation;
@@ -0,0 +1,57 @@
+-- Existing assignments may break the rules below, they are repaired first.
+-- Ports assigned to a server on another node are released.
+UPDATE node_port SET server_id = NULL, is_primary = FALSE
+    FROM server
+    WHERE node_port.server_id = server.id AND node_port.node_id <> server.node_id;
+DELETE FROM node_port WHERE port NOT BETWEEN 1 AND 65535;
+-- Of ports that exist twice, the assigned one is kept.
+DELETE FROM node_port WHERE id IN (
+    SELECT id FROM (
+        SELECT id, ROW_NUMBER() OVER (
+            PARTITION BY node_id, ip, port
+            ORDER BY server_id IS NULL, NOT is_primary, id
+        ) AS rank
+        FROM node_port
+    ) AS ranked
+    WHERE rank > 1
+);
+UPDATE node_port SET is_primary = FALSE WHERE server_id IS NULL AND is_primary;
+-- A server with several primary ports keeps the oldest, one with none gets one.
+UPDATE node_port SET is_primary = FALSE WHERE is_primary AND id NOT IN (
+    SELECT MIN(id) FROM node_port WHERE is_primary GROUP BY server_id
+);
+UPDATE node_port SET is_primary = TRUE WHERE id IN (
+    SELECT MIN(id) FROM node_port
+    WHERE server_id IS NOT NULL
+    GROUP BY server_id
+    HAVING NOT BOOL_OR(is_primary)
+);
+
+-- A port can only exist once per node
+ALTER TABLE node_port ADD CONSTRAINT node_port_unique UNIQUE (node_id, ip, port);
+ALTER TABLE node_port ADD CONSTRAINT node_port_range CHECK (port BETWEEN 1 AND 65535);
+
+-- Ports can only be assigned to servers on the same node
+ALTER TABLE server ADD CONSTRAINT server_id_node_id_key UNIQUE (id, node_id);
+ALTER TABLE node_port DROP CONSTRAINT node_port_server_id_fkey;
+ALTER TABLE node_port ADD CONSTRAINT node_port_server_node_fkey
+    FOREIGN KEY (server_id, node_id) REFERENCES server(id, node_id);
+
+-- A server has a single primary port, and free ports are never primary
+CREATE UNIQUE INDEX node_port_one_primary ON node_port (server_id) WHERE is_primary;
+ALTER TABLE node_port ADD CONSTRAINT node_port_primary_assigned
+    CHECK (server_id IS NOT NULL OR NOT is_primary);
+
+-- A port assigned to a server has to be released before another server gets it
+CREATE FUNCTION node_port_check_in_use() RETURNS TRIGGER AS $$
+BEGIN
+    IF OLD.server_id IS NOT NULL AND NEW.server_id IS NOT NULL AND OLD.server_id <> NEW.server_id THEN
+        RAISE EXCEPTION 'port % is already assigned to server %', OLD.id, OLD.server_id
+            USING ERRCODE = 'unique_violation', CONSTRAINT = 'node_port_in_use';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER node_port_in_use BEFORE UPDATE OF server_id ON node_port
+    FOR EACH ROW EXECUTE FUNCTION node_port_check_in_use();
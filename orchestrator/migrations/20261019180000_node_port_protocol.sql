@@ -0,0 +1,5 @@
+-- Port protocols and display addresses
+CREATE TYPE port_protocol AS ENUM ('tcp', 'udp', 'both');
+
+ALTER TABLE node_port ADD COLUMN protocol port_protocol NOT NULL DEFAULT 'tcp';
+ALTER TABLE node_port ADD COLUMN alias VARCHAR(255);
@@ -0,0 +1,9 @@
+-- Node heartbeats
+ALTER TABLE node ADD COLUMN last_seen_at TIMESTAMPTZ;
+ALTER TABLE node ADD COLUMN agent_version VARCHAR(64);
+ALTER TABLE node ADD COLUMN docker_version VARCHAR(64);
+ALTER TABLE node ADD COLUMN resources JSONB;
+
+-- Maintenance mode
+ALTER TABLE node ADD COLUMN maintenance BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE node ADD COLUMN maintenance_message TEXT;
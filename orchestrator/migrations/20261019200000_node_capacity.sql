@@ -0,0 +1,13 @@
+-- Node capacity, memory and disk in MiB and cpu in percent of a core.
+-- Resources without a total are not accounted for.
+ALTER TABLE node ADD COLUMN memory_total INTEGER;
+ALTER TABLE node ADD COLUMN cpu_total INTEGER;
+ALTER TABLE node ADD COLUMN disk_total INTEGER;
+ALTER TABLE node ADD COLUMN memory_overallocate INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE node ADD COLUMN cpu_overallocate INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE node ADD COLUMN disk_overallocate INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE node ADD CONSTRAINT node_capacity_total CHECK (memory_total > 0 AND cpu_total > 0 AND disk_total > 0);
+ALTER TABLE node ADD CONSTRAINT node_capacity_overallocate CHECK (memory_overallocate >= 0 AND cpu_overallocate >= 0 AND disk_overallocate >= 0);
+
+-- Location tags to filter placement by
+ALTER TABLE node ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
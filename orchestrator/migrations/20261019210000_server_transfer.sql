@@ -0,0 +1,23 @@
+-- ServerTransfer
+CREATE TYPE transfer_status AS ENUM ('pending', 'transferring', 'completed', 'failed');
+
+CREATE TABLE server_transfer (
+    id SERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES server(id) ON DELETE CASCADE,
+    source_node_id INTEGER NOT NULL REFERENCES node(id),
+    destination_node_id INTEGER NOT NULL REFERENCES node(id),
+    status transfer_status NOT NULL DEFAULT 'pending',
+    was_running BOOLEAN NOT NULL,
+    bytes_done BIGINT NOT NULL DEFAULT 0,
+    bytes_total BIGINT NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+-- A server is only transferred once at a time
+CREATE UNIQUE INDEX server_transfer_one_active ON server_transfer (server_id)
+    WHERE status IN ('pending', 'transferring');
+
+-- Ports on the destination are held for the server until the transfer ends
+ALTER TABLE node_port ADD COLUMN transfer_id INTEGER REFERENCES server_transfer(id) ON DELETE SET NULL;
@@ -0,0 +1,13 @@
+-- Server
+ALTER TABLE server ADD COLUMN suspended BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE server ADD COLUMN suspended_reason TEXT;
+
+-- ServerSuspension
+CREATE TABLE server_suspension (
+    id SERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES server(id) ON DELETE CASCADE,
+    suspended BOOLEAN NOT NULL,
+    reason TEXT,
+    user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
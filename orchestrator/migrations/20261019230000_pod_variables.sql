@@ -0,0 +1,19 @@
+-- Pod
+ALTER TABLE pod ADD COLUMN variables JSONB NOT NULL DEFAULT '[]';
+
+-- Existing env vars become variables owners can see and change, as before
+UPDATE pod SET variables = (
+    SELECT COALESCE(
+        jsonb_agg(jsonb_build_object(
+            'key', env_var.key,
+            'name', env_var.key,
+            'default', env_var.value,
+            'user_viewable', TRUE,
+            'user_editable', TRUE
+        )),
+        '[]'
+    )
+    FROM unnest(pod.env_vars) AS env_var
+);
+
+ALTER TABLE pod DROP COLUMN env_vars;
@@ -0,0 +1,3 @@
+-- Pod
+ALTER TABLE pod ADD COLUMN installer_script TEXT;
+ALTER TABLE pod ADD COLUMN installer_entrypoint VARCHAR(255) NOT NULL DEFAULT 'bash';
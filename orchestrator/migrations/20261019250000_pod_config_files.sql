@@ -0,0 +1,2 @@
+-- Pod
+ALTER TABLE pod ADD COLUMN config_files JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,14 @@
+-- Pod
+ALTER TABLE pod ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+
+-- PodRevision
+CREATE TABLE pod_revision (
+    pod_id INTEGER NOT NULL REFERENCES pod(id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    document JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (pod_id, revision)
+);
+
+-- Server
+ALTER TABLE server ADD COLUMN pod_revision INTEGER NOT NULL DEFAULT 1;
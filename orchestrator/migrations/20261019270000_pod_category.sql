@@ -0,0 +1,11 @@
+-- PodCategory
+CREATE TABLE pod_category (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    icon VARCHAR(255)
+);
+
+-- Pod
+ALTER TABLE pod ADD COLUMN category_id INTEGER REFERENCES pod_category(id) ON DELETE SET NULL;
+ALTER TABLE pod ADD COLUMN node_restrictions JSONB NOT NULL DEFAULT '{}';
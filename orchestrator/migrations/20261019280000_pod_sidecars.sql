@@ -0,0 +1,2 @@
+-- Pod
+ALTER TABLE pod ADD COLUMN sidecars JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+-- Pod
+ALTER TABLE pod ADD COLUMN hardening JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+-- Node tokens are derived from the orchestrator's agent key and this salt, so
+-- the orchestrator can authenticate against agents without storing the token.
+-- Tokens generated before have no salt and need to be generated again.
+ALTER TABLE node ADD COLUMN token_salt VARCHAR(64);
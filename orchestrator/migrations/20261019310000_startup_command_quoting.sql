@@ -0,0 +1,29 @@
+-- Startup commands used to be split on whitespace, taking quotes and backslashes
+-- literally. Commands containing them are rewritten with every argument single
+-- quoted so that they split into the same arguments as before.
+CREATE FUNCTION pg_temp.quote_legacy_command(command TEXT) RETURNS TEXT AS $$
+    SELECT CASE WHEN command ~ '[''"\\]' THEN
+        array_to_string(ARRAY(
+            SELECT '''' || replace(arg, '''', '''\''''') || ''''
+            FROM regexp_split_to_table(command, E'[ \t\n\r\f]+') WITH ORDINALITY AS t(arg, n)
+            WHERE arg <> ''
+            ORDER BY n
+        ), ' ')
+    ELSE command END
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Pod
+UPDATE pod SET startup_command = pg_temp.quote_legacy_command(startup_command)
+    WHERE startup_command ~ '[''"\\]';
+
+-- PodRevision
+UPDATE pod_revision SET document = jsonb_set(
+        document,
+        '{startup_command}',
+        to_jsonb(pg_temp.quote_legacy_command(document->>'startup_command'))
+    )
+    WHERE document->>'startup_command' ~ '[''"\\]';
+
+-- Server
+UPDATE server SET startup_command = pg_temp.quote_legacy_command(startup_command)
+    WHERE startup_command ~ '[''"\\]';
@@ -0,0 +1,10 @@
+-- Pods that existed before hardening keep running their containers as they
+-- did. New pods are still hardened unless they turn settings off.
+
+-- Pod
+UPDATE pod SET hardening = '{"drop_capabilities": false, "no_new_privileges": false, "non_root_user": false, "pid_limit": null, "read_only_root": false, "tmpfs": ["/tmp"]}'
+    WHERE hardening = '{}';
+
+-- PodRevision
+UPDATE pod_revision SET document = document || '{"hardening": {"drop_capabilities": false, "no_new_privileges": false, "non_root_user": false, "pid_limit": null, "read_only_root": false, "tmpfs": ["/tmp"]}}'
+    WHERE NOT document ? 'hardening';
@@ -0,0 +1,2 @@
+-- Backups stay in the source node's repository, so a transfer deletes them
+ALTER TABLE server_transfer ADD COLUMN removed_backups INTEGER NOT NULL DEFAULT 0;
@@ -17,6 +17,8 @@ pub mod auth;
 pub mod models;
 pub mod routes;
 pub mod services;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
 
 #[derive(Clone)]
@@ -54,6 +56,10 @@ async fn main() {
         .unwrap();
 
     let db = services::database::init_db().await;
+    services::agent::init();
+    services::scheduler::spawn(db.clone());
+    services::reconciler::spawn(db.clone());
+    services::transfer::spawn_recovery(db.clone());
 
     // Session layer.
     let session_store = MemoryStore::default();
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use common::{agent_types::Snapshot, orch_types::Backup};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+
+#[derive(sqlx::FromRow, Serialize, Deserialize, ToSchema)]
+pub struct BackupModel {
+    pub id: i32,
+    pub server_id: i32,
+    pub snapshot_id: String,
+    pub parent_snapshot_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub file_count: i64,
+    pub total_size: i64,
+    pub added_size: i64,
+}
+
+pub async fn get_backups_by_server_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+) -> Result<Vec<BackupModel>, sqlx::Error> {
+    let backups = sqlx::query_as::<_, BackupModel>(
+        "SELECT * FROM backup WHERE server_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(server_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(backups)
+}
+
+pub async fn get_backup_by_snapshot_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+    snapshot_id: &str,
+) -> Result<BackupModel, sqlx::Error> {
+    let backup = sqlx::query_as::<_, BackupModel>(
+        "SELECT * FROM backup WHERE server_id = $1 AND snapshot_id = $2",
+    )
+    .bind(server_id)
+    .bind(snapshot_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(backup)
+}
+
+pub async fn create_backup(
+    conn: &mut PgConnection,
+    snapshot: Snapshot,
+) -> Result<BackupModel, sqlx::Error> {
+    let backup = sqlx::query_as::<_, BackupModel>(
+        "INSERT INTO backup (server_id, snapshot_id, parent_snapshot_id, created_at, file_count, total_size, added_size) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+    )
+    .bind(snapshot.server_id)
+    .bind(snapshot.id)
+    .bind(snapshot.parent)
+    .bind(snapshot.created_at)
+    .bind(snapshot.file_count)
+    .bind(snapshot.total_size)
+    .bind(snapshot.added_size)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(backup)
+}
+
+pub async fn delete_backups(
+    conn: &mut PgConnection,
+    server_id: i32,
+    snapshot_ids: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM backup WHERE server_id = $1 AND snapshot_id = ANY($2)")
+        .bind(server_id)
+        .bind(snapshot_ids)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Deletes all backups of a server, returning how many there were.
+pub async fn delete_all_backups_by_server_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM backup WHERE server_id = $1")
+        .bind(server_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+impl From<BackupModel> for Backup {
+    fn from(backup: BackupModel) -> Self {
+        Backup {
+            id: backup.id,
+            server_id: backup.server_id,
+            snapshot_id: backup.snapshot_id,
+            parent_snapshot_id: backup.parent_snapshot_id,
+            created_at: backup.created_at,
+            file_count: backup.file_count,
+            total_size: backup.total_size,
+            added_size: backup.added_size,
+        }
+    }
+}
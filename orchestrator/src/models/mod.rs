@@ -1,5 +1,9 @@
+pub mod backup;
 pub mod node;
 pub mod node_port;
 pub mod pod;
+pub mod pod_category;
+pub mod schedule;
 pub mod server;
+pub mod transfer;
 pub mod user;
@@ -1,13 +1,33 @@
-use sqlx::PgConnection;
+use chrono::{DateTime, Utc};
+use common::agent_types::{Heartbeat, HostResources};
+use sqlx::{types::Json, PgConnection};
 
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-#[derive(sqlx::FromRow, Serialize, Deserialize, ToSchema)]
+#[derive(sqlx::FromRow, Clone)]
 pub struct NodeModel {
     pub id: i32,
     pub name: String,
     pub fqdn: String,
+    /// Gives the node's token together with the orchestrator's agent key.
+    pub token_salt: Option<String>,
+
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub agent_version: Option<String>,
+    pub docker_version: Option<String>,
+    pub resources: Option<Json<HostResources>>,
+
+    pub maintenance: bool,
+    pub maintenance_message: Option<String>,
+
+    pub memory_total: Option<i32>,
+    pub cpu_total: Option<i32>,
+    pub disk_total: Option<i32>,
+    pub memory_overallocate: i32,
+    pub cpu_overallocate: i32,
+    pub disk_overallocate: i32,
+    pub tags: Vec<String>,
 }
 
 pub async fn get_nodes(conn: &mut PgConnection) -> Result<Vec<NodeModel>, sqlx::Error> {
@@ -25,34 +45,182 @@ pub async fn get_node_by_id(conn: &mut PgConnection, id: i32) -> Result<NodeMode
     Ok(node)
 }
 
+/// Locks the node until the transaction ends, so concurrent placements do not
+/// overallocate it.
+pub async fn lock_node_by_id(conn: &mut PgConnection, id: i32) -> Result<NodeModel, sqlx::Error> {
+    let node = sqlx::query_as::<_, NodeModel>("SELECT * FROM node WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(node)
+}
+
+#[derive(sqlx::FromRow, Clone, Copy, Default)]
+pub struct NodeAllocation {
+    pub memory: i64,
+    pub cpu: i64,
+    pub disk: i64,
+}
+
+/// Resources allocated to the servers on a node, including the ones being
+/// transferred to it, whose capacity is held from the start of the transfer.
+pub async fn get_node_allocation(
+    conn: &mut PgConnection,
+    id: i32,
+) -> Result<NodeAllocation, sqlx::Error> {
+    let allocation = sqlx::query_as::<_, NodeAllocation>(
+        "SELECT COALESCE(SUM(memory_limit), 0) AS memory, COALESCE(SUM(cpu_limit), 0) AS cpu, COALESCE(SUM(disk_limit), 0) AS disk FROM server WHERE NOT deleted AND (node_id = $1 OR id IN (SELECT server_id FROM server_transfer WHERE destination_node_id = $1 AND status IN ('pending', 'transferring')))",
+    )
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(allocation)
+}
+
+pub async fn get_node_by_token_hash(
+    conn: &mut PgConnection,
+    token_hash: &str,
+) -> Result<NodeModel, sqlx::Error> {
+    let node = sqlx::query_as::<_, NodeModel>("SELECT * FROM node WHERE token_hash = $1")
+        .bind(token_hash)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(node)
+}
+
+pub async fn set_node_token(
+    conn: &mut PgConnection,
+    id: i32,
+    token_salt: &str,
+    token_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE node SET token_salt = $1, token_hash = $2 WHERE id = $3")
+        .bind(token_salt)
+        .bind(token_hash)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn record_heartbeat(
+    conn: &mut PgConnection,
+    id: i32,
+    heartbeat: Heartbeat,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE node SET last_seen_at = NOW(), agent_version = $1, docker_version = $2, resources = $3 WHERE id = $4",
+    )
+    .bind(heartbeat.agent_version)
+    .bind(heartbeat.docker_version)
+    .bind(Json(heartbeat.resources))
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SetMaintenance {
+    pub maintenance: bool,
+    /// Shown to owners of servers on the node.
+    pub message: Option<String>,
+}
+
+pub async fn set_maintenance(
+    conn: &mut PgConnection,
+    id: i32,
+    maintenance: SetMaintenance,
+) -> Result<NodeModel, sqlx::Error> {
+    let node = sqlx::query_as::<_, NodeModel>(
+        "UPDATE node SET maintenance = $1, maintenance_message = $2 WHERE id = $3 RETURNING *",
+    )
+    .bind(maintenance.maintenance)
+    .bind(maintenance.message)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(node)
+}
+
+/// Memory and disk are in MiB, cpu in percent of a core. Leave a total empty to
+/// not limit that resource.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SetNodeCapacity {
+    pub memory_total: Option<i32>,
+    pub cpu_total: Option<i32>,
+    pub disk_total: Option<i32>,
+    /// Percentage the totals may be exceeded by.
+    #[serde(default)]
+    pub memory_overallocate: i32,
+    #[serde(default)]
+    pub cpu_overallocate: i32,
+    #[serde(default)]
+    pub disk_overallocate: i32,
+}
+
+pub async fn set_node_capacity(
+    conn: &mut PgConnection,
+    id: i32,
+    capacity: SetNodeCapacity,
+) -> Result<NodeModel, sqlx::Error> {
+    let node = sqlx::query_as::<_, NodeModel>(
+        "UPDATE node SET memory_total = $1, cpu_total = $2, disk_total = $3, memory_overallocate = $4, cpu_overallocate = $5, disk_overallocate = $6 WHERE id = $7 RETURNING *",
+    )
+    .bind(capacity.memory_total)
+    .bind(capacity.cpu_total)
+    .bind(capacity.disk_total)
+    .bind(capacity.memory_overallocate)
+    .bind(capacity.cpu_overallocate)
+    .bind(capacity.disk_overallocate)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(node)
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct CreateNode {
     pub name: String,
     pub fqdn: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 pub async fn create_node(
     conn: &mut PgConnection,
     node: CreateNode,
 ) -> Result<NodeModel, sqlx::Error> {
-    let node =
-        sqlx::query_as::<_, NodeModel>("INSERT INTO node (name, fqdn) VALUES ($1, $2) RETURNING *")
-            .bind(node.name)
-            .bind(node.fqdn)
-            .fetch_one(&mut *conn)
-            .await?;
+    let node = sqlx::query_as::<_, NodeModel>(
+        "INSERT INTO node (name, fqdn, tags) VALUES ($1, $2, $3) RETURNING *",
+    )
+    .bind(node.name)
+    .bind(node.fqdn)
+    .bind(node.tags)
+    .fetch_one(&mut *conn)
+    .await?;
     Ok(node)
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UpdateNode {
+    pub id: i32,
+    pub name: String,
+    pub fqdn: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
 pub async fn update_node(
     conn: &mut PgConnection,
-    node: NodeModel,
+    node: UpdateNode,
 ) -> Result<NodeModel, sqlx::Error> {
     let node = sqlx::query_as::<_, NodeModel>(
-        "UPDATE node SET name = $1, fqdn = $2 WHERE id = $3 RETURNING *",
+        "UPDATE node SET name = $1, fqdn = $2, tags = $3 WHERE id = $4 RETURNING *",
     )
     .bind(node.name)
     .bind(node.fqdn)
+    .bind(node.tags)
     .bind(node.id)
     .fetch_one(&mut *conn)
     .await?;
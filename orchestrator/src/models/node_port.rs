@@ -1,4 +1,4 @@
-use common::orch_types::{NodePort, ServerNodePort};
+use common::orch_types::{NodePort, PortProtocol, ServerNodePort};
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use utoipa::ToSchema;
@@ -11,6 +11,8 @@ pub struct NodePortModel {
     pub is_primary: bool,
     pub ip: String,
     pub port: i32,
+    pub protocol: PortProtocol,
+    pub alias: Option<String>,
 }
 
 pub async fn get_node_ports_by_node_id(
@@ -65,6 +67,9 @@ pub async fn get_node_port_by_id(
 pub struct CreateNodePort {
     pub ip: String,
     pub port: i32,
+    #[serde(default)]
+    pub protocol: PortProtocol,
+    pub alias: Option<String>,
 }
 
 pub async fn create_node_port(
@@ -73,16 +78,51 @@ pub async fn create_node_port(
     node_port: CreateNodePort,
 ) -> Result<NodePortModel, sqlx::Error> {
     let node_port = sqlx::query_as::<_, NodePortModel>(
-        "INSERT INTO node_port (node_id, ip, port, is_primary) VALUES ($1, $2, $3, FALSE) RETURNING *",
+        "INSERT INTO node_port (node_id, ip, port, is_primary, protocol, alias) VALUES ($1, $2, $3, FALSE, $4, $5) RETURNING *",
     )
     .bind(node_id)
     .bind(node_port.ip)
     .bind(node_port.port)
+    .bind(node_port.protocol)
+    .bind(node_port.alias)
     .fetch_one(&mut *conn)
     .await?;
     Ok(node_port)
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateNodePortRange {
+    pub ip: String,
+    /// A single port or an inclusive range such as `25565-25600`.
+    pub ports: String,
+    #[serde(default)]
+    pub protocol: PortProtocol,
+    pub alias: Option<String>,
+}
+
+/// Creates every port of an inclusive range. Fails as a whole if any of them
+/// already exists.
+pub async fn create_node_port_range(
+    conn: &mut PgConnection,
+    node_id: i32,
+    range: CreateNodePortRange,
+    first: i32,
+    last: i32,
+) -> Result<Vec<NodePortModel>, sqlx::Error> {
+    let node_ports = sqlx::query_as::<_, NodePortModel>(
+        "INSERT INTO node_port (node_id, ip, port, is_primary, protocol, alias) SELECT $1, $2, port, FALSE, $3, $4 FROM generate_series($5::INTEGER, $6::INTEGER) AS port RETURNING *",
+    )
+    .bind(node_id)
+    .bind(range.ip)
+    .bind(range.protocol)
+    .bind(range.alias)
+    .bind(first)
+    .bind(last)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(node_ports)
+}
+
 pub async fn assign_node_port_to_server(
     conn: &mut PgConnection,
     node_port_id: i32,
@@ -100,6 +140,88 @@ pub async fn assign_node_port_to_server(
     Ok(node_port)
 }
 
+/// Assigns `count` free ports of a node to a server. Ports locked by a
+/// concurrent allocation are skipped, so fewer ports than requested may be
+/// returned if the node runs out.
+pub async fn allocate_node_ports(
+    conn: &mut PgConnection,
+    node_id: i32,
+    server_id: i32,
+    count: i64,
+    is_primary: bool,
+) -> Result<Vec<NodePortModel>, sqlx::Error> {
+    let node_ports = sqlx::query_as::<_, NodePortModel>(
+        "UPDATE node_port SET server_id = $1, is_primary = $2 WHERE id IN (SELECT id FROM node_port WHERE node_id = $3 AND server_id IS NULL AND transfer_id IS NULL ORDER BY ip, port LIMIT $4 FOR UPDATE SKIP LOCKED) RETURNING *",
+    )
+    .bind(server_id)
+    .bind(is_primary)
+    .bind(node_id)
+    .bind(count)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(node_ports)
+}
+
+/// Holds `count` free ports of a node for a server being transferred there.
+pub async fn reserve_node_ports(
+    conn: &mut PgConnection,
+    node_id: i32,
+    transfer_id: i32,
+    count: i64,
+) -> Result<Vec<NodePortModel>, sqlx::Error> {
+    let node_ports = sqlx::query_as::<_, NodePortModel>(
+        "UPDATE node_port SET transfer_id = $1 WHERE id IN (SELECT id FROM node_port WHERE node_id = $2 AND server_id IS NULL AND transfer_id IS NULL ORDER BY ip, port LIMIT $3 FOR UPDATE SKIP LOCKED) RETURNING *",
+    )
+    .bind(transfer_id)
+    .bind(node_id)
+    .bind(count)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(node_ports)
+}
+
+pub async fn get_node_ports_by_transfer_id(
+    conn: &mut PgConnection,
+    transfer_id: i32,
+) -> Result<Vec<NodePortModel>, sqlx::Error> {
+    let node_ports = sqlx::query_as::<_, NodePortModel>(
+        "SELECT * FROM node_port WHERE transfer_id = $1 ORDER BY ip, port",
+    )
+    .bind(transfer_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(node_ports)
+}
+
+/// Hands the ports held by a transfer to its server, the first one as primary.
+pub async fn assign_transfer_node_ports(
+    conn: &mut PgConnection,
+    transfer_id: i32,
+    server_id: i32,
+    primary_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE node_port SET server_id = $1, is_primary = (id = $2), transfer_id = NULL WHERE transfer_id = $3",
+    )
+    .bind(server_id)
+    .bind(primary_id)
+    .bind(transfer_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn release_transfer_node_ports(
+    conn: &mut PgConnection,
+    transfer_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE node_port SET transfer_id = NULL WHERE transfer_id = $1")
+        .bind(transfer_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
 pub async fn unassign_node_port_from_server(
     conn: &mut PgConnection,
     node_port_id: i32,
@@ -140,6 +262,8 @@ impl From<NodePortModel> for NodePort {
             is_primary: node_port.is_primary,
             ip: node_port.ip,
             port: node_port.port,
+            protocol: node_port.protocol,
+            alias: node_port.alias,
         }
     }
 }
@@ -150,6 +274,8 @@ impl From<NodePortModel> for ServerNodePort {
             id: node_port.id,
             ip: node_port.ip,
             port: node_port.port,
+            protocol: node_port.protocol,
+            alias: node_port.alias,
         }
     }
 }
@@ -1,5 +1,10 @@
-use common::orch_types::{EnvVar, Image, Pod};
+use chrono::{DateTime, Utc};
+use common::orch_types::{
+    ConfigFile, Hardening, Image, NodeRestrictions, Pod, PodDocument, PodRevision, PodVariable,
+    Sidecar, StopMethod, POD_FORMAT_VERSION,
+};
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use utoipa::ToSchema;
 
 #[derive(sqlx::FromRow, Serialize, Deserialize, ToSchema)]
@@ -9,7 +14,31 @@ pub struct PodModel {
     pub images: Vec<Image>,
     pub startup_command: String,
     pub installer_image: String,
-    pub env_vars: Vec<EnvVar>,
+    pub installer_script: Option<String>,
+    #[serde(default = "default_installer_entrypoint")]
+    pub installer_entrypoint: String,
+    #[sqlx(json)]
+    pub variables: Vec<PodVariable>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub config_files: Vec<ConfigFile>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub sidecars: Vec<Sidecar>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub hardening: Hardening,
+    #[sqlx(json)]
+    pub stop_method: StopMethod,
+    pub stop_timeout: i32,
+    pub done_regex: Option<String>,
+    /// Set by the panel, bumped on every update.
+    #[serde(default)]
+    pub revision: i32,
+    pub category_id: Option<i32>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub node_restrictions: NodeRestrictions,
 }
 
 pub async fn get_pods(conn: &mut sqlx::PgConnection) -> Result<Vec<PodModel>, sqlx::Error> {
@@ -49,7 +78,35 @@ pub struct CreatePod {
     pub images: Vec<Image>,
     pub startup_command: String,
     pub installer_image: String,
-    pub env_vars: Vec<EnvVar>,
+    #[serde(default)]
+    pub installer_script: Option<String>,
+    #[serde(default = "default_installer_entrypoint")]
+    pub installer_entrypoint: String,
+    #[serde(default)]
+    pub variables: Vec<PodVariable>,
+    #[serde(default)]
+    pub config_files: Vec<ConfigFile>,
+    #[serde(default)]
+    pub sidecars: Vec<Sidecar>,
+    #[serde(default)]
+    pub hardening: Hardening,
+    #[serde(default)]
+    pub stop_method: StopMethod,
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: i32,
+    pub done_regex: Option<String>,
+    #[serde(default)]
+    pub category_id: Option<i32>,
+    #[serde(default)]
+    pub node_restrictions: NodeRestrictions,
+}
+
+fn default_stop_timeout() -> i32 {
+    30
+}
+
+fn default_installer_entrypoint() -> String {
+    "bash".to_string()
 }
 
 pub async fn create_pod(
@@ -57,36 +114,131 @@ pub async fn create_pod(
     pod: CreatePod,
 ) -> Result<PodModel, sqlx::Error> {
     let pod = sqlx::query_as::<_, PodModel>(
-        "INSERT INTO pod (name, images, startup_command, installer_image, env_vars) VALUES ($1, $2, $3, $4, $5) RETURNING *"
+        "INSERT INTO pod (name, images, startup_command, installer_image, installer_script, installer_entrypoint, variables, config_files, sidecars, hardening, stop_method, stop_timeout, done_regex, category_id, node_restrictions) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) RETURNING *"
     )
     .bind(pod.name)
     .bind(pod.images)
     .bind(pod.startup_command)
     .bind(pod.installer_image)
-    .bind(pod.env_vars)
+    .bind(pod.installer_script)
+    .bind(pod.installer_entrypoint)
+    .bind(Json(pod.variables))
+    .bind(Json(pod.config_files))
+    .bind(Json(pod.sidecars))
+    .bind(Json(pod.hardening))
+    .bind(Json(pod.stop_method))
+    .bind(pod.stop_timeout)
+    .bind(pod.done_regex)
+    .bind(pod.category_id)
+    .bind(Json(pod.node_restrictions))
     .fetch_one(&mut *conn)
     .await?;
+    record_revision(conn, pod.id).await?;
     Ok(pod)
 }
 
+/// Updates a pod as a new revision. Servers keep the revision they were built
+/// from until it is rolled out to them.
 pub async fn update_pod(
     conn: &mut sqlx::PgConnection,
     pod: PodModel,
 ) -> Result<PodModel, sqlx::Error> {
+    // pods from before revisions were recorded have none of their current one
+    record_revision(conn, pod.id).await?;
     let pod = sqlx::query_as::<_, PodModel>(
-        "UPDATE pod SET name = $1, images = $2, startup_command = $3, installer_image = $4, env_vars = $5 WHERE id = $6 RETURNING *"
+        "UPDATE pod SET name = $1, images = $2, startup_command = $3, installer_image = $4, installer_script = $5, installer_entrypoint = $6, variables = $7, config_files = $8, sidecars = $9, hardening = $10, stop_method = $11, stop_timeout = $12, done_regex = $13, category_id = $14, node_restrictions = $15, revision = revision + 1 WHERE id = $16 RETURNING *"
     )
     .bind(pod.name)
     .bind(pod.images)
     .bind(pod.startup_command)
     .bind(pod.installer_image)
-    .bind(pod.env_vars)
+    .bind(pod.installer_script)
+    .bind(pod.installer_entrypoint)
+    .bind(Json(pod.variables))
+    .bind(Json(pod.config_files))
+    .bind(Json(pod.sidecars))
+    .bind(Json(pod.hardening))
+    .bind(Json(pod.stop_method))
+    .bind(pod.stop_timeout)
+    .bind(pod.done_regex)
+    .bind(pod.category_id)
+    .bind(Json(pod.node_restrictions))
     .bind(pod.id)
     .fetch_one(&mut *conn)
     .await?;
+    record_revision(conn, pod.id).await?;
     Ok(pod)
 }
 
+#[derive(sqlx::FromRow)]
+pub struct PodRevisionModel {
+    pub pod_id: i32,
+    pub revision: i32,
+    #[sqlx(json)]
+    pub document: PodDocument,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Keeps the current revision of a pod, unless it is kept already.
+async fn record_revision(conn: &mut sqlx::PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    let pod = get_pod_by_id(conn, id).await?;
+    let revision = pod.revision;
+    sqlx::query(
+        "INSERT INTO pod_revision (pod_id, revision, document) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(id)
+    .bind(revision)
+    .bind(Json(PodDocument::from(pod)))
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_pod_revisions(
+    conn: &mut sqlx::PgConnection,
+    pod_id: i32,
+) -> Result<Vec<PodRevisionModel>, sqlx::Error> {
+    let revisions = sqlx::query_as::<_, PodRevisionModel>(
+        "SELECT * FROM pod_revision WHERE pod_id = $1 ORDER BY revision DESC",
+    )
+    .bind(pod_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(revisions)
+}
+
+pub async fn get_pod_revision(
+    conn: &mut sqlx::PgConnection,
+    pod_id: i32,
+    revision: i32,
+) -> Result<Option<PodRevisionModel>, sqlx::Error> {
+    let revision = sqlx::query_as::<_, PodRevisionModel>(
+        "SELECT * FROM pod_revision WHERE pod_id = $1 AND revision = $2",
+    )
+    .bind(pod_id)
+    .bind(revision)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(revision)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Rollout {
+    /// Servers to roll the pod's current revision out to. All servers of the
+    /// pod if left out.
+    #[serde(default)]
+    pub server_ids: Option<Vec<i32>>,
+}
+
+/// Servers using the pod, including deleted ones that are not removed yet.
+pub async fn count_pod_servers(conn: &mut sqlx::PgConnection, id: i32) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar("SELECT COUNT(*) FROM server WHERE pod_id = $1")
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(count)
+}
+
 pub async fn delete_pod(conn: &mut sqlx::PgConnection, id: i32) -> Result<(), sqlx::Error> {
     sqlx::query("DELETE FROM pod WHERE id = $1")
         .bind(id)
@@ -103,7 +255,74 @@ impl From<PodModel> for Pod {
             images: pod.images,
             startup_command: pod.startup_command,
             installer_image: pod.installer_image,
-            env_vars: pod.env_vars,
+            installer_script: pod.installer_script,
+            installer_entrypoint: pod.installer_entrypoint,
+            variables: pod.variables,
+            config_files: pod.config_files,
+            sidecars: pod.sidecars,
+            hardening: pod.hardening,
+            stop_method: pod.stop_method,
+            stop_timeout: pod.stop_timeout,
+            done_regex: pod.done_regex,
+            revision: pod.revision,
+            category_id: pod.category_id,
+            node_restrictions: pod.node_restrictions,
+        }
+    }
+}
+
+impl From<PodRevisionModel> for PodRevision {
+    fn from(revision: PodRevisionModel) -> Self {
+        PodRevision {
+            pod_id: revision.pod_id,
+            revision: revision.revision,
+            document: revision.document,
+            created_at: revision.created_at,
+        }
+    }
+}
+
+impl From<PodModel> for PodDocument {
+    fn from(pod: PodModel) -> Self {
+        PodDocument {
+            version: POD_FORMAT_VERSION,
+            name: pod.name,
+            images: pod.images,
+            startup_command: pod.startup_command,
+            installer_image: pod.installer_image,
+            installer_script: pod.installer_script,
+            installer_entrypoint: Some(pod.installer_entrypoint),
+            variables: pod.variables,
+            config_files: pod.config_files,
+            sidecars: pod.sidecars,
+            hardening: pod.hardening,
+            stop_method: pod.stop_method,
+            stop_timeout: Some(pod.stop_timeout),
+            done_regex: pod.done_regex,
+        }
+    }
+}
+
+impl From<PodDocument> for CreatePod {
+    fn from(document: PodDocument) -> Self {
+        CreatePod {
+            name: document.name,
+            images: document.images,
+            startup_command: document.startup_command,
+            installer_image: document.installer_image,
+            installer_script: document.installer_script,
+            installer_entrypoint: document
+                .installer_entrypoint
+                .unwrap_or_else(default_installer_entrypoint),
+            variables: document.variables,
+            config_files: document.config_files,
+            sidecars: document.sidecars,
+            hardening: document.hardening,
+            stop_method: document.stop_method,
+            stop_timeout: document.stop_timeout.unwrap_or_else(default_stop_timeout),
+            done_regex: document.done_regex,
+            category_id: None,
+            node_restrictions: NodeRestrictions::default(),
         }
     }
 }
@@ -0,0 +1,60 @@
+use common::orch_types::PodCategory;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+
+pub async fn get_pod_categories(conn: &mut PgConnection) -> Result<Vec<PodCategory>, sqlx::Error> {
+    let categories = sqlx::query_as::<_, PodCategory>("SELECT * FROM pod_category ORDER BY name")
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(categories)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreatePodCategory {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+pub async fn create_pod_category(
+    conn: &mut PgConnection,
+    category: CreatePodCategory,
+) -> Result<PodCategory, sqlx::Error> {
+    let category = sqlx::query_as::<_, PodCategory>(
+        "INSERT INTO pod_category (name, description, icon) VALUES ($1, $2, $3) RETURNING *",
+    )
+    .bind(category.name)
+    .bind(category.description)
+    .bind(category.icon)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(category)
+}
+
+pub async fn update_pod_category(
+    conn: &mut PgConnection,
+    category: PodCategory,
+) -> Result<PodCategory, sqlx::Error> {
+    let category = sqlx::query_as::<_, PodCategory>(
+        "UPDATE pod_category SET name = $1, description = $2, icon = $3 WHERE id = $4 RETURNING *",
+    )
+    .bind(category.name)
+    .bind(category.description)
+    .bind(category.icon)
+    .bind(category.id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(category)
+}
+
+/// Deletes a category, its pods are left without one.
+pub async fn delete_pod_category(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM pod_category WHERE id = $1")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
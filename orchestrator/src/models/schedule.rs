@@ -0,0 +1,267 @@
+use chrono::{DateTime, Utc};
+use common::orch_types::{Schedule, ScheduleRun, ScheduleRunResult, ScheduleTask};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, PgConnection};
+use utoipa::ToSchema;
+
+#[derive(sqlx::FromRow)]
+pub struct ScheduleModel {
+    pub id: i32,
+    pub server_id: i32,
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub tasks: Json<Vec<ScheduleTask>>,
+    pub only_when_online: bool,
+    pub enabled: bool,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub running: bool,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ScheduleRunModel {
+    pub id: i32,
+    pub schedule_id: i32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub result: ScheduleRunResult,
+    pub logs: String,
+}
+
+pub async fn get_schedules_by_server_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+) -> Result<Vec<ScheduleModel>, sqlx::Error> {
+    let schedules = sqlx::query_as::<_, ScheduleModel>(
+        "SELECT * FROM schedule WHERE server_id = $1 ORDER BY id",
+    )
+    .bind(server_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(schedules)
+}
+
+pub async fn get_schedule_by_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+    id: i32,
+) -> Result<ScheduleModel, sqlx::Error> {
+    let schedule = sqlx::query_as::<_, ScheduleModel>(
+        "SELECT * FROM schedule WHERE id = $1 AND server_id = $2",
+    )
+    .bind(id)
+    .bind(server_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(schedule)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateSchedule {
+    pub name: String,
+    pub cron: String,
+    pub timezone: String,
+    pub tasks: Vec<ScheduleTask>,
+    pub only_when_online: bool,
+    pub enabled: bool,
+}
+
+pub async fn create_schedule(
+    conn: &mut PgConnection,
+    server_id: i32,
+    schedule: CreateSchedule,
+    next_run_at: Option<DateTime<Utc>>,
+) -> Result<ScheduleModel, sqlx::Error> {
+    let schedule = sqlx::query_as::<_, ScheduleModel>(
+        "INSERT INTO schedule (server_id, name, cron, timezone, tasks, only_when_online, enabled, next_run_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *",
+    )
+    .bind(server_id)
+    .bind(schedule.name)
+    .bind(schedule.cron)
+    .bind(schedule.timezone)
+    .bind(Json(schedule.tasks))
+    .bind(schedule.only_when_online)
+    .bind(schedule.enabled)
+    .bind(next_run_at)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(schedule)
+}
+
+pub async fn update_schedule(
+    conn: &mut PgConnection,
+    server_id: i32,
+    id: i32,
+    schedule: CreateSchedule,
+    next_run_at: Option<DateTime<Utc>>,
+) -> Result<ScheduleModel, sqlx::Error> {
+    let schedule = sqlx::query_as::<_, ScheduleModel>(
+        "UPDATE schedule SET name = $1, cron = $2, timezone = $3, tasks = $4, only_when_online = $5, enabled = $6, next_run_at = $7 WHERE id = $8 AND server_id = $9 RETURNING *",
+    )
+    .bind(schedule.name)
+    .bind(schedule.cron)
+    .bind(schedule.timezone)
+    .bind(Json(schedule.tasks))
+    .bind(schedule.only_when_online)
+    .bind(schedule.enabled)
+    .bind(next_run_at)
+    .bind(id)
+    .bind(server_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(schedule)
+}
+
+pub async fn delete_schedule(
+    conn: &mut PgConnection,
+    server_id: i32,
+    id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM schedule WHERE id = $1 AND server_id = $2")
+        .bind(id)
+        .bind(server_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Marks every enabled schedule that is due as running and returns it. A
+/// schedule that is already running is never claimed twice.
+pub async fn claim_due_schedules(
+    conn: &mut PgConnection,
+    now: DateTime<Utc>,
+) -> Result<Vec<ScheduleModel>, sqlx::Error> {
+    let schedules = sqlx::query_as::<_, ScheduleModel>(
+        "UPDATE schedule SET running = TRUE WHERE enabled AND NOT running AND next_run_at <= $1 RETURNING *",
+    )
+    .bind(now)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(schedules)
+}
+
+pub async fn claim_schedule(
+    conn: &mut PgConnection,
+    server_id: i32,
+    id: i32,
+) -> Result<Option<ScheduleModel>, sqlx::Error> {
+    let schedule = sqlx::query_as::<_, ScheduleModel>(
+        "UPDATE schedule SET running = TRUE WHERE id = $1 AND server_id = $2 AND NOT running RETURNING *",
+    )
+    .bind(id)
+    .bind(server_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(schedule)
+}
+
+/// Releases a schedule after a run. `next_run_at` was computed from the cron
+/// expression and timezone the run was claimed with, if the schedule was edited
+/// or disabled since, the next run set by the edit is kept.
+pub async fn release_schedule(
+    conn: &mut PgConnection,
+    schedule: &ScheduleModel,
+    last_run_at: DateTime<Utc>,
+    next_run_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE schedule SET running = FALSE, last_run_at = $1, next_run_at = CASE WHEN enabled AND cron = $2 AND timezone = $3 THEN $4 ELSE next_run_at END WHERE id = $5",
+    )
+    .bind(last_run_at)
+    .bind(&schedule.cron)
+    .bind(&schedule.timezone)
+    .bind(next_run_at)
+    .bind(schedule.id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Clears running flags left behind by an orchestrator that stopped mid run.
+pub async fn release_all_schedules(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE schedule SET running = FALSE WHERE running")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        "UPDATE schedule_run SET result = 'failed', finished_at = NOW(), logs = logs || 'interrupted by orchestrator restart' WHERE result = 'running'",
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_schedule_runs(
+    conn: &mut PgConnection,
+    schedule_id: i32,
+) -> Result<Vec<ScheduleRunModel>, sqlx::Error> {
+    let runs = sqlx::query_as::<_, ScheduleRunModel>(
+        "SELECT * FROM schedule_run WHERE schedule_id = $1 ORDER BY started_at DESC LIMIT 50",
+    )
+    .bind(schedule_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(runs)
+}
+
+pub async fn create_schedule_run(
+    conn: &mut PgConnection,
+    schedule_id: i32,
+    started_at: DateTime<Utc>,
+) -> Result<ScheduleRunModel, sqlx::Error> {
+    let run = sqlx::query_as::<_, ScheduleRunModel>(
+        "INSERT INTO schedule_run (schedule_id, started_at, result, logs) VALUES ($1, $2, 'running', '') RETURNING *",
+    )
+    .bind(schedule_id)
+    .bind(started_at)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(run)
+}
+
+pub async fn finish_schedule_run(
+    conn: &mut PgConnection,
+    id: i32,
+    result: ScheduleRunResult,
+    logs: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE schedule_run SET finished_at = NOW(), result = $1, logs = $2 WHERE id = $3",
+    )
+    .bind(result)
+    .bind(logs)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+impl From<ScheduleModel> for Schedule {
+    fn from(schedule: ScheduleModel) -> Self {
+        Schedule {
+            id: schedule.id,
+            server_id: schedule.server_id,
+            name: schedule.name,
+            cron: schedule.cron,
+            timezone: schedule.timezone,
+            tasks: schedule.tasks.0,
+            only_when_online: schedule.only_when_online,
+            enabled: schedule.enabled,
+            next_run_at: schedule.next_run_at,
+            last_run_at: schedule.last_run_at,
+        }
+    }
+}
+
+impl From<ScheduleRunModel> for ScheduleRun {
+    fn from(run: ScheduleRunModel) -> Self {
+        ScheduleRun {
+            id: run.id,
+            schedule_id: run.schedule_id,
+            started_at: run.started_at,
+            finished_at: run.finished_at,
+            result: run.result,
+            logs: run.logs,
+        }
+    }
+}
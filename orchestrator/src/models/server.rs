@@ -1,4 +1,5 @@
-use common::orch_types::EnvVar;
+use chrono::{DateTime, Utc};
+use common::orch_types::{EnvVar, PlacementStrategy, ServerSuspension};
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use utoipa::ToSchema;
@@ -17,13 +18,31 @@ pub struct ServerModel {
     pub disk_limit: Option<i32>,
 
     pub pod_id: i32,
+    pub pod_revision: i32,
     pub image: String,
     pub startup_command: String,
     pub env_vars: Vec<EnvVar>,
+
+    pub restart_on_crash: bool,
+
+    pub suspended: bool,
+    pub suspended_reason: Option<String>,
+
+    pub generation: i64,
+    pub applied_generation: i64,
+    pub syncing: bool,
+    pub sync_error: Option<String>,
+    pub sync_attempts: i32,
+    pub next_sync_at: DateTime<Utc>,
+    pub deleted: bool,
+}
+
+fn default_restart_on_crash() -> bool {
+    true
 }
 
 pub async fn get_servers(conn: &mut PgConnection) -> Result<Vec<ServerModel>, sqlx::Error> {
-    let servers = sqlx::query_as::<_, ServerModel>("SELECT * FROM server")
+    let servers = sqlx::query_as::<_, ServerModel>("SELECT * FROM server WHERE NOT deleted")
         .fetch_all(&mut *conn)
         .await?;
     Ok(servers)
@@ -33,10 +52,12 @@ pub async fn get_servers_by_user(
     conn: &mut PgConnection,
     user_id: i32,
 ) -> Result<Vec<ServerModel>, sqlx::Error> {
-    let servers = sqlx::query_as::<_, ServerModel>("SELECT * FROM server WHERE owner_id = $1")
-        .bind(user_id)
-        .fetch_all(&mut *conn)
-        .await?;
+    let servers = sqlx::query_as::<_, ServerModel>(
+        "SELECT * FROM server WHERE owner_id = $1 AND NOT deleted",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?;
     Ok(servers)
 }
 
@@ -44,50 +65,79 @@ pub async fn get_server_by_id(
     conn: &mut PgConnection,
     id: i32,
 ) -> Result<ServerModel, sqlx::Error> {
-    let server = sqlx::query_as::<_, ServerModel>("SELECT * FROM server WHERE id = $1")
-        .bind(id)
-        .fetch_one(conn)
-        .await?;
+    let server =
+        sqlx::query_as::<_, ServerModel>("SELECT * FROM server WHERE id = $1 AND NOT deleted")
+            .bind(id)
+            .fetch_one(conn)
+            .await?;
     Ok(server)
 }
 
+pub async fn get_servers_by_pod_id(
+    conn: &mut PgConnection,
+    pod_id: i32,
+) -> Result<Vec<ServerModel>, sqlx::Error> {
+    let servers = sqlx::query_as::<_, ServerModel>(
+        "SELECT * FROM server WHERE pod_id = $1 AND NOT deleted ORDER BY id",
+    )
+    .bind(pod_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(servers)
+}
+
 pub async fn get_servers_by_node_id(
     conn: &mut PgConnection,
     node_id: i32,
 ) -> Result<Vec<ServerModel>, sqlx::Error> {
-    let server = sqlx::query_as::<_, ServerModel>("SELECT * FROM server WHERE node_id = $1")
-        .bind(node_id)
-        .fetch_all(&mut *conn)
-        .await?;
+    let server =
+        sqlx::query_as::<_, ServerModel>("SELECT * FROM server WHERE node_id = $1 AND NOT deleted")
+            .bind(node_id)
+            .fetch_all(&mut *conn)
+            .await?;
     Ok(server)
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct CreateServer {
     pub name: String,
-    pub node_id: i32,
+    /// Leave empty to place the server on a node by `placement`.
+    #[serde(default)]
+    pub node_id: Option<i32>,
+    #[serde(default)]
+    pub placement: PlacementStrategy,
+    /// Only nodes with all of these tags are considered for placement.
+    #[serde(default)]
+    pub location_tags: Vec<String>,
     pub owner_id: i32,
     pub cpu_limit: Option<i32>,
     pub memory_limit: Option<i32>,
     pub disk_limit: Option<i32>,
 
-    pub port: i32,
+    /// Leave empty to assign any free port of the node.
+    #[serde(default)]
+    pub port: Option<i32>,
+    #[serde(default)]
     pub additional_ports: Vec<i32>,
+    /// Number of free ports to assign in addition to `additional_ports`.
+    #[serde(default)]
+    pub additional_port_count: u32,
 
     pub pod_id: i32,
     pub image: String,
     pub startup_command: String,
     pub env_vars: Vec<EnvVar>,
+
+    #[serde(default = "default_restart_on_crash")]
+    pub restart_on_crash: bool,
 }
 
 pub async fn create_server(
     conn: &mut PgConnection,
     cserver: CreateServer,
 ) -> Result<ServerModel, sqlx::Error> {
-    // TODO verify image and env_vars
-
     let server = sqlx::query_as::<_, ServerModel>(
-        "INSERT INTO server (name, node_id, owner_id,cpu_limit, memory_limit, disk_limit, pod_id, image, startup_command, env_vars) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *",
+        "INSERT INTO server (name, node_id, owner_id,cpu_limit, memory_limit, disk_limit, pod_id, pod_revision, image, startup_command, env_vars, restart_on_crash) VALUES ($1, $2, $3, $4, $5, $6, $7, (SELECT revision FROM pod WHERE id = $7), $8, $9, $10, $11) RETURNING *",
     )
     .bind(cserver.name)
     .bind(cserver.node_id)
@@ -99,13 +149,14 @@ pub async fn create_server(
     .bind(cserver.image)
     .bind(cserver.startup_command)
     .bind(cserver.env_vars)
+    .bind(cserver.restart_on_crash)
     .fetch_one(&mut *conn)
     .await?;
 
-    // TODO verify that the port is not already in use AND that port belongs to the node
-
-    assign_node_port_to_server(conn, cserver.port, server.id, true).await?;
-
+    // ports in use or of another node are rejected by constraints
+    if let Some(port) = cserver.port {
+        assign_node_port_to_server(conn, port, server.id, true).await?;
+    }
     for port in cserver.additional_ports {
         assign_node_port_to_server(conn, port, server.id, false).await?;
     }
@@ -122,28 +173,90 @@ pub struct UpdateServer {
     pub image: String,
     pub startup_command: String,
     pub env_vars: Vec<EnvVar>,
+
+    #[serde(default = "default_restart_on_crash")]
+    pub restart_on_crash: bool,
 }
 
 pub async fn update_server(
     conn: &mut PgConnection,
     userver: UpdateServer,
 ) -> Result<ServerModel, sqlx::Error> {
-    // TODO verify image and env_vars
-
     let server = sqlx::query_as::<_, ServerModel>(
-        "UPDATE server SET name = $1, pod_id = $2, image = $3, startup_command = $4, env_vars = $5 WHERE id = $6 RETURNING *",
+        "UPDATE server SET name = $1, pod_id = $2, image = $3, startup_command = $4, env_vars = $5, restart_on_crash = $6, generation = generation + 1, sync_attempts = 0, next_sync_at = NOW() WHERE id = $7 AND NOT deleted RETURNING *",
     )
     .bind(userver.name)
     .bind(userver.pod_id)
     .bind(userver.image)
     .bind(userver.startup_command)
     .bind(userver.env_vars)
+    .bind(userver.restart_on_crash)
     .bind(userver.id)
     .fetch_one(&mut *conn)
     .await?;
     Ok(server)
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChangePod {
+    pub pod_id: i32,
+    /// One of the new pod's images.
+    pub image: String,
+    /// Deletes the server's files before the new pod's installer runs.
+    #[serde(default)]
+    pub wipe: bool,
+}
+
+pub async fn change_server_pod(
+    conn: &mut PgConnection,
+    id: i32,
+    pod_id: i32,
+    image: String,
+    startup_command: String,
+    env_vars: Vec<EnvVar>,
+) -> Result<ServerModel, sqlx::Error> {
+    let server = sqlx::query_as::<_, ServerModel>(
+        "UPDATE server SET pod_id = $1, pod_revision = (SELECT revision FROM pod WHERE id = $1), image = $2, startup_command = $3, env_vars = $4, generation = generation + 1, sync_attempts = 0, next_sync_at = NOW() WHERE id = $5 AND NOT deleted RETURNING *",
+    )
+    .bind(pod_id)
+    .bind(image)
+    .bind(startup_command)
+    .bind(env_vars)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(server)
+}
+
+/// Moves a server to a revision of its pod.
+pub async fn roll_out_server_pod(
+    conn: &mut PgConnection,
+    id: i32,
+    pod_revision: i32,
+    image: String,
+    startup_command: String,
+    env_vars: Vec<EnvVar>,
+) -> Result<ServerModel, sqlx::Error> {
+    let server = sqlx::query_as::<_, ServerModel>(
+        "UPDATE server SET pod_revision = $1, image = $2, startup_command = $3, env_vars = $4, generation = generation + 1, sync_attempts = 0, next_sync_at = NOW() WHERE id = $5 AND NOT deleted RETURNING *",
+    )
+    .bind(pod_revision)
+    .bind(image)
+    .bind(startup_command)
+    .bind(env_vars)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(server)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PreviewStartup {
+    /// A startup command to try instead of the server's current one.
+    #[serde(default)]
+    pub startup_command: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct UpdateServerStaff {
     pub id: i32,
@@ -161,16 +274,17 @@ pub struct UpdateServerStaff {
     pub image: String,
     pub startup_command: String,
     pub env_vars: Vec<EnvVar>,
+
+    #[serde(default = "default_restart_on_crash")]
+    pub restart_on_crash: bool,
 }
 
 pub async fn update_server_staff(
     conn: &mut PgConnection,
     userver: UpdateServerStaff,
 ) -> Result<ServerModel, sqlx::Error> {
-    // TODO verify image and env_vars
-
     let server = sqlx::query_as::<_, ServerModel>(
-        "UPDATE server SET name = $1, owner_id=$2, cpu_limit = $3, memory_limit = $4, disk_limit = $5, pod_id = $6, image = $7, startup_command = $8, env_vars = $9 WHERE id = $10 RETURNING *",
+        "UPDATE server SET name = $1, owner_id=$2, cpu_limit = $3, memory_limit = $4, disk_limit = $5, pod_id = $6, pod_revision = CASE WHEN pod_id = $6 THEN pod_revision ELSE (SELECT revision FROM pod WHERE id = $6) END, image = $7, startup_command = $8, env_vars = $9, restart_on_crash = $10, generation = generation + 1, sync_attempts = 0, next_sync_at = NOW() WHERE id = $11 AND NOT deleted RETURNING *",
     )
     .bind(userver.name)
     .bind(userver.owner_id)
@@ -181,12 +295,12 @@ pub async fn update_server_staff(
     .bind(userver.image)
     .bind(userver.startup_command)
     .bind(userver.env_vars)
+    .bind(userver.restart_on_crash)
     .bind(userver.id)
     .fetch_one(&mut *conn)
     .await?;
 
-    // TODO verify that the port is not already in use AND that port belongs to the node
-
+    // ports in use or of another node are rejected by constraints
     unassign_all_node_port_from_server(conn, server.id).await?;
 
     assign_node_port_to_server(conn, userver.port, server.id, true).await?;
@@ -197,11 +311,276 @@ pub async fn update_server_staff(
     Ok(server)
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct SuspendServer {
+    /// Shown to the owner while the server is suspended.
+    pub reason: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SuspensionModel {
+    pub id: i32,
+    pub server_id: i32,
+    pub suspended: bool,
+    pub reason: Option<String>,
+    pub user_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Suspends or unsuspends a server and records who did it.
+pub async fn set_server_suspension(
+    conn: &mut PgConnection,
+    id: i32,
+    suspended: bool,
+    reason: Option<String>,
+    user_id: i32,
+) -> Result<ServerModel, sqlx::Error> {
+    let server = sqlx::query_as::<_, ServerModel>(
+        "UPDATE server SET suspended = $1, suspended_reason = $2, generation = generation + 1, sync_attempts = 0, next_sync_at = NOW() WHERE id = $3 AND NOT deleted RETURNING *",
+    )
+    .bind(suspended)
+    .bind(&reason)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    sqlx::query(
+        "INSERT INTO server_suspension (server_id, suspended, reason, user_id) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(id)
+    .bind(suspended)
+    .bind(reason)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(server)
+}
+
+pub async fn get_suspensions_by_server_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+) -> Result<Vec<SuspensionModel>, sqlx::Error> {
+    let suspensions = sqlx::query_as::<_, SuspensionModel>(
+        "SELECT * FROM server_suspension WHERE server_id = $1 ORDER BY id DESC",
+    )
+    .bind(server_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(suspensions)
+}
+
+impl From<SuspensionModel> for ServerSuspension {
+    fn from(suspension: SuspensionModel) -> Self {
+        ServerSuspension {
+            id: suspension.id,
+            server_id: suspension.server_id,
+            suspended: suspension.suspended,
+            reason: suspension.reason,
+            user_id: suspension.user_id,
+            created_at: suspension.created_at,
+        }
+    }
+}
+
+/// Marks a server as deleted. It is removed for good once its node deleted it.
 pub async fn delete_server(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server SET deleted = TRUE, generation = generation + 1, sync_attempts = 0, next_sync_at = NOW() WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Takes back the deletion of a server its node refused to delete.
+pub async fn undelete_server(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server SET deleted = FALSE, generation = generation + 1, syncing = FALSE, sync_attempts = 0, next_sync_at = NOW() WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Puts a server back the way it was before a change its node rejected, unless
+/// it changed again since `generation`. Returns whether it was reverted.
+pub async fn revert_server(
+    conn: &mut PgConnection,
+    previous: &ServerModel,
+    generation: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE server SET name = $1, owner_id = $2, cpu_limit = $3, memory_limit = $4, disk_limit = $5, pod_id = $6, pod_revision = $7, image = $8, startup_command = $9, env_vars = $10, restart_on_crash = $11, suspended = $12, suspended_reason = $13, generation = generation + 1, syncing = FALSE, sync_error = NULL, sync_attempts = 0, next_sync_at = NOW() WHERE id = $14 AND generation = $15 AND NOT deleted",
+    )
+    .bind(&previous.name)
+    .bind(previous.owner_id)
+    .bind(previous.cpu_limit)
+    .bind(previous.memory_limit)
+    .bind(previous.disk_limit)
+    .bind(previous.pod_id)
+    .bind(previous.pod_revision)
+    .bind(&previous.image)
+    .bind(&previous.startup_command)
+    .bind(previous.env_vars.clone())
+    .bind(previous.restart_on_crash)
+    .bind(previous.suspended)
+    .bind(&previous.suspended_reason)
+    .bind(previous.id)
+    .bind(generation)
+    .execute(&mut *conn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn purge_server(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
     unassign_all_node_port_from_server(conn, id).await?;
-    sqlx::query("DELETE FROM server WHERE id = $1")
+    sqlx::query("DELETE FROM server WHERE id = $1 AND deleted")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Moves a server to another node whose agent already has the next generation
+/// of it. Fails if the server changed since that generation was built.
+pub async fn move_server(
+    conn: &mut PgConnection,
+    id: i32,
+    node_id: i32,
+    generation: i64,
+) -> Result<ServerModel, sqlx::Error> {
+    let server = sqlx::query_as::<_, ServerModel>(
+        "UPDATE server SET node_id = $1, generation = $2, applied_generation = $2, sync_error = NULL, sync_attempts = 0 WHERE id = $3 AND generation = $2 - 1 AND NOT deleted RETURNING *",
+    )
+    .bind(node_id)
+    .bind(generation)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(server)
+}
+
+/// Ids of every server on a node, including deleted ones that still exist there.
+pub async fn get_server_ids_by_node_id(
+    conn: &mut PgConnection,
+    node_id: i32,
+) -> Result<Vec<i32>, sqlx::Error> {
+    let ids = sqlx::query_scalar("SELECT id FROM server WHERE node_id = $1")
+        .bind(node_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    Ok(ids)
+}
+
+/// The servers of a node with the generation the node last reported for them.
+pub async fn get_applied_generations_by_node_id(
+    conn: &mut PgConnection,
+    node_id: i32,
+) -> Result<Vec<(i32, i64)>, sqlx::Error> {
+    let generations =
+        sqlx::query_as("SELECT id, applied_generation FROM server WHERE node_id = $1")
+            .bind(node_id)
+            .fetch_all(&mut *conn)
+            .await?;
+    Ok(generations)
+}
+
+/// Marks every server whose node is behind as syncing and returns it. A server
+/// that is already being synced is never claimed twice.
+pub async fn claim_unsynced_servers(
+    conn: &mut PgConnection,
+    now: DateTime<Utc>,
+) -> Result<Vec<ServerModel>, sqlx::Error> {
+    let servers = sqlx::query_as::<_, ServerModel>(
+        "UPDATE server SET syncing = TRUE WHERE NOT syncing AND applied_generation <> generation AND next_sync_at <= $1 RETURNING *",
+    )
+    .bind(now)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(servers)
+}
+
+/// Claims a single server right after it changed, regardless of any backoff.
+pub async fn claim_server_sync(
+    conn: &mut PgConnection,
+    id: i32,
+) -> Result<Option<ServerModel>, sqlx::Error> {
+    let server = sqlx::query_as::<_, ServerModel>(
+        "UPDATE server SET syncing = TRUE WHERE id = $1 AND NOT syncing AND applied_generation <> generation RETURNING *",
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(server)
+}
+
+pub async fn finish_server_sync(
+    conn: &mut PgConnection,
+    id: i32,
+    applied_generation: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server SET syncing = FALSE, applied_generation = $1, sync_error = NULL, sync_attempts = 0 WHERE id = $2",
+    )
+    .bind(applied_generation)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn fail_server_sync(
+    conn: &mut PgConnection,
+    id: i32,
+    error: &str,
+    next_sync_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server SET syncing = FALSE, sync_error = $1, sync_attempts = sync_attempts + 1, next_sync_at = $2 WHERE id = $3",
+    )
+    .bind(error)
+    .bind(next_sync_at)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Gives up a claimed sync without applying anything, the reconciler picks the
+/// server up again.
+pub async fn release_server_sync(conn: &mut PgConnection, id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE server SET syncing = FALSE WHERE id = $1")
         .bind(id)
         .execute(&mut *conn)
         .await?;
     Ok(())
 }
+
+/// Records what the node reports to have applied, so a node that lost a change
+/// gets it again. `previous` is the applied generation read before asking the
+/// node, a sync that finished since then knows better and is kept.
+pub async fn set_applied_generation(
+    conn: &mut PgConnection,
+    id: i32,
+    previous: i64,
+    applied_generation: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server SET applied_generation = $1, next_sync_at = NOW() WHERE id = $2 AND NOT syncing AND applied_generation = $3 AND applied_generation <> $1",
+    )
+    .bind(applied_generation)
+    .bind(id)
+    .bind(previous)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Clears syncing flags left behind by an orchestrator that stopped mid sync.
+pub async fn release_all_server_syncs(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE server SET syncing = FALSE WHERE syncing")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use chrono::{DateTime, Utc};
+use common::orch_types::{ServerTransfer, TransferStatus};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+
+#[derive(sqlx::FromRow)]
+pub struct TransferModel {
+    pub id: i32,
+    pub server_id: i32,
+    pub source_node_id: i32,
+    pub destination_node_id: i32,
+    pub status: TransferStatus,
+    pub bytes_done: i64,
+    pub bytes_total: i64,
+    pub error: Option<String>,
+    /// Whether the server ran before the transfer, so it is started again after.
+    pub was_running: bool,
+    pub removed_backups: i32,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+pub async fn get_transfer_by_id(
+    conn: &mut PgConnection,
+    id: i32,
+) -> Result<TransferModel, sqlx::Error> {
+    let transfer =
+        sqlx::query_as::<_, TransferModel>("SELECT * FROM server_transfer WHERE id = $1")
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await?;
+    Ok(transfer)
+}
+
+pub async fn get_latest_transfer_by_server_id(
+    conn: &mut PgConnection,
+    server_id: i32,
+) -> Result<Option<TransferModel>, sqlx::Error> {
+    let transfer = sqlx::query_as::<_, TransferModel>(
+        "SELECT * FROM server_transfer WHERE server_id = $1 ORDER BY id DESC LIMIT 1",
+    )
+    .bind(server_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(transfer)
+}
+
+pub async fn is_server_transferring(
+    conn: &mut PgConnection,
+    server_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let transferring = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM server_transfer WHERE server_id = $1 AND status IN ('pending', 'transferring'))",
+    )
+    .bind(server_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(transferring)
+}
+
+pub async fn get_active_transfers(
+    conn: &mut PgConnection,
+) -> Result<Vec<TransferModel>, sqlx::Error> {
+    let transfers = sqlx::query_as::<_, TransferModel>(
+        "SELECT * FROM server_transfer WHERE status IN ('pending', 'transferring')",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(transfers)
+}
+
+/// Ids of the servers currently being transferred to a node.
+pub async fn get_incoming_server_ids(
+    conn: &mut PgConnection,
+    node_id: i32,
+) -> Result<Vec<i32>, sqlx::Error> {
+    let ids = sqlx::query_scalar(
+        "SELECT server_id FROM server_transfer WHERE destination_node_id = $1 AND status IN ('pending', 'transferring')",
+    )
+    .bind(node_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(ids)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CreateTransfer {
+    pub destination_node_id: i32,
+}
+
+pub async fn create_transfer(
+    conn: &mut PgConnection,
+    server_id: i32,
+    source_node_id: i32,
+    ctransfer: CreateTransfer,
+    was_running: bool,
+) -> Result<TransferModel, sqlx::Error> {
+    let transfer = sqlx::query_as::<_, TransferModel>(
+        "INSERT INTO server_transfer (server_id, source_node_id, destination_node_id, was_running) VALUES ($1, $2, $3, $4) RETURNING *",
+    )
+    .bind(server_id)
+    .bind(source_node_id)
+    .bind(ctransfer.destination_node_id)
+    .bind(was_running)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(transfer)
+}
+
+pub async fn set_transfer_progress(
+    conn: &mut PgConnection,
+    id: i32,
+    status: TransferStatus,
+    bytes_done: i64,
+    bytes_total: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server_transfer SET status = $1, bytes_done = $2, bytes_total = $3 WHERE id = $4",
+    )
+    .bind(status)
+    .bind(bytes_done)
+    .bind(bytes_total)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn finish_transfer(
+    conn: &mut PgConnection,
+    id: i32,
+    status: TransferStatus,
+    error: Option<String>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE server_transfer SET status = $1, error = $2, finished_at = NOW() WHERE id = $3",
+    )
+    .bind(status)
+    .bind(error)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_removed_backups(
+    conn: &mut PgConnection,
+    id: i32,
+    removed_backups: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE server_transfer SET removed_backups = $1 WHERE id = $2")
+        .bind(removed_backups)
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+impl From<TransferModel> for ServerTransfer {
+    fn from(transfer: TransferModel) -> Self {
+        ServerTransfer {
+            id: transfer.id,
+            server_id: transfer.server_id,
+            source_node_id: transfer.source_node_id,
+            destination_node_id: transfer.destination_node_id,
+            status: transfer.status,
+            bytes_done: transfer.bytes_done,
+            bytes_total: transfer.bytes_total,
+            error: transfer.error,
+            removed_backups: transfer.removed_backups,
+            created_at: transfer.created_at,
+            finished_at: transfer.finished_at,
+        }
+    }
+}
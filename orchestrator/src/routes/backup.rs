@@ -0,0 +1,107 @@
+use axum::{extract::Path, http::StatusCode, Json};
+use common::{
+    agent_types::{PruneResult, RestoreSnapshot, RetentionPolicy},
+    orch_types::Backup,
+};
+
+use crate::{
+    auth::AuthSession,
+    models::backup,
+    services::{agent, transfer::ensure_not_transferring},
+    utils::{auth::ensure_not_suspended, get_node_from_server_id, AppError, DbConn},
+};
+
+#[utoipa::path(
+    get,
+    path = "/{id}/backup",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = [Backup]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::BACKUP_TAG
+)]
+pub async fn get_backups(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Vec<Backup>>, AppError> {
+    let backups = backup::get_backups_by_server_id(&mut conn, id).await?;
+    Ok(Json(backups.into_iter().map(Into::into).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/backup",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = CREATED, body = Backup), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::BACKUP_TAG
+)]
+pub async fn create_backup(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<(StatusCode, Json<Backup>), AppError> {
+    ensure_not_transferring(&mut conn, id).await?;
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    let snapshot = agent::create_snapshot(&node, id).await?;
+    let backup = backup::create_backup(&mut conn, snapshot).await?;
+    Ok((StatusCode::CREATED, Json(backup.into())))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/backup/{snapshot_id}/restore",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("snapshot_id" = String, Path, description = "snapshot id")
+    ),
+    responses((status = OK), (status = FORBIDDEN, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::BACKUP_TAG
+)]
+pub async fn restore_backup(
+    Path((id, snapshot_id)): Path<(i32, String)>,
+    session: AuthSession,
+    DbConn(mut conn): DbConn,
+    Json(body): Json<RestoreSnapshot>,
+) -> Result<(), AppError> {
+    ensure_not_suspended(&session, &mut conn, id).await?;
+    ensure_not_transferring(&mut conn, id).await?;
+    let backup = backup::get_backup_by_snapshot_id(&mut conn, id, &snapshot_id).await?;
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    agent::restore_snapshot(&node, id, &backup.snapshot_id, &body).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{id}/backup/{snapshot_id}",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("snapshot_id" = String, Path, description = "snapshot id")
+    ),
+    responses((status = OK), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::BACKUP_TAG
+)]
+pub async fn delete_backup(
+    Path((id, snapshot_id)): Path<(i32, String)>,
+    DbConn(mut conn): DbConn,
+) -> Result<(), AppError> {
+    let backup = backup::get_backup_by_snapshot_id(&mut conn, id, &snapshot_id).await?;
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    agent::delete_snapshot(&node, id, &backup.snapshot_id).await?;
+    backup::delete_backups(&mut conn, id, &[backup.snapshot_id]).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/backup/prune",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = PruneResult), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::BACKUP_TAG
+)]
+pub async fn prune_backups(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+    Json(policy): Json<RetentionPolicy>,
+) -> Result<Json<PruneResult>, AppError> {
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    let result = agent::prune_snapshots(&node, id, &policy).await?;
+    backup::delete_backups(&mut conn, id, &result.removed).await?;
+    Ok(Json(result))
+}
@@ -6,8 +6,11 @@ use utoipa_axum::router::OpenApiRouter;
 use crate::{auth::AuthBackend, utils::auth::require_staff, AppState};
 
 pub mod auth;
+pub mod backup;
 pub mod nodes;
 pub mod pod;
+pub mod remote;
+pub mod schedule;
 pub mod server;
 pub mod user;
 
@@ -16,6 +19,9 @@ const SERVER_TAG: &str = "server";
 const POD_TAG: &str = "pod";
 const USER_TAG: &str = "user";
 const AUTH_TAG: &str = "auth";
+const BACKUP_TAG: &str = "backup";
+const SCHEDULE_TAG: &str = "schedule";
+const REMOTE_TAG: &str = "remote";
 
 #[derive(OpenApi)]
 #[openapi(
@@ -24,7 +30,10 @@ const AUTH_TAG: &str = "auth";
         (name = SERVER_TAG, description = "Server API endpoints"),
         (name = POD_TAG, description = "Pod API endpoints"),
         (name = USER_TAG, description = "User API endpoints"),
-        (name = AUTH_TAG, description = "Authentication API endpoints")
+        (name = AUTH_TAG, description = "Authentication API endpoints"),
+        (name = BACKUP_TAG, description = "Backup API endpoints"),
+        (name = SCHEDULE_TAG, description = "Schedule API endpoints"),
+        (name = REMOTE_TAG, description = "Agent facing API endpoints")
     )
 )]
 pub struct ApiDoc;
@@ -35,7 +44,8 @@ pub fn api_router(state: AppState) -> OpenApiRouter<AppState> {
         .nest("/pod", pod::pods_router())
         .route_layer(middleware::from_fn(require_staff))
         .nest("/user", user::user_router())
-        .nest("/server", server::server_router(state))
+        .nest("/server", server::server_router(state.clone()))
         .layer(login_required!(AuthBackend))
         .nest("/auth", auth::auth_router())
+        .nest("/remote", remote::remote_router(state))
 }
@@ -1,13 +1,18 @@
 use axum::{extract::Path, http::StatusCode, Json};
-use common::orch_types::{Node, NodePort};
+use common::{
+    agent_types::{DriftReport, VerifyReport},
+    orch_types::{Node, NodePort},
+};
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
     models::{
-        node::{self, CreateNode, NodeModel},
-        node_port::{self, CreateNodePort},
+        node::{self, CreateNode, SetMaintenance, SetNodeCapacity, UpdateNode},
+        node_port::{self, CreateNodePort, CreateNodePortRange},
+        server, transfer,
     },
-    utils::{node_model_to_node, AppError, DbConn},
+    services::agent,
+    utils::{auth, node_model_to_node, AppError, DbConn},
     AppState,
 };
 
@@ -16,6 +21,13 @@ pub fn nodes_router() -> OpenApiRouter<AppState> {
         .routes(routes!(get_nodes, create_node, update_node))
         .routes(routes!(get_node_by_id, delete_node))
         .routes(routes!(get_node_port, create_node_port, delete_node_port))
+        .routes(routes!(create_node_port_range))
+        .routes(routes!(verify_backups))
+        .routes(routes!(regenerate_token))
+        .routes(routes!(set_maintenance))
+        .routes(routes!(set_capacity))
+        .routes(routes!(get_drift_report, reconcile))
+        .routes(routes!(delete_unknown_server))
 }
 
 #[utoipa::path(
@@ -77,7 +89,7 @@ pub async fn create_node(
 )]
 pub async fn update_node(
     DbConn(mut conn): DbConn,
-    Json(node): Json<NodeModel>,
+    Json(node): Json<UpdateNode>,
 ) -> Result<Json<Node>, AppError> {
     let node = node::update_node(&mut conn, node).await?;
     let node = node_model_to_node(node, &mut conn).await?;
@@ -130,6 +142,48 @@ pub async fn create_node_port(
     Ok((StatusCode::CREATED, Json(node_port.into())))
 }
 
+const MAX_PORT_RANGE: i32 = 1000;
+
+/// Parses `25565` or `25565-25600` into the first and last port.
+fn parse_port_range(ports: &str) -> Result<(i32, i32), AppError> {
+    let invalid = || AppError::BadRequest(format!("invalid port range: {}", ports));
+    let (first, last) = match ports.split_once('-') {
+        Some((first, last)) => (first.trim(), last.trim()),
+        None => (ports.trim(), ports.trim()),
+    };
+    let first: i32 = first.parse().map_err(|_| invalid())?;
+    let last: i32 = last.parse().map_err(|_| invalid())?;
+    if !(1..=65535).contains(&first) || !(1..=65535).contains(&last) || first > last {
+        return Err(invalid());
+    }
+    if last - first >= MAX_PORT_RANGE {
+        return Err(AppError::BadRequest(format!(
+            "at most {} ports can be created at once",
+            MAX_PORT_RANGE
+        )));
+    }
+    Ok((first, last))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/port/range",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = CREATED, body = [NodePort]), (status = BAD_REQUEST, body = String), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn create_node_port_range(
+    Path(node_id): Path<i32>,
+    DbConn(mut conn): DbConn,
+    Json(range): Json<CreateNodePortRange>,
+) -> Result<(StatusCode, Json<Vec<NodePort>>), AppError> {
+    let (first, last) = parse_port_range(&range.ports)?;
+    let node_ports =
+        node_port::create_node_port_range(&mut conn, node_id, range, first, last).await?;
+    let node_ports: Vec<NodePort> = node_ports.into_iter().map(Into::into).collect();
+    Ok((StatusCode::CREATED, Json(node_ports)))
+}
+
 #[utoipa::path(
     delete,
     path = "/port/{id}",
@@ -144,3 +198,159 @@ pub async fn delete_node_port(
     node_port::delete_node_port(&mut conn, id).await?;
     Ok(())
 }
+
+#[utoipa::path(
+    post,
+    path = "/{id}/backup/verify",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = OK, body = VerifyReport), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn verify_backups(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<VerifyReport>, AppError> {
+    let node = node::get_node_by_id(&mut conn, id).await?;
+    Ok(Json(agent::verify_backups(&node).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/token",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = OK, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn regenerate_token(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<String>, AppError> {
+    let node = node::get_node_by_id(&mut conn, id).await?;
+    // only the salt and the hash are stored, so the token can not be shown again
+    let salt = auth::generate_token();
+    let token = agent::node_token(node.id, &salt);
+    node::set_node_token(&mut conn, node.id, &salt, &auth::hash_node_token(&token)).await?;
+    tracing::info!("Generated a new token for node {}", node.id);
+    Ok(Json(token))
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/capacity",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = OK, body = Node), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn set_capacity(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+    Json(capacity): Json<SetNodeCapacity>,
+) -> Result<Json<Node>, AppError> {
+    // lowering the capacity below what is allocated only affects new servers
+    let node = node::set_node_capacity(&mut conn, id, capacity).await?;
+    let node = node_model_to_node(node, &mut conn).await?;
+    Ok(Json(node))
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/maintenance",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = OK, body = Node), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn set_maintenance(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+    Json(maintenance): Json<SetMaintenance>,
+) -> Result<Json<Node>, AppError> {
+    let node = node::set_maintenance(&mut conn, id, maintenance).await?;
+    tracing::info!(
+        "Node {} is {} maintenance",
+        node.id,
+        if node.maintenance { "in" } else { "out of" }
+    );
+    let node = node_model_to_node(node, &mut conn).await?;
+    Ok(Json(node))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/reconcile",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = OK, body = Option<DriftReport>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn get_drift_report(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Option<DriftReport>>, AppError> {
+    let node = node::get_node_by_id(&mut conn, id).await?;
+    Ok(Json(agent::get_drift_report(&node).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/reconcile",
+    params(("id" = i32, Path, description = "node id")),
+    responses((status = OK, body = DriftReport), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn reconcile(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<DriftReport>, AppError> {
+    let node = node::get_node_by_id(&mut conn, id).await?;
+    Ok(Json(agent::reconcile(&node).await?))
+}
+
+/// Deletes a server the database does not know from a node, such as one the
+/// reconciler reports after a database was restored.
+#[utoipa::path(
+    delete,
+    path = "/{id}/unknown-server/{server_id}",
+    params(("id" = i32, Path, description = "node id"), ("server_id" = i32, Path, description = "server id")),
+    responses((status = OK), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::NODE_TAG
+)]
+pub async fn delete_unknown_server(
+    Path((id, server_id)): Path<(i32, i32)>,
+    DbConn(mut conn): DbConn,
+) -> Result<(), AppError> {
+    let node = node::get_node_by_id(&mut conn, id).await?;
+    let known = server::get_server_ids_by_node_id(&mut conn, id).await?;
+    let incoming = transfer::get_incoming_server_ids(&mut conn, id).await?;
+    if known.contains(&server_id) || incoming.contains(&server_id) {
+        return Err(AppError::Conflict(format!(
+            "server {} belongs on node {}, delete it as a server",
+            server_id, id
+        )));
+    }
+    drop(conn);
+    agent::delete_server(&node, server_id).await?;
+    tracing::info!("Deleted unknown server {} from node {}", server_id, id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ports_and_ranges() {
+        assert_eq!(parse_port_range("25565").unwrap(), (25565, 25565));
+        assert_eq!(parse_port_range(" 25565 - 25600 ").unwrap(), (25565, 25600));
+        assert_eq!(parse_port_range("1-1000").unwrap(), (1, 1000));
+    }
+
+    #[test]
+    fn rejects_invalid_port_ranges() {
+        for ports in ["", "port", "0", "65536", "25600-25565", "1-", "-1", "1-2-3"] {
+            assert!(parse_port_range(ports).is_err(), "{}", ports);
+        }
+        assert_eq!(
+            parse_port_range("1-1001").err().unwrap().to_string(),
+            "at most 1000 ports can be created at once"
+        );
+    }
+}
@@ -1,9 +1,29 @@
-use axum::{extract::Path, Json};
-use common::orch_types::Pod;
+use axum::{
+    extract::{Path, State},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use common::orch_types::{
+    ConfigFile, Hardening, Pod, PodCategory, PodDocument, PodRevision, PodVariable, ServerRollout,
+    Sidecar, POD_FORMAT_VERSION,
+};
+use sqlx::Connection;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
-    models::pod,
+    models::{pod, pod_category},
+    services::{
+        egg::{self, Egg},
+        rollout,
+        variables::{
+            validate_config_files, validate_pod_variables, validate_sidecars,
+            validate_startup_command,
+        },
+    },
     utils::{AppError, DbConn},
     AppState,
 };
@@ -12,6 +32,56 @@ pub fn pods_router() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(get_pods, create_pod, update_pod))
         .routes(routes!(get_pod_by_id, delete_pod))
+        .routes(routes!(export_pod))
+        .routes(routes!(import_pod))
+        .routes(routes!(import_egg))
+        .routes(routes!(get_pod_revisions))
+        .routes(routes!(preview_rollout))
+        .routes(routes!(roll_out_pod))
+        .routes(routes!(
+            get_pod_categories,
+            create_pod_category,
+            update_pod_category
+        ))
+        .routes(routes!(delete_pod_category))
+}
+
+fn validate_pod(
+    done_regex: &Option<String>,
+    variables: &[PodVariable],
+    startup_command: &str,
+    config_files: &[ConfigFile],
+    sidecars: &[Sidecar],
+    hardening: &Hardening,
+) -> Result<(), AppError> {
+    if hardening.pid_limit.is_some_and(|limit| limit <= 0) {
+        return Err(AppError::BadRequest(
+            "pid limit must be positive".to_string(),
+        ));
+    }
+    if let Some(path) = hardening.tmpfs.iter().find(|path| !path.starts_with('/')) {
+        return Err(AppError::BadRequest(format!(
+            "tmpfs mount {} must be an absolute path",
+            path
+        )));
+    }
+    if let Some(done_regex) = done_regex {
+        regex::Regex::new(done_regex)
+            .map_err(|e| AppError::BadRequest(format!("invalid done regex: {}", e)))?;
+    }
+    validate_pod_variables(variables)?;
+    validate_startup_command(variables, startup_command)?;
+    validate_config_files(variables, config_files)?;
+    validate_sidecars(variables, sidecars)
+}
+
+const YAML: &str = "application/yaml";
+
+fn is_yaml(headers: &HeaderMap, header: impl axum::http::header::AsHeaderName) -> bool {
+    headers
+        .get(header)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("yaml"))
 }
 
 #[utoipa::path(
@@ -44,13 +114,21 @@ pub async fn get_pod_by_id(
 #[utoipa::path(
     post,
     path = "",
-    responses((status = OK, body = Pod),(status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = OK, body = Pod),(status = BAD_REQUEST, body = String),(status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::POD_TAG
 )]
 pub async fn create_pod(
     DbConn(mut conn): DbConn,
     Json(pod): Json<pod::CreatePod>,
 ) -> Result<Json<Pod>, AppError> {
+    validate_pod(
+        &pod.done_regex,
+        &pod.variables,
+        &pod.startup_command,
+        &pod.config_files,
+        &pod.sidecars,
+        &pod.hardening,
+    )?;
     let pod = pod::create_pod(&mut conn, pod).await?;
     Ok(Json(pod.into()))
 }
@@ -58,14 +136,24 @@ pub async fn create_pod(
 #[utoipa::path(
     put,
     path = "",
-    responses((status = OK, body = Pod),(status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = OK, body = Pod),(status = BAD_REQUEST, body = String),(status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::POD_TAG
 )]
 pub async fn update_pod(
     DbConn(mut conn): DbConn,
     Json(pod): Json<pod::PodModel>,
 ) -> Result<Json<Pod>, AppError> {
-    let pod = pod::update_pod(&mut conn, pod).await?;
+    validate_pod(
+        &pod.done_regex,
+        &pod.variables,
+        &pod.startup_command,
+        &pod.config_files,
+        &pod.sidecars,
+        &pod.hardening,
+    )?;
+    let mut tx = conn.begin().await?;
+    let pod = pod::update_pod(&mut tx, pod).await?;
+    tx.commit().await?;
     Ok(Json(pod.into()))
 }
 
@@ -73,10 +161,206 @@ pub async fn update_pod(
     delete,
     path = "/{id}",
     params(("id" = u32, Path, description = "pod id")),
-    responses((status = OK),(status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = OK),(status = CONFLICT, body = String),(status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::POD_TAG
 )]
 pub async fn delete_pod(DbConn(mut conn): DbConn, Path(id): Path<u32>) -> Result<(), AppError> {
+    let servers = pod::count_pod_servers(&mut conn, id as i32).await?;
+    if servers > 0 {
+        return Err(AppError::Conflict(format!(
+            "pod {} is still used by {} servers, move or delete them first",
+            id, servers
+        )));
+    }
     pod::delete_pod(&mut conn, id as i32).await?;
     Ok(())
 }
+
+#[utoipa::path(
+    get,
+    path = "/{id}/export",
+    params(("id" = u32, Path, description = "pod id")),
+    responses(
+        (status = OK, content((PodDocument = "application/json"), (PodDocument = "application/yaml"))),
+        (status = INTERNAL_SERVER_ERROR, body = String)
+    ),
+    tag = super::POD_TAG
+)]
+pub async fn export_pod(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<u32>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let document: PodDocument = pod::get_pod_by_id(&mut conn, id as i32).await?.into();
+    if is_yaml(&headers, ACCEPT) {
+        let yaml = serde_yaml::to_string(&document).map_err(|e| {
+            tracing::error!("Failed to write pod {} as yaml: {}", id, e);
+            AppError::InternalServerError
+        })?;
+        return Ok(([(CONTENT_TYPE, YAML)], yaml).into_response());
+    }
+    Ok(Json(document).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/import",
+    request_body(content((PodDocument = "application/json"), (PodDocument = "application/yaml"))),
+    responses((status = OK, body = Pod), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn import_pod(
+    DbConn(mut conn): DbConn,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<Pod>, AppError> {
+    let document: PodDocument = if is_yaml(&headers, CONTENT_TYPE) {
+        serde_yaml::from_str(&body).map_err(|e| AppError::BadRequest(e.to_string()))?
+    } else {
+        serde_json::from_str(&body).map_err(|e| AppError::BadRequest(e.to_string()))?
+    };
+    if document.version > POD_FORMAT_VERSION {
+        return Err(AppError::BadRequest(format!(
+            "pod format version {} is newer than this panel supports",
+            document.version
+        )));
+    }
+    let pod: pod::CreatePod = document.into();
+    validate_pod(
+        &pod.done_regex,
+        &pod.variables,
+        &pod.startup_command,
+        &pod.config_files,
+        &pod.sidecars,
+        &pod.hardening,
+    )?;
+    let pod = pod::create_pod(&mut conn, pod).await?;
+    Ok(Json(pod.into()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/import/egg",
+    responses((status = OK, body = Pod), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn import_egg(
+    DbConn(mut conn): DbConn,
+    Json(egg): Json<Egg>,
+) -> Result<Json<Pod>, AppError> {
+    let pod = egg::egg_to_pod(egg)?;
+    validate_pod(
+        &pod.done_regex,
+        &pod.variables,
+        &pod.startup_command,
+        &pod.config_files,
+        &pod.sidecars,
+        &pod.hardening,
+    )?;
+    let pod = pod::create_pod(&mut conn, pod).await?;
+    tracing::info!("Imported egg {} as pod {}", pod.name, pod.id);
+    Ok(Json(pod.into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/revisions",
+    params(("id" = u32, Path, description = "pod id")),
+    responses((status = OK, body = [PodRevision]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn get_pod_revisions(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<PodRevision>>, AppError> {
+    let revisions = pod::get_pod_revisions(&mut conn, id as i32).await?;
+    Ok(Json(revisions.into_iter().map(|r| r.into()).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/rollout/preview",
+    params(("id" = u32, Path, description = "pod id")),
+    responses((status = OK, body = [ServerRollout]), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn preview_rollout(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<u32>,
+    Json(rollout): Json<pod::Rollout>,
+) -> Result<Json<Vec<ServerRollout>>, AppError> {
+    let rollouts = rollout::preview(&mut conn, id as i32, rollout.server_ids).await?;
+    Ok(Json(rollouts))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/rollout",
+    params(("id" = u32, Path, description = "pod id")),
+    responses((status = OK, body = [ServerRollout]), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn roll_out_pod(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    Json(rollout): Json<pod::Rollout>,
+) -> Result<Json<Vec<ServerRollout>>, AppError> {
+    let rollouts = rollout::roll_out(&state.db, id as i32, rollout.server_ids).await?;
+    Ok(Json(rollouts))
+}
+
+#[utoipa::path(
+    get,
+    path = "/category",
+    responses((status = OK, body = [PodCategory]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn get_pod_categories(
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Vec<PodCategory>>, AppError> {
+    let categories = pod_category::get_pod_categories(&mut conn).await?;
+    Ok(Json(categories))
+}
+
+#[utoipa::path(
+    post,
+    path = "/category",
+    responses((status = OK, body = PodCategory), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn create_pod_category(
+    DbConn(mut conn): DbConn,
+    Json(category): Json<pod_category::CreatePodCategory>,
+) -> Result<Json<PodCategory>, AppError> {
+    let category = pod_category::create_pod_category(&mut conn, category).await?;
+    Ok(Json(category))
+}
+
+#[utoipa::path(
+    put,
+    path = "/category",
+    responses((status = OK, body = PodCategory), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn update_pod_category(
+    DbConn(mut conn): DbConn,
+    Json(category): Json<PodCategory>,
+) -> Result<Json<PodCategory>, AppError> {
+    let category = pod_category::update_pod_category(&mut conn, category).await?;
+    Ok(Json(category))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/category/{id}",
+    params(("id" = u32, Path, description = "pod category id")),
+    responses((status = OK), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::POD_TAG
+)]
+pub async fn delete_pod_category(
+    DbConn(mut conn): DbConn,
+    Path(id): Path<u32>,
+) -> Result<(), AppError> {
+    pod_category::delete_pod_category(&mut conn, id as i32).await?;
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use axum::{middleware, Extension, Json};
+use common::{agent_types::Heartbeat, orch_types::Server};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    models::{
+        node::{self, NodeModel},
+        server,
+    },
+    utils::{auth::require_node, server_model_to_server, AppError, DbConn},
+    AppState,
+};
+
+/// Endpoints called by agents, authenticated with the node token instead of a
+/// user session.
+pub fn remote_router(state: AppState) -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_servers))
+        .routes(routes!(heartbeat))
+        .route_layer(middleware::from_fn_with_state(state, require_node))
+}
+
+#[utoipa::path(
+    get,
+    path = "/servers",
+    responses((status = OK, body = [Server]), (status = UNAUTHORIZED, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::REMOTE_TAG
+)]
+pub async fn get_servers(
+    Extension(node): Extension<NodeModel>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Vec<Server>>, AppError> {
+    let servers = server::get_servers_by_node_id(&mut conn, node.id).await?;
+    let servers: Vec<Server> = {
+        let mut new = vec![];
+        for server in servers {
+            new.push(server_model_to_server(server, &mut conn).await?);
+        }
+        new
+    };
+    Ok(Json(servers))
+}
+
+#[utoipa::path(
+    post,
+    path = "/heartbeat",
+    responses((status = OK), (status = UNAUTHORIZED, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::REMOTE_TAG
+)]
+pub async fn heartbeat(
+    Extension(node): Extension<NodeModel>,
+    DbConn(mut conn): DbConn,
+    Json(heartbeat): Json<Heartbeat>,
+) -> Result<(), AppError> {
+    if node.last_seen_at.is_none() {
+        tracing::info!("Node {} sent its first heartbeat", node.id);
+    }
+    node::record_heartbeat(&mut conn, node.id, heartbeat).await?;
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use common::orch_types::{Schedule, ScheduleRun, ScheduleTask};
+
+use crate::{
+    models::schedule::{self, CreateSchedule},
+    services::scheduler,
+    utils::{AppError, DbConn},
+    AppState,
+};
+
+fn validate(schedule: &CreateSchedule) -> Result<(), AppError> {
+    scheduler::parse_cron(&schedule.cron).map_err(AppError::BadRequest)?;
+    scheduler::parse_timezone(&schedule.timezone).map_err(AppError::BadRequest)?;
+    if schedule.tasks.is_empty() {
+        return Err(AppError::BadRequest(
+            "a schedule needs at least one task".to_string(),
+        ));
+    }
+    for task in &schedule.tasks {
+        if let ScheduleTask::Wait { seconds } = task {
+            if *seconds > scheduler::MAX_WAIT.as_secs() {
+                return Err(AppError::BadRequest(format!(
+                    "wait tasks can take at most {} seconds",
+                    scheduler::MAX_WAIT.as_secs()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn next_run_at(schedule: &CreateSchedule) -> Option<chrono::DateTime<Utc>> {
+    if !schedule.enabled {
+        return None;
+    }
+    scheduler::next_run(&schedule.cron, &schedule.timezone, Utc::now())
+        .ok()
+        .flatten()
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/schedule",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = [Schedule]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SCHEDULE_TAG
+)]
+pub async fn get_schedules(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Vec<Schedule>>, AppError> {
+    let schedules = schedule::get_schedules_by_server_id(&mut conn, id).await?;
+    Ok(Json(schedules.into_iter().map(Into::into).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/schedule",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = CREATED, body = Schedule), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SCHEDULE_TAG
+)]
+pub async fn create_schedule(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+    Json(body): Json<CreateSchedule>,
+) -> Result<(StatusCode, Json<Schedule>), AppError> {
+    validate(&body)?;
+    let next_run_at = next_run_at(&body);
+    let schedule = schedule::create_schedule(&mut conn, id, body, next_run_at).await?;
+    Ok((StatusCode::CREATED, Json(schedule.into())))
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/schedule/{schedule_id}",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("schedule_id" = i32, Path, description = "schedule id")
+    ),
+    responses((status = OK, body = Schedule), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SCHEDULE_TAG
+)]
+pub async fn update_schedule(
+    Path((id, schedule_id)): Path<(i32, i32)>,
+    DbConn(mut conn): DbConn,
+    Json(body): Json<CreateSchedule>,
+) -> Result<Json<Schedule>, AppError> {
+    validate(&body)?;
+    let next_run_at = next_run_at(&body);
+    let schedule = schedule::update_schedule(&mut conn, id, schedule_id, body, next_run_at).await?;
+    Ok(Json(schedule.into()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{id}/schedule/{schedule_id}",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("schedule_id" = i32, Path, description = "schedule id")
+    ),
+    responses((status = OK), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SCHEDULE_TAG
+)]
+pub async fn delete_schedule(
+    Path((id, schedule_id)): Path<(i32, i32)>,
+    DbConn(mut conn): DbConn,
+) -> Result<(), AppError> {
+    schedule::delete_schedule(&mut conn, id, schedule_id).await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/schedule/{schedule_id}/run",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("schedule_id" = i32, Path, description = "schedule id")
+    ),
+    responses((status = OK, body = [ScheduleRun]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SCHEDULE_TAG
+)]
+pub async fn get_schedule_runs(
+    Path((id, schedule_id)): Path<(i32, i32)>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Vec<ScheduleRun>>, AppError> {
+    let schedule = schedule::get_schedule_by_id(&mut conn, id, schedule_id).await?;
+    let runs = schedule::get_schedule_runs(&mut conn, schedule.id).await?;
+    Ok(Json(runs.into_iter().map(Into::into).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/schedule/{schedule_id}/run",
+    params(
+        ("id" = i32, Path, description = "server id"),
+        ("schedule_id" = i32, Path, description = "schedule id")
+    ),
+    responses((status = ACCEPTED), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SCHEDULE_TAG
+)]
+pub async fn run_schedule(
+    Path((id, schedule_id)): Path<(i32, i32)>,
+    State(state): State<AppState>,
+    DbConn(mut conn): DbConn,
+) -> Result<StatusCode, AppError> {
+    let schedule = schedule::claim_schedule(&mut conn, id, schedule_id)
+        .await?
+        .ok_or(AppError::Conflict(
+            "schedule is already running".to_string(),
+        ))?;
+    tokio::spawn(scheduler::execute(state.db, schedule));
+    Ok(StatusCode::ACCEPTED)
+}
@@ -1,18 +1,53 @@
-use axum::{extract::Path, http::StatusCode, middleware, response::IntoResponse, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware, Json,
+};
 use common::{
-    agent_types::{ServerSignal, ServerStatus},
-    orch_types::Server,
+    agent_types::{
+        ConsoleCommand, CrashReport, InstallOptions, InstallReport, ServerSignal, ServerStatus,
+        ServerStatusReport,
+    },
+    orch_types::{Server, ServerSuspension, ServerTransfer},
+    startup::{self, StartupPreview},
 };
+use sqlx::PgConnection;
 use utoipa_axum::{
     router::{OpenApiRouter, UtoipaMethodRouterExt},
     routes,
 };
 
+use super::backup::{
+    __path_create_backup, __path_delete_backup, __path_get_backups, __path_prune_backups,
+    __path_restore_backup, create_backup, delete_backup, get_backups, prune_backups,
+    restore_backup,
+};
+use super::schedule::{
+    __path_create_schedule, __path_delete_schedule, __path_get_schedule_runs, __path_get_schedules,
+    __path_run_schedule, __path_update_schedule, create_schedule, delete_schedule,
+    get_schedule_runs, get_schedules, run_schedule, update_schedule,
+};
 use crate::{
     auth::AuthSession,
-    models::server::{self, CreateServer, UpdateServer, UpdateServerStaff},
+    models::{
+        pod,
+        server::{
+            self, ChangePod, CreateServer, PreviewStartup, SuspendServer, UpdateServer,
+            UpdateServerStaff,
+        },
+        transfer::{self, CreateTransfer},
+        user::User,
+    },
+    services::{
+        agent, placement, provisioning, reconciler,
+        transfer::{ensure_not_transferring, start_transfer},
+        variables,
+    },
     utils::{
-        auth::{require_server_owner_staff, require_server_owner_staff_path, require_staff},
+        auth::{
+            ensure_not_suspended, require_server_owner_staff, require_server_owner_staff_path,
+            require_staff,
+        },
         get_node_from_server_id, server_model_to_server, AppError, DbConn,
     },
     AppState,
@@ -24,13 +59,29 @@ pub fn server_router(state: AppState) -> OpenApiRouter<AppState> {
         .routes(routes!(create_server))
         .routes(routes!(delete_server))
         .routes(routes!(get_servers_by_node_id))
+        .routes(routes!(get_transfer, transfer_server))
+        .routes(routes!(suspend_server))
+        .routes(routes!(unsuspend_server))
+        .routes(routes!(get_suspensions))
         .route_layer(middleware::from_fn(require_staff));
 
     OpenApiRouter::new()
         .routes(routes!(status))
+        .routes(routes!(status_report))
+        .routes(routes!(crash_report))
         .routes(routes!(signal))
-        .routes(routes!(install))
+        .routes(routes!(install_report, install))
+        .routes(routes!(change_pod))
+        .routes(routes!(preview_startup))
         .routes(routes!(get_server))
+        .routes(routes!(get_backups, create_backup))
+        .routes(routes!(delete_backup))
+        .routes(routes!(restore_backup))
+        .routes(routes!(prune_backups))
+        .routes(routes!(get_schedules, create_schedule))
+        .routes(routes!(update_schedule, delete_schedule))
+        .routes(routes!(get_schedule_runs, run_schedule))
+        .routes(routes!(command))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             require_server_owner_staff_path,
@@ -63,7 +114,8 @@ pub async fn get_servers(
     let servers: Vec<Server> = {
         let mut new = vec![];
         for server in servers {
-            new.push(server_model_to_server(server, &mut conn).await?);
+            let server = server_model_to_server(server, &mut conn).await?;
+            new.push(for_user(&user, server, &mut conn).await?);
         }
         new
     };
@@ -80,11 +132,13 @@ pub async fn get_servers(
 )]
 pub async fn get_server(
     Path(id): axum::extract::Path<i32>,
+    session: AuthSession,
     DbConn(mut conn): DbConn,
 ) -> Result<Json<Server>, AppError> {
+    let user = session.user.unwrap();
     let server = server::get_server_by_id(&mut conn, id).await?;
     let server = server_model_to_server(server, &mut conn).await?;
-    Ok(Json(server))
+    Ok(Json(for_user(&user, server, &mut conn).await?))
 }
 
 #[utoipa::path(
@@ -112,75 +166,129 @@ pub async fn get_servers_by_node_id(
 #[utoipa::path(
     post,
     path = "",
-    responses((status = CREATED, body = Server), (status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = CREATED, body = Server), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::SERVER_TAG
 )]
 pub async fn create_server(
-    DbConn(mut conn): DbConn,
+    State(state): State<AppState>,
     Json(server): Json<CreateServer>,
 ) -> Result<Json<Server>, AppError> {
-    let server = server::create_server(&mut conn, server).await?;
-    let node = get_node_from_server_id(server.id, &mut conn).await?;
-    let server = server_model_to_server(server, &mut conn).await?;
-    let res = reqwest::Client::new()
-        .post(format!("http://{}/server", node.fqdn))
-        .json(&server)
-        .send()
-        .await?;
-    if res.status() != StatusCode::OK {
-        return Err(AppError::NodeError(res.text().await?));
-    }
+    let server = provisioning::create_server(&state.db, server).await?;
     Ok(Json(server))
 }
 
 #[utoipa::path(
     put,
     path = "",
-    responses((status = OK, body = Server), (status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = OK, body = Server), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::SERVER_TAG
 )]
 pub async fn update_server(
+    session: AuthSession,
+    State(state): State<AppState>,
     DbConn(mut conn): DbConn,
-    Json(server): Json<UpdateServer>,
+    Json(mut server): Json<UpdateServer>,
 ) -> Result<Json<Server>, AppError> {
+    let user = session.user.unwrap();
+    ensure_not_transferring(&mut conn, server.id).await?;
+    let current = server::get_server_by_id(&mut conn, server.id).await?;
+    if current.pod_id != server.pod_id {
+        return Err(AppError::BadRequest(
+            "the pod can only be switched through the change pod action".to_string(),
+        ));
+    }
+    let current_pod = pod::get_pod_by_id(&mut conn, current.pod_id).await?;
+    provisioning::check_image(&current_pod, &server.image)?;
+    server.env_vars = if user.staff {
+        variables::resolve(&current_pod.variables, &server.env_vars)?
+    } else {
+        variables::resolve_for_owner(&current_pod.variables, &current.env_vars, &server.env_vars)?
+    };
+    variables::validate_startup_command(&current_pod.variables, &server.startup_command)?;
     let server = server::update_server(&mut conn, server).await?;
-    let node = get_node_from_server_id(server.id, &mut conn).await?;
+    reconciler::sync_now(&state.db, server.id).await?;
+    let server = server::get_server_by_id(&mut conn, server.id).await?;
     let server = server_model_to_server(server, &mut conn).await?;
-    let res = reqwest::Client::new()
-        .put(format!("http://{}/server", node.fqdn))
-        .json(&server)
-        .send()
-        .await?;
-    if res.status() != StatusCode::OK {
-        return Err(AppError::NodeError(res.text().await?));
+    Ok(Json(for_user(&user, server, &mut conn).await?))
+}
+
+/// Hides the variables of a server its owner may not see.
+async fn for_user(
+    user: &User,
+    mut server: Server,
+    conn: &mut PgConnection,
+) -> Result<Server, AppError> {
+    if !user.staff {
+        let pod = pod::get_pod_by_id(conn, server.pod_id).await?;
+        variables::visible_to_owner(&pod.variables, &mut server.env_vars);
     }
-    Ok(Json(server))
+    Ok(server)
 }
 
 #[utoipa::path(
     put,
     path = "/staff",
-    responses((status = OK, body = Server), (status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = OK, body = Server), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::SERVER_TAG
 )]
 pub async fn update_server_staff(
-    DbConn(mut conn): DbConn,
+    State(state): State<AppState>,
     Json(server): Json<UpdateServerStaff>,
 ) -> Result<Json<Server>, AppError> {
-    let server = server::update_server_staff(&mut conn, server).await?;
-    let node = get_node_from_server_id(server.id, &mut conn).await?;
-    let server = server_model_to_server(server, &mut conn).await?;
-    let res = reqwest::Client::new()
-        .put(format!("http://{}/server", node.fqdn))
-        .json(&server)
-        .send()
-        .await?;
-    if res.status() != StatusCode::OK {
-        return Err(AppError::NodeError(res.text().await?));
-    }
+    let server = provisioning::update_server_staff(&state.db, server).await?;
+    Ok(Json(server))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/suspend",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Server), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn suspend_server(
+    Path(id): Path<i32>,
+    session: AuthSession,
+    State(state): State<AppState>,
+    Json(suspension): Json<SuspendServer>,
+) -> Result<Json<Server>, AppError> {
+    let user = session.user.unwrap();
+    let server = provisioning::set_suspension(&state.db, id, Some(suspension), user.id).await?;
     Ok(Json(server))
 }
 
+#[utoipa::path(
+    post,
+    path = "/{id}/unsuspend",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Server), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn unsuspend_server(
+    Path(id): Path<i32>,
+    session: AuthSession,
+    State(state): State<AppState>,
+) -> Result<Json<Server>, AppError> {
+    let user = session.user.unwrap();
+    let server = provisioning::set_suspension(&state.db, id, None, user.id).await?;
+    Ok(Json(server))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/suspensions",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = [ServerSuspension]), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn get_suspensions(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Vec<ServerSuspension>>, AppError> {
+    let suspensions = server::get_suspensions_by_server_id(&mut conn, id).await?;
+    Ok(Json(suspensions.into_iter().map(Into::into).collect()))
+}
+
 #[utoipa::path(
     delete,
     path = "/{id}",
@@ -188,17 +296,43 @@ pub async fn update_server_staff(
     responses((status = OK), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::SERVER_TAG
 )]
-pub async fn delete_server(Path(id): Path<i32>, DbConn(mut conn): DbConn) -> Result<(), AppError> {
-    let node = get_node_from_server_id(id, &mut conn).await?;
-    let res = reqwest::Client::new()
-        .delete(format!("http://{}/server/{}", node.fqdn, id))
-        .send()
-        .await?;
-    if res.status() != StatusCode::OK {
-        return Err(AppError::NodeError(res.text().await?));
-    }
-    server::delete_server(&mut conn, id).await?;
-    Ok(())
+pub async fn delete_server(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<(), AppError> {
+    provisioning::delete_server(&state.db, id).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/transfer",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Option<ServerTransfer>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn get_transfer(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Option<ServerTransfer>>, AppError> {
+    let transfer = transfer::get_latest_transfer_by_server_id(&mut conn, id).await?;
+    Ok(Json(transfer.map(Into::into)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/transfer",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = ACCEPTED, body = ServerTransfer), (status = CONFLICT, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn transfer_server(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Json(ctransfer): Json<CreateTransfer>,
+) -> Result<(StatusCode, Json<ServerTransfer>), AppError> {
+    // the volume is sent in the background, progress is reported through get_transfer
+    let transfer = start_transfer(&state.db, id, ctransfer).await?;
+    Ok((StatusCode::ACCEPTED, Json(transfer)))
 }
 
 #[utoipa::path(
@@ -214,59 +348,165 @@ pub async fn status(
     DbConn(mut conn): DbConn,
 ) -> Result<Json<ServerStatus>, AppError> {
     let node = get_node_from_server_id(id, &mut conn).await?;
-    let res = reqwest::get(format!("http://{}/server/{}", node.fqdn, id)).await?;
-    if res.status() != StatusCode::OK {
-        return Err(AppError::NodeError(res.text().await?));
-    }
-    let status: ServerStatus = res.json().await?;
+    let status = agent::get_status(&node, id).await?;
     Ok(Json(status))
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}/status/report",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = ServerStatusReport), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn status_report(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<ServerStatusReport>, AppError> {
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    let report = agent::get_status_report(&node, id).await?;
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/crash",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Option<CrashReport>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn crash_report(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Option<CrashReport>>, AppError> {
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    let report = agent::get_crash_report(&node, id).await?;
+    Ok(Json(report))
+}
+
 #[utoipa::path(
     post,
     path = "/{id}/signal",
     params(("id" = i32, Path, description = "server id")),
-    responses((status = OK, body = ()), (status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = OK, body = ()), (status = FORBIDDEN, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::SERVER_TAG
 )]
 pub async fn signal(
     Path(id): Path<i32>,
+    session: AuthSession,
     DbConn(mut conn): DbConn,
     Json(body): Json<ServerSignal>,
 ) -> Result<(), AppError> {
+    ensure_not_suspended(&session, &mut conn, id).await?;
+    // a server started after its volume was sent would diverge from the copy
+    ensure_not_transferring(&mut conn, id).await?;
     let node = get_node_from_server_id(id, &mut conn).await?;
-    let res = reqwest::Client::new()
-        .post(format!("http://{}/server/{}/signal", node.fqdn, id))
-        .json(&body)
-        .send()
-        .await?;
-    if res.status() != StatusCode::OK {
-        return Err(AppError::NodeError(res.text().await?));
-    }
-    Ok(())
+    agent::send_signal(&node, id, body).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/command",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = ()), (status = FORBIDDEN, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn command(
+    Path(id): Path<i32>,
+    session: AuthSession,
+    DbConn(mut conn): DbConn,
+    Json(body): Json<ConsoleCommand>,
+) -> Result<(), AppError> {
+    ensure_not_suspended(&session, &mut conn, id).await?;
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    agent::send_command(&node, id, &body.command).await
 }
 
-// TODO DO THIS PROPERLY
+#[utoipa::path(
+    get,
+    path = "/{id}/install",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Option<InstallReport>), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn install_report(
+    Path(id): Path<i32>,
+    DbConn(mut conn): DbConn,
+) -> Result<Json<Option<InstallReport>>, AppError> {
+    let node = get_node_from_server_id(id, &mut conn).await?;
+    Ok(Json(agent::get_install_report(&node, id).await?))
+}
 
 #[utoipa::path(
     post,
     path = "/{id}/install",
     params(("id" = i32, Path, description = "server id")),
-    responses((status = OK, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    responses((status = ACCEPTED), (status = BAD_REQUEST, body = String), (status = CONFLICT, body = String), (status = FORBIDDEN, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
     tag = super::SERVER_TAG
 )]
 pub async fn install(
     Path(id): Path<i32>,
+    session: AuthSession,
     DbConn(mut conn): DbConn,
-    Json(body): Json<serde_json::Value>,
-) -> Result<impl IntoResponse, AppError> {
+    Json(options): Json<InstallOptions>,
+) -> Result<StatusCode, AppError> {
+    ensure_not_suspended(&session, &mut conn, id).await?;
+    ensure_not_transferring(&mut conn, id).await?;
     let node = get_node_from_server_id(id, &mut conn).await?;
-    let status = reqwest::Client::new()
-        .post(format!("http://{}/server/{}/install", node.fqdn, id))
-        .json(&body)
-        .send()
-        .await?
-        .text()
-        .await?;
-    Ok((StatusCode::OK, status))
+    placement::check_not_in_maintenance(&node)?;
+    // the installer runs in the background, progress is reported through the status route
+    agent::install_server(&node, id, options).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}/pod",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = Server), (status = BAD_REQUEST, body = String), (status = CONFLICT, body = String), (status = FORBIDDEN, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn change_pod(
+    Path(id): Path<i32>,
+    session: AuthSession,
+    State(state): State<AppState>,
+    DbConn(mut conn): DbConn,
+    Json(change): Json<ChangePod>,
+) -> Result<Json<Server>, AppError> {
+    ensure_not_suspended(&session, &mut conn, id).await?;
+    let user = session.user.unwrap();
+    let server = provisioning::change_pod(&state.db, id, change).await?;
+    Ok(Json(for_user(&user, server, &mut conn).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/startup/preview",
+    params(("id" = i32, Path, description = "server id")),
+    responses((status = OK, body = StartupPreview), (status = BAD_REQUEST, body = String), (status = INTERNAL_SERVER_ERROR, body = String)),
+    tag = super::SERVER_TAG
+)]
+pub async fn preview_startup(
+    Path(id): Path<i32>,
+    session: AuthSession,
+    DbConn(mut conn): DbConn,
+    Json(preview): Json<PreviewStartup>,
+) -> Result<Json<StartupPreview>, AppError> {
+    let user = session.user.unwrap();
+    let server = server::get_server_by_id(&mut conn, id).await?;
+    let mut server = server_model_to_server(server, &mut conn).await?;
+    let pod = pod::get_pod_by_id(&mut conn, server.pod_id).await?;
+    if let Some(startup_command) = preview.startup_command {
+        variables::validate_startup_command(&pod.variables, &startup_command)?;
+        server.startup_command = startup_command;
+    }
+    if !user.staff {
+        variables::mask_for_owner(&pod.variables, &mut server.env_vars);
+    }
+    let args = startup::render(&server)?;
+    Ok(Json(StartupPreview {
+        command: startup::join(&args),
+        template: server.startup_command,
+        args,
+    }))
 }
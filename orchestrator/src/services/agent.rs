@@ -0,0 +1,181 @@
+use std::sync::OnceLock;
+
+use common::{
+    agent_client::{Agent, AgentClient, AgentClientConfig, AgentError},
+    agent_types::{
+        AppliedGeneration, CrashReport, DriftReport, InstallOptions, InstallReport, PruneResult,
+        RestoreSnapshot, RetentionPolicy, ServerSignal, ServerStatus, ServerStatusReport, Snapshot,
+        TransferProgress, TransferTarget, VerifyReport,
+    },
+    orch_types::Server,
+};
+
+use crate::{
+    models::node::NodeModel,
+    utils::{auth, AppError},
+};
+
+static CLIENT: OnceLock<AgentClient> = OnceLock::new();
+static KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Sets up the client all agents are called with. Agents are reached over HTTPS
+/// if `NERDPANEL_AGENT_TLS` is `true`, trusting the PEM certificate at
+/// `NERDPANEL_AGENT_CA` if they use a self signed one. Node tokens are derived
+/// from `NERDPANEL_AGENT_KEY`, which has to stay the same for them to remain valid.
+pub fn init() {
+    let key = std::env::var("NERDPANEL_AGENT_KEY").expect("NERDPANEL_AGENT_KEY is not set.");
+    KEY.get_or_init(|| key.into_bytes());
+    let tls = std::env::var("NERDPANEL_AGENT_TLS").is_ok_and(|tls| tls == "true");
+    if !tls {
+        tracing::warn!(
+            "NERDPANEL_AGENT_TLS is not set, node tokens are sent to agents in plain text"
+        );
+    }
+    let root_certificate = std::env::var("NERDPANEL_AGENT_CA")
+        .ok()
+        .map(|path| std::fs::read(path).expect("Failed to read the agent CA certificate."));
+    let client = AgentClient::new(AgentClientConfig {
+        tls,
+        root_certificate,
+        ..Default::default()
+    })
+    .expect("Failed to set up the agent client.");
+    CLIENT.get_or_init(|| client);
+}
+
+fn client() -> &'static AgentClient {
+    CLIENT.get().expect("agent client is not set up")
+}
+
+/// The token of a node, derived from the agent key and the node's salt.
+pub fn node_token(id: i32, salt: &str) -> String {
+    let key = KEY.get().expect("agent key is not set up");
+    auth::derive_node_token(key, id, salt)
+}
+
+/// The node's agent, which authenticates the orchestrator by the node's token.
+fn agent(node: &NodeModel) -> Agent {
+    let token = node
+        .token_salt
+        .as_deref()
+        .map(|salt| node_token(node.id, salt));
+    client().agent(&client().url(&node.fqdn), token.as_deref())
+}
+
+pub async fn get_status(node: &NodeModel, id: i32) -> Result<ServerStatus, AppError> {
+    Ok(agent(node).get_status(id).await?)
+}
+
+/// Status of a server together with its sidecars.
+pub async fn get_status_report(node: &NodeModel, id: i32) -> Result<ServerStatusReport, AppError> {
+    Ok(agent(node).get_status_report(id).await?)
+}
+
+pub async fn get_crash_report(node: &NodeModel, id: i32) -> Result<Option<CrashReport>, AppError> {
+    Ok(agent(node).get_crash_report(id).await?)
+}
+
+pub async fn send_signal(node: &NodeModel, id: i32, signal: ServerSignal) -> Result<(), AppError> {
+    Ok(agent(node).send_signal(id, signal).await?)
+}
+
+/// Starts the pod's installer, progress is reported through the server status.
+pub async fn install_server(
+    node: &NodeModel,
+    id: i32,
+    options: InstallOptions,
+) -> Result<(), AppError> {
+    Ok(agent(node).install_server(id, options).await?)
+}
+
+pub async fn get_install_report(
+    node: &NodeModel,
+    id: i32,
+) -> Result<Option<InstallReport>, AppError> {
+    Ok(agent(node).get_install_report(id).await?)
+}
+
+pub async fn send_command(node: &NodeModel, id: i32, command: &str) -> Result<(), AppError> {
+    Ok(agent(node).send_command(id, command).await?)
+}
+
+pub async fn create_snapshot(node: &NodeModel, id: i32) -> Result<Snapshot, AppError> {
+    Ok(agent(node).create_snapshot(id).await?)
+}
+
+pub async fn restore_snapshot(
+    node: &NodeModel,
+    id: i32,
+    snapshot: &str,
+    options: &RestoreSnapshot,
+) -> Result<(), AppError> {
+    Ok(agent(node).restore_snapshot(id, snapshot, options).await?)
+}
+
+/// Deletes a snapshot, one that is already gone counts as deleted.
+pub async fn delete_snapshot(node: &NodeModel, id: i32, snapshot: &str) -> Result<(), AppError> {
+    match agent(node).delete_snapshot(id, snapshot).await {
+        Err(AgentError::NotFound(_)) => Ok(()),
+        res => Ok(res?),
+    }
+}
+
+pub async fn prune_snapshots(
+    node: &NodeModel,
+    id: i32,
+    policy: &RetentionPolicy,
+) -> Result<PruneResult, AppError> {
+    Ok(agent(node).prune_snapshots(id, policy).await?)
+}
+
+pub async fn verify_backups(node: &NodeModel) -> Result<VerifyReport, AppError> {
+    Ok(agent(node).verify_backups().await?)
+}
+
+pub async fn get_drift_report(node: &NodeModel) -> Result<Option<DriftReport>, AppError> {
+    Ok(agent(node).get_drift_report().await?)
+}
+
+pub async fn reconcile(node: &NodeModel) -> Result<DriftReport, AppError> {
+    Ok(agent(node).reconcile().await?)
+}
+
+/// Creates or updates a server on its node, the agent handles both the same way.
+pub async fn apply_server(node: &NodeModel, server: &Server) -> Result<(), AppError> {
+    Ok(agent(node).apply_server(server).await?)
+}
+
+pub async fn delete_server(node: &NodeModel, id: i32) -> Result<(), AppError> {
+    Ok(agent(node).delete_server(id).await?)
+}
+
+pub async fn get_applied_generations(node: &NodeModel) -> Result<Vec<AppliedGeneration>, AppError> {
+    Ok(agent(node).get_applied_generations().await?)
+}
+
+/// Lets the destination of a transfer accept the volume sent with `token`.
+pub async fn expect_transfer(node: &NodeModel, id: i32, token: &str) -> Result<(), AppError> {
+    Ok(agent(node).expect_transfer(id, token).await?)
+}
+
+/// Has the source of a transfer stop the server and send its volume to the
+/// destination, progress is polled with `get_transfer_progress`.
+pub async fn send_transfer(
+    node: &NodeModel,
+    id: i32,
+    destination: &NodeModel,
+    token: &str,
+) -> Result<TransferProgress, AppError> {
+    let target = TransferTarget {
+        url: client().url(&destination.fqdn),
+        token: token.to_string(),
+    };
+    Ok(agent(node).send_transfer(id, &target).await?)
+}
+
+pub async fn get_transfer_progress(
+    node: &NodeModel,
+    id: i32,
+) -> Result<Option<TransferProgress>, AppError> {
+    Ok(agent(node).get_transfer_progress(id).await?)
+}
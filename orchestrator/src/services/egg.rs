@@ -0,0 +1,613 @@
+use std::collections::BTreeMap;
+
+use common::{
+    orch_types::{
+        ConfigFile, ConfigFormat, ConfigValue, Hardening, Image, NodeRestrictions, PodVariable,
+        StopMethod, VariableRule, VariableType,
+    },
+    startup,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{models::pod::CreatePod, utils::AppError};
+
+// Pterodactyl eggs describe a game server much like a pod does. Their rules
+// are Laravel validation rules, which are mapped onto variable rules as far as
+// they have an equivalent and dropped otherwise.
+
+/// A Pterodactyl egg, `PTDL_v1` or `PTDL_v2`. Fields a pod has no use for are
+/// ignored.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Egg {
+    #[serde(default)]
+    pub meta: Option<EggMeta>,
+    pub name: String,
+    /// Display name to image, `PTDL_v2`.
+    #[serde(default)]
+    pub docker_images: BTreeMap<String, String>,
+    /// `PTDL_v1`.
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub image: Option<String>,
+    pub startup: String,
+    #[serde(default)]
+    pub config: EggConfig,
+    #[serde(default)]
+    pub scripts: EggScripts,
+    #[serde(default)]
+    pub variables: Vec<EggVariable>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EggMeta {
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+pub struct EggConfig {
+    /// JSON encoded, holds the `done` pattern(s).
+    #[serde(default)]
+    pub startup: Option<String>,
+    /// A console command, or `^C` for SIGINT.
+    #[serde(default)]
+    pub stop: Option<String>,
+    /// JSON encoded, file path to parser and the values to set.
+    #[serde(default)]
+    pub files: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+pub struct EggScripts {
+    #[serde(default)]
+    pub installation: Option<EggInstallation>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EggInstallation {
+    pub script: String,
+    pub container: String,
+    pub entrypoint: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct EggVariable {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub env_variable: String,
+    #[serde(default)]
+    pub default_value: Option<String>,
+    #[serde(default)]
+    pub user_viewable: bool,
+    #[serde(default)]
+    pub user_editable: bool,
+    /// Laravel validation rules, e.g. `required|string|max:20`.
+    #[serde(default)]
+    pub rules: String,
+}
+
+pub fn egg_to_pod(egg: Egg) -> Result<CreatePod, AppError> {
+    if let Some(meta) = &egg.meta {
+        if !meta.version.starts_with("PTDL_") {
+            return Err(AppError::BadRequest(format!(
+                "unsupported egg version {}",
+                meta.version
+            )));
+        }
+    }
+
+    let images: Vec<Image> = egg
+        .docker_images
+        .values()
+        .chain(&egg.images)
+        .chain(&egg.image)
+        .map(|image| parse_image(image))
+        .collect();
+    if images.is_empty() {
+        return Err(AppError::BadRequest("the egg has no images".to_string()));
+    }
+    let (installer_image, installer_script, installer_entrypoint) = match egg.scripts.installation {
+        Some(installation) => (
+            installation.container,
+            Some(installation.script.replace("\r\n", "\n")),
+            installation.entrypoint,
+        ),
+        None => (String::new(), None, "bash".to_string()),
+    };
+
+    let variables: Vec<PodVariable> = egg.variables.into_iter().map(variable).collect();
+    let config_files = egg
+        .config
+        .files
+        .as_deref()
+        .map_or(vec![], |files| config_files(files, &variables));
+    Ok(CreatePod {
+        name: egg.name,
+        images,
+        startup_command: startup_command(&egg.startup),
+        installer_image,
+        installer_script,
+        installer_entrypoint,
+        variables,
+        config_files,
+        sidecars: vec![],
+        // egg images expect to run as their own user
+        hardening: Hardening {
+            non_root_user: false,
+            ..Default::default()
+        },
+        stop_method: stop_method(egg.config.stop.as_deref()),
+        stop_timeout: 30,
+        done_regex: egg.config.startup.as_deref().and_then(done_regex),
+        category_id: None,
+        node_restrictions: NodeRestrictions::default(),
+    })
+}
+
+/// Splits `name:tag`, a colon in the registry's host is no tag.
+fn parse_image(image: &str) -> Image {
+    let name_start = image.rfind('/').map_or(0, |slash| slash + 1);
+    match image[name_start..].rfind(':') {
+        Some(colon) => Image {
+            name: image[..name_start + colon].to_string(),
+            tag: image[name_start + colon + 1..].to_string(),
+        },
+        None => Image {
+            name: image.to_string(),
+            tag: "latest".to_string(),
+        },
+    }
+}
+
+/// Renames the placeholders of eggs to the ones of pods.
+fn placeholders(template: &str) -> String {
+    let placeholder = Regex::new(r"\{\{\s*([^}]*?)\s*\}\}").unwrap();
+    placeholder
+        .replace_all(template, |captures: &regex::Captures| {
+            let name = &captures[1];
+            let name = match name {
+                "server.build.memory" => "SERVER_MEMORY",
+                "server.build.default.port" => "SERVER_PORT",
+                "server.build.default.ip" => "SERVER_IP",
+                name => name
+                    .strip_prefix("server.build.env.")
+                    .or_else(|| name.strip_prefix("env."))
+                    .unwrap_or(name),
+            };
+            format!("{{{{{}}}}}", name)
+        })
+        .into_owned()
+}
+
+/// Eggs run their startup through a shell and may use its syntax, which only
+/// works here when the command runs in one.
+fn startup_command(startup: &str) -> String {
+    let startup = placeholders(startup);
+    let shell = ["&&", "||", ";", "|", "$", ">", "<", "`"]
+        .iter()
+        .any(|syntax| startup.contains(syntax));
+    if shell {
+        startup::join(&["sh".to_string(), "-c".to_string(), startup])
+    } else {
+        startup
+    }
+}
+
+fn stop_method(stop: Option<&str>) -> StopMethod {
+    match stop.map(str::trim) {
+        None | Some("") => StopMethod::Docker,
+        Some(stop) if stop.starts_with('^') => StopMethod::Signal {
+            signal: "SIGINT".to_string(),
+        },
+        Some(stop) => StopMethod::Command {
+            command: stop.to_string(),
+        },
+    }
+}
+
+/// Turns the `done` string or strings of the egg's startup config into a regex.
+fn done_regex(config: &str) -> Option<String> {
+    let config: serde_json::Value = serde_json::from_str(config).ok()?;
+    let done: Vec<&str> = match config.get("done")? {
+        serde_json::Value::String(done) => vec![done],
+        serde_json::Value::Array(done) => done.iter().filter_map(|done| done.as_str()).collect(),
+        _ => return None,
+    };
+    let done: Vec<String> = done
+        .into_iter()
+        .filter(|done| !done.is_empty())
+        .map(regex::escape)
+        .collect();
+    (!done.is_empty()).then(|| done.join("|"))
+}
+
+/// Maps the egg's config file parsers. Files in formats pods can not patch and
+/// values using placeholders pods do not have are dropped.
+fn config_files(files: &str, variables: &[PodVariable]) -> Vec<ConfigFile> {
+    let files: BTreeMap<String, serde_json::Value> = match serde_json::from_str(files) {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable egg config files: {}", e);
+            return vec![];
+        }
+    };
+    let keys: Vec<&str> = variables
+        .iter()
+        .map(|variable| variable.key.as_str())
+        .collect();
+    files
+        .into_iter()
+        .filter_map(|(path, file)| {
+            let format = match file.get("parser")?.as_str()? {
+                "properties" => ConfigFormat::Properties,
+                "ini" => ConfigFormat::Ini,
+                "yaml" | "yml" => ConfigFormat::Yaml,
+                "json" => ConfigFormat::Json,
+                parser => {
+                    tracing::warn!("Dropping config file {} with parser {}", path, parser);
+                    return None;
+                }
+            };
+            let values = file
+                .get("find")?
+                .as_object()?
+                .iter()
+                .filter_map(|(key, value)| {
+                    let value = match value {
+                        serde_json::Value::String(value) => placeholders(value),
+                        serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                            value.to_string()
+                        }
+                        _ => return None,
+                    };
+                    // array indexes and wildcards of egg keys can not be expressed
+                    let supported =
+                        !key.contains(['[', '*']) && startup::validate_value(&value, &keys).is_ok();
+                    if !supported {
+                        tracing::warn!("Dropping {} of config file {}", key, path);
+                        return None;
+                    }
+                    Some(ConfigValue {
+                        key: key.clone(),
+                        value,
+                    })
+                })
+                .collect();
+            Some(ConfigFile {
+                path,
+                format,
+                values,
+            })
+        })
+        .collect()
+}
+
+fn variable(egg: EggVariable) -> PodVariable {
+    let mut kind = VariableType::String;
+    let mut required = false;
+    let mut rules = vec![];
+    let (mut min, mut max) = (None, None);
+    for rule in split_rules(&egg.rules) {
+        let (name, args) = rule.split_once(':').unwrap_or((&rule, ""));
+        match name {
+            "required" => required = true,
+            "numeric" => kind = VariableType::Number,
+            "integer" => {
+                kind = VariableType::Number;
+                rules.push(VariableRule::Regex {
+                    pattern: "-?[0-9]+".to_string(),
+                });
+            }
+            // Laravel also takes 1 and 0, which eggs use as defaults
+            "boolean" => rules.push(VariableRule::Enum {
+                values: ["true", "false", "1", "0"].map(String::from).to_vec(),
+            }),
+            "in" => rules.push(VariableRule::Enum {
+                values: args.split(',').map(String::from).collect(),
+            }),
+            "regex" => {
+                if let Some(pattern) = laravel_regex(args) {
+                    rules.push(VariableRule::Regex { pattern });
+                }
+            }
+            "min" => min = args.parse::<f64>().ok(),
+            "max" => max = args.parse::<f64>().ok(),
+            "between" => {
+                if let Some((low, high)) = args.split_once(',') {
+                    min = low.parse().ok();
+                    max = high.parse().ok();
+                }
+            }
+            _ => {}
+        }
+    }
+    if min.is_some() || max.is_some() {
+        rules.push(match kind {
+            VariableType::Number => VariableRule::Range { min, max },
+            // for strings the bounds are lengths
+            _ => VariableRule::Regex {
+                pattern: format!(
+                    "(?s).{{{},{}}}",
+                    min.map_or(0, |min| min as u64),
+                    max.map_or(String::new(), |max| (max as u64).to_string())
+                ),
+            },
+        });
+    }
+
+    PodVariable {
+        key: egg.env_variable,
+        name: egg.name,
+        description: egg.description,
+        default: egg.default_value.unwrap_or_default(),
+        kind,
+        required,
+        rules,
+        user_viewable: egg.user_viewable,
+        user_editable: egg.user_editable,
+    }
+}
+
+/// Splits rules on `|`, except within a regex rule.
+fn split_rules(rules: &str) -> Vec<String> {
+    let mut split: Vec<String> = vec![];
+    let mut in_regex = false;
+    for part in rules.split('|') {
+        match split.last_mut() {
+            Some(last) if in_regex => {
+                last.push('|');
+                last.push_str(part);
+            }
+            _ => split.push(part.trim().to_string()),
+        }
+        let last = split.last().map(String::as_str).unwrap_or_default();
+        in_regex = last.starts_with("regex:") && !closes_regex(last);
+    }
+    split
+}
+
+/// Whether a `regex:/pattern/flags` rule has its closing delimiter. Only `/` is
+/// tracked, a rule with another delimiter ends at the next `|`.
+fn closes_regex(rule: &str) -> bool {
+    let body = rule.strip_prefix("regex:").unwrap_or(rule);
+    match body.strip_prefix('/') {
+        Some(body) => match body.trim_end_matches(char::is_alphabetic).strip_suffix('/') {
+            // an odd number of backslashes escapes the slash
+            Some(pattern) => pattern.chars().rev().take_while(|c| *c == '\\').count() % 2 == 0,
+            None => false,
+        },
+        None => true,
+    }
+}
+
+/// Turns a `/pattern/flags` regex into one the regex crate understands.
+///
+/// Laravel searches the value for the pattern while variable rules must match
+/// the whole value, so the pattern is padded to match anywhere.
+fn laravel_regex(regex: &str) -> Option<String> {
+    let regex = regex.strip_prefix('/')?;
+    let end = regex.rfind('/')?;
+    let (pattern, flags) = (&regex[..end], &regex[end + 1..]);
+    let flags: String = flags
+        .chars()
+        .filter(|flag| "imsx".contains(*flag))
+        .collect();
+    // a comment of verbose mode would swallow the closing parenthesis
+    let newline = if flags.contains('x') { "\n" } else { "" };
+    let pattern = if flags.is_empty() {
+        format!("(?s:.*?)(?:{})(?s:.*)", pattern)
+    } else {
+        format!("(?s:.*?)(?{}:{}{})(?s:.*)", flags, pattern, newline)
+    };
+    // PCRE only features such as lookarounds have no equivalent, the rule is dropped
+    match regex::Regex::new(&pattern) {
+        Ok(_) => Some(pattern),
+        Err(e) => {
+            tracing::warn!("Dropping egg rule regex {}: {}", pattern, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::orch_types::EnvVar;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::variables;
+
+    fn paper() -> CreatePod {
+        let egg: Egg = serde_json::from_str(include_str!("testdata/egg-paper.json")).unwrap();
+        egg_to_pod(egg).unwrap()
+    }
+
+    fn env_var(key: &str, value: &str) -> EnvVar {
+        EnvVar {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn matches(pattern: &str, value: &str) -> bool {
+        Regex::new(&format!("^(?:{})$", pattern))
+            .unwrap()
+            .is_match(value)
+    }
+
+    #[test]
+    fn imports_an_egg() {
+        let pod = paper();
+        assert_eq!(pod.name, "Paper");
+        assert_eq!(
+            pod.startup_command,
+            "java -Xms128M -XX:MaxRAMPercentage=95.0 -Dterminal.jline=false -Dterminal.ansi=true -jar {{SERVER_JARFILE}}"
+        );
+        let images: Vec<String> = pod
+            .images
+            .iter()
+            .map(|image| format!("{}:{}", image.name, image.tag))
+            .collect();
+        assert_eq!(
+            images,
+            [
+                "ghcr.io/pterodactyl/yolks:java_11",
+                "ghcr.io/pterodactyl/yolks:java_17",
+                "ghcr.io/pterodactyl/yolks:java_21",
+                "ghcr.io/pterodactyl/yolks:java_8",
+            ]
+        );
+        assert_eq!(pod.installer_image, "ghcr.io/pterodactyl/installers:alpine");
+        assert_eq!(pod.installer_entrypoint, "ash");
+        assert!(!pod.installer_script.unwrap().contains('\r'));
+        assert_eq!(
+            serde_json::to_value(&pod.stop_method).unwrap(),
+            json!({"type": "command", "command": "stop"})
+        );
+        assert_eq!(pod.done_regex.as_deref(), Some(r"\)! For help, type "));
+        assert_eq!(
+            serde_json::to_value(&pod.config_files).unwrap(),
+            json!([{
+                "path": "server.properties",
+                "format": "properties",
+                "values": [
+                    {"key": "server-ip", "value": "0.0.0.0"},
+                    {"key": "server-port", "value": "{{SERVER_PORT}}"},
+                    {"key": "query.port", "value": "{{SERVER_PORT}}"},
+                ],
+            }])
+        );
+        startup::validate(&pod.startup_command, &["SERVER_JARFILE"]).unwrap();
+    }
+
+    #[test]
+    fn imports_egg_variables_and_rules() {
+        let pod = paper();
+        let keys: Vec<&str> = pod
+            .variables
+            .iter()
+            .map(|variable| variable.key.as_str())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "MINECRAFT_VERSION",
+                "SERVER_JARFILE",
+                "DL_PATH",
+                "BUILD_NUMBER"
+            ]
+        );
+        let version = &pod.variables[0];
+        assert!(!version.required && version.user_viewable && !version.user_editable);
+        assert_eq!(
+            serde_json::to_value(&version.rules).unwrap(),
+            json!([{"type": "regex", "pattern": "(?s).{0,20}"}])
+        );
+        assert!(pod.variables[1].required);
+
+        variables::validate_pod_variables(&pod.variables).unwrap();
+        let resolved = variables::resolve(
+            &pod.variables,
+            &[env_var("SERVER_JARFILE", "paper-1.21.jar")],
+        )
+        .unwrap();
+        assert_eq!(resolved[1].value, "paper-1.21.jar");
+        assert_eq!(resolved[0].value, "latest");
+        assert!(
+            variables::resolve(&pod.variables, &[env_var("SERVER_JARFILE", "paper.zip")]).is_err()
+        );
+        assert!(
+            variables::resolve(&pod.variables, &[env_var("BUILD_NUMBER", &"1".repeat(21))])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let egg: Egg = serde_json::from_value(json!({
+            "meta": {"version": "other"},
+            "name": "x",
+            "image": "alpine",
+            "startup": "run",
+        }))
+        .unwrap();
+        assert!(egg_to_pod(egg).is_err());
+    }
+
+    #[test]
+    fn wraps_shell_startups_in_a_shell() {
+        assert_eq!(
+            startup_command("./run --port {{server.build.default.port}} && echo {{ env.NAME }}"),
+            "sh -c './run --port {{SERVER_PORT}} && echo {{NAME}}'"
+        );
+        assert_eq!(
+            startup_command("./run -Xmx{{SERVER_MEMORY}}M"),
+            "./run -Xmx{{SERVER_MEMORY}}M"
+        );
+    }
+
+    #[test]
+    fn parses_images() {
+        let image = parse_image("registry.local:5000/team/game:1.2");
+        assert_eq!(
+            (image.name.as_str(), image.tag.as_str()),
+            ("registry.local:5000/team/game", "1.2")
+        );
+        let image = parse_image("registry.local:5000/game");
+        assert_eq!(
+            (image.name.as_str(), image.tag.as_str()),
+            ("registry.local:5000/game", "latest")
+        );
+        let image = parse_image("alpine:3");
+        assert_eq!((image.name.as_str(), image.tag.as_str()), ("alpine", "3"));
+    }
+
+    #[test]
+    fn splits_rules_outside_of_regexes() {
+        assert_eq!(
+            split_rules("required| string |max:20"),
+            ["required", "string", "max:20"]
+        );
+        assert_eq!(
+            split_rules("required|regex:/^(a|b)$/i|max:5"),
+            ["required", "regex:/^(a|b)$/i", "max:5"]
+        );
+        assert_eq!(
+            split_rules(r"regex:/a\/|b/|string"),
+            [r"regex:/a\/|b/", "string"]
+        );
+        assert_eq!(
+            split_rules(r"regex:/a\\/|string"),
+            [r"regex:/a\\/", "string"]
+        );
+        // delimiters other than a slash are not tracked
+        assert_eq!(split_rules("regex:#a#|string"), ["regex:#a#", "string"]);
+        assert_eq!(split_rules("regex:éa|string"), ["regex:éa", "string"]);
+    }
+
+    #[test]
+    fn laravel_regexes_match_anywhere() {
+        let pattern = laravel_regex("/[0-9]+/").unwrap();
+        assert!(matches(&pattern, "version 12 beta"));
+        assert!(!matches(&pattern, "none"));
+
+        let pattern = laravel_regex(r"/^([\w\d._-]+)(\.jar)$/").unwrap();
+        assert!(matches(&pattern, "server.jar"));
+        assert!(!matches(&pattern, "server.jar.zip"));
+
+        let pattern = laravel_regex("/^abc$/im").unwrap();
+        assert!(matches(&pattern, "x\nABC\ny"));
+
+        let pattern = laravel_regex("/a b # comment/x").unwrap();
+        assert!(matches(&pattern, "xaby"));
+    }
+
+    #[test]
+    fn drops_unsupported_laravel_regexes() {
+        assert_eq!(laravel_regex("/(?=a)b/"), None);
+        assert_eq!(laravel_regex("no delimiters"), None);
+    }
+}
@@ -1 +1,10 @@
+pub mod agent;
 pub mod database;
+pub mod egg;
+pub mod placement;
+pub mod provisioning;
+pub mod reconciler;
+pub mod rollout;
+pub mod scheduler;
+pub mod transfer;
+pub mod variables;
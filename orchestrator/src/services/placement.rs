@@ -0,0 +1,287 @@
+use common::orch_types::{NodeRestrictions, NodeStatus, PlacementStrategy};
+use sqlx::PgConnection;
+
+use crate::{
+    models::{
+        node::{self, NodeAllocation, NodeModel},
+        pod::PodModel,
+    },
+    utils::{node_status, AppError},
+};
+
+/// Resources a server asks its node for.
+#[derive(Clone, Copy)]
+pub struct Request {
+    pub memory: i64,
+    pub cpu: i64,
+    pub disk: i64,
+}
+
+impl Request {
+    pub fn new(memory_limit: Option<i32>, cpu_limit: Option<i32>, disk_limit: Option<i32>) -> Self {
+        Self {
+            memory: memory_limit.unwrap_or(0).into(),
+            cpu: cpu_limit.unwrap_or(0).into(),
+            disk: disk_limit.unwrap_or(0).into(),
+        }
+    }
+}
+
+/// How much of a resource may be allocated, `None` when it is not limited.
+fn capacity(total: Option<i32>, overallocate: i32) -> Option<i64> {
+    total.map(|total| i64::from(total) * (100 + i64::from(overallocate)) / 100)
+}
+
+/// Share of the node's capacity in use once the request is placed on it. The
+/// fullest resource counts, unlimited resources are ignored.
+fn usage(node: &NodeModel, allocation: NodeAllocation, request: Request) -> f64 {
+    [
+        (
+            node.memory_total,
+            node.memory_overallocate,
+            allocation.memory + request.memory,
+        ),
+        (
+            node.cpu_total,
+            node.cpu_overallocate,
+            allocation.cpu + request.cpu,
+        ),
+        (
+            node.disk_total,
+            node.disk_overallocate,
+            allocation.disk + request.disk,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(total, overallocate, used)| {
+        capacity(total, overallocate).map(|capacity| used as f64 / capacity as f64)
+    })
+    .fold(0.0, f64::max)
+}
+
+pub fn fits(node: &NodeModel, allocation: NodeAllocation, request: Request) -> bool {
+    usage(node, allocation, request) <= 1.0
+}
+
+/// Whether a pod's restrictions allow its servers on the node.
+pub fn allows(restrictions: &NodeRestrictions, node: &NodeModel) -> bool {
+    let at_least = |total: Option<i32>, min: Option<i32>| {
+        min.is_none_or(|min| total.is_none_or(|total| total >= min))
+    };
+    (restrictions.node_ids.is_empty() || restrictions.node_ids.contains(&node.id))
+        && restrictions.tags.iter().all(|tag| node.tags.contains(tag))
+        && at_least(node.cpu_total, restrictions.min_cpu)
+        && at_least(node.memory_total, restrictions.min_memory)
+}
+
+/// Rejects nodes the pod may not be deployed to.
+pub fn check_allowed(pod: &PodModel, node: &NodeModel) -> Result<(), AppError> {
+    if allows(&pod.node_restrictions, node) {
+        return Ok(());
+    }
+    Err(AppError::Conflict(format!(
+        "pod {} may not be deployed to node {}",
+        pod.id, node.id
+    )))
+}
+
+/// Rejects nodes in maintenance, which take no new servers and no installs.
+pub fn check_not_in_maintenance(node: &NodeModel) -> Result<(), AppError> {
+    if !node.maintenance {
+        return Ok(());
+    }
+    Err(AppError::Conflict(format!(
+        "node {} is in maintenance",
+        node.id
+    )))
+}
+
+/// Checks that a node can take the request, for servers given a node by hand.
+/// Locks the node until the transaction ends.
+pub async fn reserve(
+    conn: &mut PgConnection,
+    node_id: i32,
+    request: Request,
+) -> Result<NodeModel, AppError> {
+    let node = node::lock_node_by_id(conn, node_id).await?;
+    let allocation = node::get_node_allocation(conn, node.id).await?;
+    if !fits(&node, allocation, request) {
+        return Err(AppError::Conflict(format!(
+            "node {} does not have enough capacity",
+            node.id
+        )));
+    }
+    Ok(node)
+}
+
+/// Whether a new server may be placed on the node at all, regardless of its
+/// capacity.
+fn eligible(node: &NodeModel, tags: &[String], restrictions: &NodeRestrictions) -> bool {
+    !node.maintenance
+        && node_status(node) == NodeStatus::Online
+        && tags.iter().all(|tag| node.tags.contains(tag))
+        && allows(restrictions, node)
+}
+
+/// Picks the node to deploy a new server on. Only online nodes that are not in
+/// maintenance, carry all tags, are allowed by the pod's restrictions and fit
+/// the request are considered. Nodes are scored without locks, only the chosen
+/// one is locked until the transaction ends and checked again, so placements on
+/// other nodes and heartbeats are not held up.
+pub async fn place(
+    conn: &mut PgConnection,
+    request: Request,
+    strategy: PlacementStrategy,
+    tags: &[String],
+    restrictions: &NodeRestrictions,
+) -> Result<NodeModel, AppError> {
+    let mut passed = vec![];
+    loop {
+        let mut best: Option<(f64, NodeModel)> = None;
+        for node in node::get_nodes(conn).await? {
+            if passed.contains(&node.id) || !eligible(&node, tags, restrictions) {
+                continue;
+            }
+            let allocation = node::get_node_allocation(conn, node.id).await?;
+            if !fits(&node, allocation, request) {
+                continue;
+            }
+            let usage = usage(&node, allocation, request);
+            let better = best.as_ref().is_none_or(|(best, _)| match strategy {
+                PlacementStrategy::BinPack => usage > *best,
+                PlacementStrategy::Spread => usage < *best,
+            });
+            if better {
+                best = Some((usage, node));
+            }
+        }
+        let Some((_, chosen)) = best else {
+            return Err(AppError::Conflict(
+                "no node has enough capacity for the server".to_string(),
+            ));
+        };
+
+        // another placement may have filled the node since it was scored
+        let node = node::lock_node_by_id(conn, chosen.id).await?;
+        let allocation = node::get_node_allocation(conn, node.id).await?;
+        if eligible(&node, tags, restrictions) && fits(&node, allocation, request) {
+            return Ok(node);
+        }
+        passed.push(node.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::node;
+
+    fn allocation(memory: i64, cpu: i64, disk: i64) -> NodeAllocation {
+        NodeAllocation { memory, cpu, disk }
+    }
+
+    fn request(memory: i32, cpu: i32, disk: i32) -> Request {
+        Request::new(Some(memory), Some(cpu), Some(disk))
+    }
+
+    #[test]
+    fn usage_is_the_fullest_resource() {
+        let node = node(1);
+        assert_eq!(usage(&node, allocation(0, 0, 0), request(0, 0, 0)), 0.0);
+        assert_eq!(
+            usage(&node, allocation(2048, 100, 0), request(2048, 0, 0)),
+            0.5
+        );
+        assert_eq!(
+            usage(&node, allocation(0, 300, 0), request(1024, 100, 0)),
+            1.0
+        );
+    }
+
+    #[test]
+    fn usage_ignores_unlimited_resources() {
+        let mut node = node(1);
+        node.memory_total = None;
+        node.cpu_total = None;
+        assert_eq!(
+            usage(&node, allocation(1 << 20, 1000, 0), request(0, 0, 51200)),
+            0.5
+        );
+        node.disk_total = None;
+        assert_eq!(
+            usage(&node, allocation(1 << 20, 1000, 1 << 20), request(0, 0, 0)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn fits_up_to_the_overallocated_capacity() {
+        let mut node = node(1);
+        assert!(fits(&node, allocation(4096, 0, 0), request(4096, 0, 0)));
+        assert!(!fits(&node, allocation(4096, 0, 0), request(4097, 0, 0)));
+        node.memory_overallocate = 50;
+        assert!(fits(&node, allocation(8192, 0, 0), request(4096, 0, 0)));
+        assert!(!fits(&node, allocation(8192, 0, 0), request(4097, 0, 0)));
+        // servers without limits take nothing from a node
+        assert!(fits(
+            &node,
+            allocation(0, 400, 102400),
+            Request::new(None, None, None)
+        ));
+    }
+
+    #[test]
+    fn allows_nodes_matching_restrictions() {
+        let mut node = node(2);
+        node.tags = vec!["eu".to_string(), "ssd".to_string()];
+        assert!(allows(&NodeRestrictions::default(), &node));
+
+        let restrictions =
+            |node_ids: Vec<i32>, tags: &[&str], min_cpu, min_memory| NodeRestrictions {
+                node_ids,
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                min_cpu,
+                min_memory,
+            };
+        assert!(allows(&restrictions(vec![1, 2], &[], None, None), &node));
+        assert!(!allows(&restrictions(vec![1], &[], None, None), &node));
+        assert!(allows(&restrictions(vec![], &["eu"], None, None), &node));
+        assert!(!allows(
+            &restrictions(vec![], &["eu", "us"], None, None),
+            &node
+        ));
+        assert!(allows(
+            &restrictions(vec![], &[], Some(400), Some(8192)),
+            &node
+        ));
+        assert!(!allows(&restrictions(vec![], &[], Some(401), None), &node));
+        assert!(!allows(&restrictions(vec![], &[], None, Some(8193)), &node));
+
+        node.cpu_total = None;
+        assert!(allows(
+            &restrictions(vec![], &[], Some(10_000), None),
+            &node
+        ));
+    }
+
+    #[test]
+    fn only_available_nodes_are_eligible() {
+        let tags = ["eu".to_string()];
+        let mut node = node(3);
+        node.tags = tags.to_vec();
+        assert!(eligible(&node, &tags, &NodeRestrictions::default()));
+        assert!(!eligible(
+            &node,
+            &["us".to_string()],
+            &NodeRestrictions::default()
+        ));
+
+        node.maintenance = true;
+        assert!(!eligible(&node, &tags, &NodeRestrictions::default()));
+        assert!(check_not_in_maintenance(&node).is_err());
+        node.maintenance = false;
+
+        node.last_seen_at = None;
+        assert!(!eligible(&node, &tags, &NodeRestrictions::default()));
+    }
+}
@@ -0,0 +1,420 @@
+use chrono::Utc;
+use common::{
+    agent_types::InstallOptions,
+    orch_types::{Server, ServerRollout},
+};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+
+use crate::{
+    models::{
+        node::{self, NodeModel},
+        node_port::{self, NodePortModel},
+        pod::{self, PodModel},
+        server::{self, ChangePod, CreateServer, ServerModel, SuspendServer, UpdateServerStaff},
+    },
+    services::{
+        agent,
+        placement::{self, Request},
+        rollout,
+        transfer::ensure_not_transferring,
+        variables,
+    },
+    utils::{server_model_to_server, AppError},
+};
+
+// Creating, updating and deleting a server changes both the database and the
+// node. The change is committed first with the server claimed for syncing, so
+// no locks are held while the node is called. Once the node applied the change
+// its sync is finished. If the node rejected it the change is undone in the
+// database, and if the node may have applied it the sync is left to the
+// reconciler, which retries it until the node is in line.
+
+/// Whether the node might have applied a request that returned an error. A
+/// rejected request changed nothing, a timed out one may have.
+fn maybe_applied(e: &AppError) -> bool {
+    matches!(e, AppError::NodeRequestError(_))
+}
+
+/// A server as it was before a change, to go back to if its node rejects it.
+struct Previous {
+    server: ServerModel,
+    ports: Vec<NodePortModel>,
+}
+
+impl Previous {
+    async fn of(conn: &mut PgConnection, server: ServerModel) -> Result<Self, AppError> {
+        let ports = node_port::get_node_ports_by_server_id(conn, server.id).await?;
+        Ok(Self { server, ports })
+    }
+}
+
+/// Claims the changed server for syncing and commits the change. Returns the
+/// server to apply, or `None` while a sync of an earlier change still runs,
+/// which the reconciler follows up on.
+async fn commit_claimed(
+    mut tx: Transaction<'_, Postgres>,
+    id: i32,
+) -> Result<Option<Server>, AppError> {
+    let spec = match server::claim_server_sync(&mut tx, id).await? {
+        Some(claimed) => Some(server_model_to_server(claimed, &mut tx).await?),
+        None => None,
+    };
+    tx.commit().await?;
+    Ok(spec)
+}
+
+/// Applies a claimed server to its node and records the outcome. Only an error
+/// of a node that rejected the change is returned, the caller undoes it.
+async fn apply(db: &PgPool, node: &NodeModel, spec: &Server) -> Result<(), AppError> {
+    let result = agent::apply_server(node, spec).await;
+    let mut conn = db.acquire().await?;
+    match result {
+        Ok(()) => server::finish_server_sync(&mut conn, spec.id, spec.generation).await?,
+        Err(e) if maybe_applied(&e) => {
+            tracing::warn!(
+                "Node {} unreachable while applying server {}, retrying later: {}",
+                node.id,
+                spec.id,
+                e
+            );
+            server::fail_server_sync(&mut conn, spec.id, &e.to_string(), Utc::now()).await?;
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+pub async fn create_server(db: &PgPool, mut cserver: CreateServer) -> Result<Server, AppError> {
+    let auto_primary = cserver.port.is_none();
+    let auto_additional = cserver.additional_port_count;
+    let request = Request::new(cserver.memory_limit, cserver.cpu_limit, cserver.disk_limit);
+
+    let mut tx = db.begin().await?;
+    let pod = pod::get_pod_by_id(&mut tx, cserver.pod_id).await?;
+    check_image(&pod, &cserver.image)?;
+    cserver.env_vars = variables::resolve(&pod.variables, &cserver.env_vars)?;
+    variables::validate_startup_command(&pod.variables, &cserver.startup_command)?;
+    let node = match cserver.node_id {
+        Some(node_id) => {
+            let node = placement::reserve(&mut tx, node_id, request).await?;
+            placement::check_not_in_maintenance(&node)?;
+            placement::check_allowed(&pod, &node)?;
+            node
+        }
+        None => {
+            if !auto_primary || !cserver.additional_ports.is_empty() {
+                return Err(AppError::BadRequest(
+                    "ports can only be picked together with a node".to_string(),
+                ));
+            }
+            placement::place(
+                &mut tx,
+                request,
+                cserver.placement,
+                &cserver.location_tags,
+                &pod.node_restrictions,
+            )
+            .await?
+        }
+    };
+    cserver.node_id = Some(node.id);
+    let created = server::create_server(&mut tx, cserver).await?;
+    if auto_primary {
+        allocate_ports(&mut tx, &created, 1, true).await?;
+    }
+    if auto_additional > 0 {
+        allocate_ports(&mut tx, &created, auto_additional, false).await?;
+    }
+    // committing reserves the node's capacity and ports for the server
+    let id = created.id;
+    if let Some(spec) = commit_claimed(tx, id).await? {
+        if let Err(e) = apply(db, &node, &spec).await {
+            // the node created nothing, so the server is dropped again
+            let mut tx = db.begin().await?;
+            server::delete_server(&mut tx, id).await?;
+            server::purge_server(&mut tx, id).await?;
+            tx.commit().await?;
+            return Err(e);
+        }
+    }
+
+    let mut conn = db.acquire().await?;
+    let server = server::get_server_by_id(&mut conn, id).await?;
+    Ok(server_model_to_server(server, &mut conn).await?)
+}
+
+async fn allocate_ports(
+    conn: &mut PgConnection,
+    server: &ServerModel,
+    count: u32,
+    is_primary: bool,
+) -> Result<(), AppError> {
+    let allocated =
+        node_port::allocate_node_ports(conn, server.node_id, server.id, count.into(), is_primary)
+            .await?;
+    if allocated.len() < count as usize {
+        return Err(AppError::Conflict(format!(
+            "node {} does not have {} free ports",
+            server.node_id, count
+        )));
+    }
+    Ok(())
+}
+
+pub async fn update_server_staff(
+    db: &PgPool,
+    mut userver: UpdateServerStaff,
+) -> Result<Server, AppError> {
+    let mut tx = db.begin().await?;
+    ensure_not_transferring(&mut tx, userver.id).await?;
+    let previous = server::get_server_by_id(&mut tx, userver.id).await?;
+    let pod = pod::get_pod_by_id(&mut tx, userver.pod_id).await?;
+    check_image(&pod, &userver.image)?;
+    userver.env_vars = variables::resolve(&pod.variables, &userver.env_vars)?;
+    variables::validate_startup_command(&pod.variables, &userver.startup_command)?;
+    let grows = userver.memory_limit > previous.memory_limit
+        || userver.cpu_limit > previous.cpu_limit
+        || userver.disk_limit > previous.disk_limit;
+    let node = if grows {
+        // the server's current limits are counted as allocated already
+        let growth = Request {
+            memory: limit_growth(previous.memory_limit, userver.memory_limit),
+            cpu: limit_growth(previous.cpu_limit, userver.cpu_limit),
+            disk: limit_growth(previous.disk_limit, userver.disk_limit),
+        };
+        placement::reserve(&mut tx, previous.node_id, growth).await?
+    } else {
+        node::get_node_by_id(&mut tx, previous.node_id).await?
+    };
+    if previous.pod_id != pod.id {
+        placement::check_allowed(&pod, &node)?;
+    }
+    let id = previous.id;
+    let previous = Previous::of(&mut tx, previous).await?;
+    server::update_server_staff(&mut tx, userver).await?;
+    commit_update(db, tx, &node, &previous).await?;
+
+    let mut conn = db.acquire().await?;
+    let server = server::get_server_by_id(&mut conn, id).await?;
+    Ok(server_model_to_server(server, &mut conn).await?)
+}
+
+/// Commits the transaction a server was updated in and applies the server to
+/// its node, reverting it to `previous` if the node rejects it. Returns whether
+/// the node applied the update.
+async fn commit_update(
+    db: &PgPool,
+    tx: Transaction<'_, Postgres>,
+    node: &NodeModel,
+    previous: &Previous,
+) -> Result<bool, AppError> {
+    let Some(spec) = commit_claimed(tx, previous.server.id).await? else {
+        return Ok(false);
+    };
+    if let Err(e) = apply(db, node, &spec).await {
+        revert(db, previous, spec.generation).await?;
+        return Err(e);
+    }
+    let mut conn = db.acquire().await?;
+    let server = server::get_server_by_id(&mut conn, spec.id).await?;
+    Ok(server.applied_generation == spec.generation)
+}
+
+fn limit_growth(previous: Option<i32>, next: Option<i32>) -> i64 {
+    i64::from(next.unwrap_or(0) - previous.unwrap_or(0))
+}
+
+/// Reverts a server whose node rejected its change at `generation`. The node
+/// still runs the previous server, which the reconciler applies again.
+async fn revert(db: &PgPool, previous: &Previous, generation: i64) -> Result<(), AppError> {
+    let id = previous.server.id;
+    let mut tx = db.begin().await?;
+    if server::revert_server(&mut tx, &previous.server, generation).await? {
+        node_port::unassign_all_node_port_from_server(&mut tx, id).await?;
+        for port in &previous.ports {
+            node_port::assign_node_port_to_server(&mut tx, port.id, id, port.is_primary).await?;
+        }
+    } else {
+        // changed again since, the reconciler applies the newer change
+        server::release_server_sync(&mut tx, id).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Rejects images the pod does not offer.
+pub fn check_image(pod: &PodModel, image: &str) -> Result<(), AppError> {
+    if pod
+        .images
+        .iter()
+        .any(|offered| format!("{}:{}", offered.name, offered.tag) == image)
+    {
+        return Ok(());
+    }
+    Err(AppError::BadRequest(format!(
+        "image {} is not offered by pod {}",
+        image, pod.id
+    )))
+}
+
+/// Switches a server to another pod or image and reinstalls it, as the files
+/// of the previous pod are of no use to the new one.
+pub async fn change_pod(db: &PgPool, id: i32, change: ChangePod) -> Result<Server, AppError> {
+    let mut tx = db.begin().await?;
+    ensure_not_transferring(&mut tx, id).await?;
+    let current = server::get_server_by_id(&mut tx, id).await?;
+    let node = node::get_node_by_id(&mut tx, current.node_id).await?;
+    // switching pods reinstalls the server
+    placement::check_not_in_maintenance(&node)?;
+    let pod = pod::get_pod_by_id(&mut tx, change.pod_id).await?;
+    check_image(&pod, &change.image)?;
+    placement::check_allowed(&pod, &node)?;
+
+    let carried = variables::carry_over(&pod.variables, &current.env_vars);
+    let env_vars = variables::resolve(&pod.variables, &carried)?;
+    let previous = Previous::of(&mut tx, current).await?;
+    server::change_server_pod(
+        &mut tx,
+        id,
+        pod.id,
+        change.image,
+        pod.startup_command,
+        env_vars,
+    )
+    .await?;
+    if !commit_update(db, tx, &node, &previous).await? {
+        // installing now would run the previous pod's installer
+        return Err(AppError::NodeError(format!(
+            "server {} uses pod {} now, but its node did not apply it yet, reinstall it once it is in sync",
+            id, pod.id
+        )));
+    }
+    tracing::info!("Server {} now uses pod {}, reinstalling it", id, pod.id);
+
+    agent::install_server(&node, id, InstallOptions { wipe: change.wipe }).await?;
+    let mut conn = db.acquire().await?;
+    let server = server::get_server_by_id(&mut conn, id).await?;
+    Ok(server_model_to_server(server, &mut conn).await?)
+}
+
+/// Rolls the current revision of its pod out to a server, keeping the settings
+/// it overrides.
+pub async fn roll_out(db: &PgPool, id: i32, pod_id: i32) -> Result<ServerRollout, AppError> {
+    let mut tx = db.begin().await?;
+    ensure_not_transferring(&mut tx, id).await?;
+    let current = server::get_server_by_id(&mut tx, id).await?;
+    if current.pod_id != pod_id {
+        return Err(AppError::Conflict(format!(
+            "server {} no longer uses pod {}",
+            id, pod_id
+        )));
+    }
+    let pod = pod::get_pod_by_id(&mut tx, pod_id).await?;
+    let revision = pod.revision;
+    let old = pod::get_pod_revision(&mut tx, pod_id, current.pod_revision).await?;
+    let plan = rollout::plan(old.as_ref().map(|old| &old.document), &pod.into(), &current)?;
+    let outcome = rollout::outcome(&current, revision, Ok(&plan), true);
+
+    let node = node::get_node_by_id(&mut tx, current.node_id).await?;
+    let previous = Previous::of(&mut tx, current).await?;
+    server::roll_out_server_pod(
+        &mut tx,
+        id,
+        revision,
+        plan.image,
+        plan.startup_command,
+        plan.env_vars,
+    )
+    .await?;
+    commit_update(db, tx, &node, &previous).await?;
+    tracing::info!(
+        "Server {} now uses revision {} of pod {}",
+        id,
+        revision,
+        pod_id
+    );
+    Ok(outcome)
+}
+
+/// Suspends a server, which stops it on its node and keeps it stopped, or
+/// lifts a suspension with `None`.
+pub async fn set_suspension(
+    db: &PgPool,
+    id: i32,
+    suspension: Option<SuspendServer>,
+    user_id: i32,
+) -> Result<Server, AppError> {
+    let mut tx = db.begin().await?;
+    ensure_not_transferring(&mut tx, id).await?;
+    let current = server::get_server_by_id(&mut tx, id).await?;
+    if suspension.is_none() && !current.suspended {
+        return Err(AppError::Conflict(format!(
+            "server {} is not suspended",
+            id
+        )));
+    }
+    let node = node::get_node_by_id(&mut tx, current.node_id).await?;
+    let previous = Previous::of(&mut tx, current).await?;
+    let updated = match suspension {
+        Some(suspension) => {
+            server::set_server_suspension(&mut tx, id, true, suspension.reason, user_id).await?
+        }
+        None => server::set_server_suspension(&mut tx, id, false, None, user_id).await?,
+    };
+    // the node stops a suspended server when it applies it
+    commit_update(db, tx, &node, &previous).await?;
+    tracing::info!(
+        "Server {} was {} by user {}",
+        id,
+        if updated.suspended {
+            "suspended"
+        } else {
+            "unsuspended"
+        },
+        user_id
+    );
+
+    let mut conn = db.acquire().await?;
+    let server = server::get_server_by_id(&mut conn, id).await?;
+    Ok(server_model_to_server(server, &mut conn).await?)
+}
+
+/// Deletes a server. If its node can not be reached the server is only marked
+/// as deleted and the reconciler removes it once the node is back.
+pub async fn delete_server(db: &PgPool, id: i32) -> Result<(), AppError> {
+    let mut tx = db.begin().await?;
+    ensure_not_transferring(&mut tx, id).await?;
+    let existing = server::get_server_by_id(&mut tx, id).await?;
+    let node = node::get_node_by_id(&mut tx, existing.node_id).await?;
+    server::delete_server(&mut tx, id).await?;
+    let claimed = server::claim_server_sync(&mut tx, id).await?.is_some();
+    tx.commit().await?;
+    if !claimed {
+        // the reconciler deletes it once the running sync is done
+        return Ok(());
+    }
+
+    match agent::delete_server(&node, id).await {
+        Ok(()) => {
+            let mut tx = db.begin().await?;
+            server::purge_server(&mut tx, id).await?;
+            tx.commit().await?;
+        }
+        Err(e) if maybe_applied(&e) => {
+            tracing::warn!(
+                "Node {} unreachable while deleting server {}, deleting it later: {}",
+                node.id,
+                id,
+                e
+            );
+            let mut conn = db.acquire().await?;
+            server::fail_server_sync(&mut conn, id, &e.to_string(), Utc::now()).await?;
+        }
+        Err(e) => {
+            let mut conn = db.acquire().await?;
+            server::undelete_server(&mut conn, id).await?;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    models::{
+        node,
+        server::{self, ServerModel},
+        transfer,
+    },
+    services::agent,
+    utils::{server_model_to_server, AppError},
+};
+
+const TICK: Duration = Duration::from_secs(15);
+/// How many ticks pass between comparing the database with what nodes report.
+const AUDIT_TICKS: u32 = 20;
+const RETRY_DELAY: Duration = Duration::from_secs(15);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// Keeps nodes in line with the servers in the database. Changes are applied
+/// right away by the routes, this retries the ones that failed and catches
+/// nodes that lost changes.
+pub fn spawn(db: PgPool) {
+    tokio::spawn(async move {
+        match db.acquire().await {
+            Ok(mut conn) => {
+                if let Err(e) = server::release_all_server_syncs(&mut conn).await {
+                    tracing::error!("Failed to release server syncs: {:?}", e);
+                }
+            }
+            Err(e) => tracing::error!("Reconciler could not connect to database: {:?}", e),
+        }
+
+        let mut interval = tokio::time::interval(TICK);
+        let mut ticks = 0;
+        loop {
+            interval.tick().await;
+            if ticks % AUDIT_TICKS == 0 {
+                if let Err(e) = audit(&db).await {
+                    tracing::error!("Reconciler audit failed: {}", e);
+                }
+            }
+            ticks += 1;
+            if let Err(e) = tick(&db).await {
+                tracing::error!("Reconciler tick failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn tick(db: &PgPool) -> Result<(), AppError> {
+    let mut conn = db.acquire().await?;
+    let unsynced = server::claim_unsynced_servers(&mut conn, Utc::now()).await?;
+    for server in unsynced {
+        tokio::spawn(sync_claimed(db.clone(), server));
+    }
+    Ok(())
+}
+
+/// Compares the generations every node reports with the database. Servers the
+/// node is behind on are synced again on the next tick. Servers the database
+/// does not know are only reported, a restored database must not cost a node
+/// its servers, staff delete them by hand.
+async fn audit(db: &PgPool) -> Result<(), AppError> {
+    let nodes = {
+        let mut conn = db.acquire().await?;
+        node::get_nodes(&mut conn).await?
+    };
+    for node in nodes {
+        // read before asking the node, so syncs finishing meanwhile are not undone
+        let known = {
+            let mut conn = db.acquire().await?;
+            server::get_applied_generations_by_node_id(&mut conn, node.id).await?
+        };
+        let applied = match agent::get_applied_generations(&node).await {
+            Ok(applied) => applied,
+            Err(e) => {
+                tracing::warn!("Could not audit node {}: {}", node.id, e);
+                continue;
+            }
+        };
+        let applied: HashMap<i32, i64> = applied
+            .into_iter()
+            .map(|server| (server.id, server.generation))
+            .collect();
+
+        let mut conn = db.acquire().await?;
+        for (id, previous) in &known {
+            let generation = applied.get(id).copied().unwrap_or(0);
+            server::set_applied_generation(&mut conn, *id, *previous, generation).await?;
+        }
+        // servers being transferred here are only known to the node until they moved
+        let incoming = transfer::get_incoming_server_ids(&mut conn, node.id).await?;
+        let mut unknown: Vec<i32> = applied
+            .keys()
+            .copied()
+            .filter(|id| !known.iter().any(|(known, _)| known == id) && !incoming.contains(id))
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort_unstable();
+            tracing::warn!(
+                "Node {} has servers the database does not know, leaving them alone: {:?}",
+                node.id,
+                unknown
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Applies a server that was just changed. Failures are left to the background
+/// retries and show up in the server's sync status.
+pub async fn sync_now(db: &PgPool, id: i32) -> Result<(), AppError> {
+    let mut conn = db.acquire().await?;
+    if let Some(server) = server::claim_server_sync(&mut conn, id).await? {
+        drop(conn);
+        sync_claimed(db.clone(), server).await;
+    }
+    Ok(())
+}
+
+async fn sync_claimed(db: PgPool, server: ServerModel) {
+    let (id, generation, attempts) = (server.id, server.generation, server.sync_attempts);
+    let result = apply(&db, server).await;
+
+    let recorded = async {
+        let mut conn = db.acquire().await?;
+        match &result {
+            Ok(()) => server::finish_server_sync(&mut conn, id, generation).await,
+            Err(e) => {
+                let delay = RETRY_DELAY
+                    .saturating_mul(1 << attempts.clamp(0, 16))
+                    .min(MAX_RETRY_DELAY);
+                let next_sync_at = Utc::now() + delay;
+                server::fail_server_sync(&mut conn, id, &e.to_string(), next_sync_at).await
+            }
+        }
+    };
+    if let Err(e) = recorded.await {
+        tracing::error!("Failed to record sync of server {}: {:?}", id, e);
+    }
+    match result {
+        Ok(()) => tracing::info!("Server {} is in sync at generation {}", id, generation),
+        Err(e) => tracing::warn!("Failed to sync server {}: {}", id, e),
+    }
+}
+
+async fn apply(db: &PgPool, server: ServerModel) -> Result<(), AppError> {
+    let id = server.id;
+    let (node, spec) = {
+        let mut conn = db.acquire().await?;
+        let node = node::get_node_by_id(&mut conn, server.node_id).await?;
+        if server.deleted {
+            (node, None)
+        } else {
+            (node, Some(server_model_to_server(server, &mut conn).await?))
+        }
+    };
+    match spec {
+        Some(spec) => agent::apply_server(&node, &spec).await,
+        None => {
+            agent::delete_server(&node, id).await?;
+            let mut conn = db.acquire().await?;
+            server::purge_server(&mut conn, id).await?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use common::orch_types::{EnvVar, Image, PodDocument, RolloutChange, ServerRollout};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    models::{
+        pod,
+        server::{self, ServerModel},
+    },
+    services::{provisioning, variables},
+    utils::AppError,
+};
+
+// Servers copy the image, startup command and env vars of their pod when they
+// are created. Rolling a pod revision out compares a server's settings with
+// the revision it was built from: the ones still at that revision's values
+// follow the pod, the ones the server overrides keep their value.
+
+/// A server's settings under a new revision of its pod.
+pub struct Plan {
+    pub image: String,
+    pub startup_command: String,
+    pub env_vars: Vec<EnvVar>,
+    pub changes: Vec<RolloutChange>,
+    pub kept: Vec<String>,
+}
+
+fn image_name(image: &Image) -> String {
+    format!("{}:{}", image.name, image.tag)
+}
+
+fn change(changes: &mut Vec<RolloutChange>, setting: &str, from: Option<&str>, to: Option<&str>) {
+    if from != to {
+        changes.push(RolloutChange {
+            setting: setting.to_string(),
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+        });
+    }
+}
+
+/// Works out the settings of a server built from revision `old` under `new`.
+/// Without the old revision every setting counts as overridden.
+pub fn plan(
+    old: Option<&PodDocument>,
+    new: &PodDocument,
+    server: &ServerModel,
+) -> Result<Plan, AppError> {
+    let mut changes = vec![];
+    let mut kept = vec![];
+
+    let offered: Vec<String> = new.images.iter().map(image_name).collect();
+    let image = if offered.contains(&server.image) {
+        server.image.clone()
+    } else {
+        // the server's image was dropped, take the one that replaced it
+        let position = old.and_then(|old| {
+            old.images
+                .iter()
+                .position(|image| image_name(image) == server.image)
+        });
+        position
+            .and_then(|position| offered.get(position))
+            .or(offered.first())
+            .cloned()
+            .ok_or_else(|| AppError::BadRequest("the pod has no images".to_string()))?
+    };
+    change(&mut changes, "image", Some(&server.image), Some(&image));
+
+    let follows = old.is_some_and(|old| old.startup_command == server.startup_command);
+    let startup_command = if follows {
+        new.startup_command.clone()
+    } else {
+        if server.startup_command != new.startup_command {
+            kept.push("startup_command".to_string());
+        }
+        server.startup_command.clone()
+    };
+    variables::validate_startup_command(&new.variables, &startup_command)?;
+    change(
+        &mut changes,
+        "startup_command",
+        Some(&server.startup_command),
+        Some(&startup_command),
+    );
+
+    let mut env_vars = vec![];
+    for variable in &new.variables {
+        let current = server
+            .env_vars
+            .iter()
+            .find(|env_var| env_var.key == variable.key);
+        let old_default = old
+            .and_then(|old| old.variables.iter().find(|old| old.key == variable.key))
+            .map(|old| old.default.as_str());
+        let value = match current {
+            Some(current) if old_default != Some(current.value.as_str()) => {
+                if current.value != variable.default {
+                    kept.push(format!("env_vars.{}", variable.key));
+                }
+                current.value.clone()
+            }
+            _ => variable.default.clone(),
+        };
+        env_vars.push(EnvVar {
+            key: variable.key.clone(),
+            value,
+        });
+    }
+    // overrides the new revision does not accept fail here rather than being lost
+    let env_vars = variables::resolve(&new.variables, &env_vars)?;
+    for env_var in &server.env_vars {
+        let to = env_vars
+            .iter()
+            .find(|new| new.key == env_var.key)
+            .map(|new| new.value.as_str());
+        change(
+            &mut changes,
+            &format!("env_vars.{}", env_var.key),
+            Some(&env_var.value),
+            to,
+        );
+    }
+    for env_var in &env_vars {
+        if !server.env_vars.iter().any(|old| old.key == env_var.key) {
+            change(
+                &mut changes,
+                &format!("env_vars.{}", env_var.key),
+                None,
+                Some(&env_var.value),
+            );
+        }
+    }
+
+    Ok(Plan {
+        image,
+        startup_command,
+        env_vars,
+        changes,
+        kept,
+    })
+}
+
+/// Describes a planned or applied rollout, or why it is not possible.
+pub fn outcome(
+    server: &ServerModel,
+    revision: i32,
+    plan: Result<&Plan, String>,
+    applied: bool,
+) -> ServerRollout {
+    let (changes, kept, error) = match plan {
+        Ok(plan) => (plan.changes.clone(), plan.kept.clone(), None),
+        Err(e) => (vec![], vec![], Some(e)),
+    };
+    ServerRollout {
+        server_id: server.id,
+        from_revision: server.pod_revision,
+        to_revision: revision,
+        changes,
+        kept,
+        error,
+        applied,
+    }
+}
+
+/// The servers of a pod to roll out to, all of them if none are picked.
+async fn selected_servers(
+    conn: &mut PgConnection,
+    pod_id: i32,
+    server_ids: Option<Vec<i32>>,
+) -> Result<Vec<ServerModel>, AppError> {
+    let servers = server::get_servers_by_pod_id(conn, pod_id).await?;
+    let Some(server_ids) = server_ids else {
+        return Ok(servers);
+    };
+    if let Some(id) = server_ids
+        .iter()
+        .find(|id| !servers.iter().any(|server| server.id == **id))
+    {
+        return Err(AppError::BadRequest(format!(
+            "server {} does not use pod {}",
+            id, pod_id
+        )));
+    }
+    Ok(servers
+        .into_iter()
+        .filter(|server| server_ids.contains(&server.id))
+        .collect())
+}
+
+/// What rolling out the pod's current revision would change, without changing it.
+pub async fn preview(
+    conn: &mut PgConnection,
+    pod_id: i32,
+    server_ids: Option<Vec<i32>>,
+) -> Result<Vec<ServerRollout>, AppError> {
+    let pod = pod::get_pod_by_id(conn, pod_id).await?;
+    let revision = pod.revision;
+    let new: PodDocument = pod.into();
+    let mut rollouts = vec![];
+    for server in selected_servers(conn, pod_id, server_ids).await? {
+        let old = pod::get_pod_revision(conn, pod_id, server.pod_revision).await?;
+        let plan = plan(old.as_ref().map(|old| &old.document), &new, &server);
+        let plan = plan.as_ref().map_err(ToString::to_string);
+        rollouts.push(outcome(&server, revision, plan, false));
+    }
+    Ok(rollouts)
+}
+
+/// Rolls the pod's current revision out to the servers one by one. A server
+/// that fails is reported and does not stop the others.
+pub async fn roll_out(
+    db: &PgPool,
+    pod_id: i32,
+    server_ids: Option<Vec<i32>>,
+) -> Result<Vec<ServerRollout>, AppError> {
+    let mut conn = db.acquire().await?;
+    let previews = preview(&mut conn, pod_id, server_ids).await?;
+    drop(conn);
+    let mut rollouts = vec![];
+    for preview in previews {
+        if preview.error.is_some() || preview.from_revision == preview.to_revision {
+            rollouts.push(preview);
+            continue;
+        }
+        match provisioning::roll_out(db, preview.server_id, pod_id).await {
+            Ok(rollout) => rollouts.push(rollout),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to roll pod {} out to server {}: {}",
+                    pod_id,
+                    preview.server_id,
+                    e
+                );
+                rollouts.push(ServerRollout {
+                    error: Some(e.to_string()),
+                    ..preview
+                });
+            }
+        }
+    }
+    Ok(rollouts)
+}
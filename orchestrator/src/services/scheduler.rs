@@ -0,0 +1,250 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use common::{
+    agent_types::ServerStatus,
+    orch_types::{ScheduleRunResult, ScheduleTask},
+};
+use sqlx::PgPool;
+
+use crate::{
+    models::{
+        backup, node,
+        schedule::{self, ScheduleModel},
+        server,
+    },
+    services::agent,
+    utils::AppError,
+};
+
+const TICK: Duration = Duration::from_secs(15);
+/// Longest a wait task may take, the schedule can not run again until it is done.
+pub const MAX_WAIT: Duration = Duration::from_secs(15 * 60);
+
+/// Accepts standard five field expressions as well as the six/seven field
+/// format (with seconds and year) understood by the `cron` crate.
+pub fn parse_cron(expr: &str) -> Result<cron::Schedule, String> {
+    let expr = expr.trim();
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {}", expr)
+    } else {
+        expr.to_string()
+    };
+    cron::Schedule::from_str(&expr).map_err(|e| format!("invalid cron expression: {}", e))
+}
+
+pub fn parse_timezone(timezone: &str) -> Result<Tz, String> {
+    Tz::from_str(timezone).map_err(|_| format!("unknown timezone: {}", timezone))
+}
+
+/// Next time the schedule fires after `after`, evaluated in the schedule's own
+/// timezone so daylight saving changes are handled the way owners expect.
+pub fn next_run(
+    cron: &str,
+    timezone: &str,
+    after: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, String> {
+    let schedule = parse_cron(cron)?;
+    let tz = parse_timezone(timezone)?;
+    Ok(schedule
+        .after(&after.with_timezone(&tz))
+        .next()
+        .map(|next| next.with_timezone(&Utc)))
+}
+
+pub fn spawn(db: PgPool) {
+    tokio::spawn(async move {
+        match db.acquire().await {
+            Ok(mut conn) => {
+                if let Err(e) = schedule::release_all_schedules(&mut conn).await {
+                    tracing::error!("Failed to release schedules: {:?}", e);
+                }
+            }
+            Err(e) => tracing::error!("Scheduler could not connect to database: {:?}", e),
+        }
+
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            if let Err(e) = tick(&db).await {
+                tracing::error!("Scheduler tick failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn tick(db: &PgPool) -> Result<(), AppError> {
+    let mut conn = db.acquire().await?;
+    let due = schedule::claim_due_schedules(&mut conn, Utc::now()).await?;
+    for schedule in due {
+        tokio::spawn(execute(db.clone(), schedule));
+    }
+    Ok(())
+}
+
+struct RunLog(String);
+
+impl RunLog {
+    fn line(&mut self, message: impl AsRef<str>) {
+        self.0.push_str(&format!(
+            "[{}] {}\n",
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            message.as_ref()
+        ));
+    }
+}
+
+/// Runs a schedule that has already been claimed, records the run and
+/// releases the schedule with its next run time.
+pub async fn execute(db: PgPool, schedule: ScheduleModel) {
+    let started_at = Utc::now();
+    let mut log = RunLog(String::new());
+
+    let run = match db.acquire().await {
+        Ok(mut conn) => schedule::create_schedule_run(&mut conn, schedule.id, started_at)
+            .await
+            .ok(),
+        Err(_) => None,
+    };
+
+    let result = match run_tasks(&db, &schedule, &mut log).await {
+        Ok(result) => result,
+        Err(e) => {
+            log.line(format!("failed: {}", e));
+            ScheduleRunResult::Failed
+        }
+    };
+
+    let next_run_at = next_run(&schedule.cron, &schedule.timezone, Utc::now())
+        .ok()
+        .flatten();
+    let finished = async {
+        let mut conn = db.acquire().await?;
+        if let Some(run) = run {
+            schedule::finish_schedule_run(&mut conn, run.id, result, &log.0).await?;
+        }
+        schedule::release_schedule(&mut conn, &schedule, started_at, next_run_at).await
+    };
+    if let Err(e) = finished.await {
+        tracing::error!("Failed to record run of schedule {}: {:?}", schedule.id, e);
+    }
+}
+
+async fn run_tasks(
+    db: &PgPool,
+    schedule: &ScheduleModel,
+    log: &mut RunLog,
+) -> Result<ScheduleRunResult, AppError> {
+    // connections are only held briefly, a wait task may take minutes
+    let (server, node) = {
+        let mut conn = db.acquire().await?;
+        let server = server::get_server_by_id(&mut conn, schedule.server_id).await?;
+        let node = node::get_node_by_id(&mut conn, server.node_id).await?;
+        (server, node)
+    };
+
+    if server.suspended {
+        log.line("server is suspended, skipping");
+        return Ok(ScheduleRunResult::Skipped);
+    }
+
+    if schedule.only_when_online {
+        let status = agent::get_status(&node, schedule.server_id).await?;
+        if status != ServerStatus::Running {
+            log.line(format!("server is {:?}, skipping", status));
+            return Ok(ScheduleRunResult::Skipped);
+        }
+    }
+
+    for (i, task) in schedule.tasks.0.iter().enumerate() {
+        match task {
+            ScheduleTask::Power { signal } => {
+                log.line(format!("task {}: power {:?}", i + 1, signal));
+                agent::send_signal(&node, schedule.server_id, *signal).await?;
+            }
+            ScheduleTask::Command { command } => {
+                log.line(format!("task {}: command `{}`", i + 1, command));
+                agent::send_command(&node, schedule.server_id, command).await?;
+            }
+            ScheduleTask::Backup { retention } => {
+                log.line(format!("task {}: backup", i + 1));
+                let snapshot = agent::create_snapshot(&node, schedule.server_id).await?;
+                log.line(format!(
+                    "created snapshot {} ({} new bytes)",
+                    snapshot.id, snapshot.added_size
+                ));
+                let mut conn = db.acquire().await?;
+                backup::create_backup(&mut conn, snapshot).await?;
+                if let Some(retention) = retention {
+                    let pruned =
+                        agent::prune_snapshots(&node, schedule.server_id, retention).await?;
+                    backup::delete_backups(&mut conn, schedule.server_id, &pruned.removed).await?;
+                    log.line(format!("pruned {} snapshots", pruned.removed.len()));
+                }
+            }
+            ScheduleTask::Wait { seconds } => {
+                log.line(format!("task {}: wait {}s", i + 1, seconds));
+                // schedules saved before waits were limited may ask for longer
+                tokio::time::sleep(Duration::from_secs(*seconds).min(MAX_WAIT)).await;
+            }
+        }
+    }
+    log.line("done");
+    Ok(ScheduleRunResult::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn accepts_five_and_six_field_expressions() {
+        let after = utc(2026, 10, 19, 12, 0);
+        assert_eq!(
+            next_run("*/15 * * * *", "UTC", after).unwrap(),
+            Some(utc(2026, 10, 19, 12, 15))
+        );
+        assert_eq!(
+            next_run("30 0 13 * * *", "UTC", after).unwrap(),
+            Some(utc(2026, 10, 19, 13, 0) + chrono::Duration::seconds(30))
+        );
+    }
+
+    #[test]
+    fn runs_in_the_schedule_timezone() {
+        // Berlin is 2 hours ahead of UTC in summer and 1 hour in winter
+        assert_eq!(
+            next_run("0 4 * * *", "Europe/Berlin", utc(2026, 10, 19, 12, 0)).unwrap(),
+            Some(utc(2026, 10, 20, 2, 0))
+        );
+        assert_eq!(
+            next_run("0 4 * * *", "Europe/Berlin", utc(2026, 10, 24, 12, 0)).unwrap(),
+            Some(utc(2026, 10, 25, 3, 0))
+        );
+    }
+
+    #[test]
+    fn skips_times_that_do_not_exist() {
+        // 02:30 does not exist in Berlin on the 29th of March 2026
+        assert_eq!(
+            next_run("30 2 * * *", "Europe/Berlin", utc(2026, 3, 28, 12, 0)).unwrap(),
+            Some(utc(2026, 3, 30, 0, 30))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        let after = utc(2026, 10, 19, 12, 0);
+        assert!(next_run("not cron", "UTC", after).is_err());
+        assert!(next_run("* * * * *", "Mars/Olympus", after).is_err());
+        assert_eq!(next_run("0 0 0 1 1 * 2020", "UTC", after).unwrap(), None);
+    }
+}
@@ -0,0 +1,339 @@
+use std::time::Duration;
+
+use common::{
+    agent_types::{ServerSignal, ServerStatus, TransferState},
+    orch_types::{ServerTransfer, TransferStatus},
+};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    models::{
+        backup,
+        node::{self, NodeModel},
+        node_port, pod,
+        server::{self, ServerModel},
+        transfer::{self, CreateTransfer, TransferModel},
+    },
+    services::{
+        agent,
+        placement::{self, Request},
+    },
+    utils::{auth, server_model_to_server, AppError},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Polls of the source that may fail in a row before the transfer is given up.
+const MAX_POLL_FAILURES: u32 = 5;
+
+// A transfer creates the server on the destination with ports held for it
+// there, has the source stream the volume to the destination and then moves
+// the server in a single transaction. Until then the source keeps the server,
+// so a failed transfer only has to remove it from the destination. Backups are
+// not moved: their snapshots are in the source node's repository, which drops
+// them with the server, so a completed transfer deletes the server's backups.
+
+/// Refuses changes to a server while it is moved, they would only reach the
+/// source node.
+pub async fn ensure_not_transferring(conn: &mut PgConnection, id: i32) -> Result<(), AppError> {
+    if transfer::is_server_transferring(conn, id).await? {
+        return Err(AppError::Conflict(format!(
+            "server {} is being transferred",
+            id
+        )));
+    }
+    Ok(())
+}
+
+pub async fn start_transfer(
+    db: &PgPool,
+    id: i32,
+    ctransfer: CreateTransfer,
+) -> Result<ServerTransfer, AppError> {
+    let (server, source) = {
+        let mut conn = db.acquire().await?;
+        let server = server::get_server_by_id(&mut conn, id).await?;
+        let source = node::get_node_by_id(&mut conn, server.node_id).await?;
+        (server, source)
+    };
+    if source.id == ctransfer.destination_node_id {
+        return Err(AppError::BadRequest(format!(
+            "server is already on node {}",
+            source.id
+        )));
+    }
+    let was_running = matches!(
+        agent::get_status(&source, id).await?,
+        ServerStatus::Starting | ServerStatus::Running
+    );
+
+    let mut tx = db.begin().await?;
+    let request = Request::new(server.memory_limit, server.cpu_limit, server.disk_limit);
+    let destination = placement::reserve(&mut tx, ctransfer.destination_node_id, request).await?;
+    // servers may be moved off a node in maintenance, but not onto one
+    placement::check_not_in_maintenance(&destination)?;
+    let pod = pod::get_pod_by_id(&mut tx, server.pod_id).await?;
+    placement::check_allowed(&pod, &destination)?;
+    let created = transfer::create_transfer(&mut tx, id, source.id, ctransfer, was_running).await?;
+    let ports = node_port::get_node_ports_by_server_id(&mut tx, id)
+        .await?
+        .len();
+    let reserved =
+        node_port::reserve_node_ports(&mut tx, destination.id, created.id, ports as i64).await?;
+    if reserved.len() < ports {
+        return Err(AppError::Conflict(format!(
+            "node {} does not have {} free ports",
+            destination.id, ports
+        )));
+    }
+    tx.commit().await?;
+
+    tracing::info!(
+        "Transferring server {} from node {} to node {}",
+        id,
+        source.id,
+        destination.id
+    );
+    tokio::spawn(run(db.clone(), created.id));
+    Ok(created.into())
+}
+
+async fn run(db: PgPool, id: i32) {
+    let result = async {
+        let mut conn = db.acquire().await?;
+        let transfer = transfer::get_transfer_by_id(&mut conn, id).await?;
+        let source = node::get_node_by_id(&mut conn, transfer.source_node_id).await?;
+        let destination = node::get_node_by_id(&mut conn, transfer.destination_node_id).await?;
+        drop(conn);
+        Ok::<_, AppError>((transfer, source, destination))
+    };
+    let (transfer, source, destination) = match result.await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::error!("Failed to load transfer {}: {}", id, e);
+            return;
+        }
+    };
+
+    match execute(&db, &transfer, &source, &destination).await {
+        Ok(()) => finish(&transfer, &source, &destination).await,
+        Err(e) => {
+            tracing::error!("Transfer of server {} failed: {}", transfer.server_id, e);
+            rollback(&db, &transfer, &source, &destination, e.to_string()).await;
+        }
+    }
+}
+
+async fn execute(
+    db: &PgPool,
+    transfer: &TransferModel,
+    source: &NodeModel,
+    destination: &NodeModel,
+) -> Result<(), AppError> {
+    let mut conn = db.acquire().await?;
+    let server = server::get_server_by_id(&mut conn, transfer.server_id).await?;
+    let generation = server.generation + 1;
+    let spec = destination_spec(&mut conn, server, transfer, generation).await?;
+    transfer::set_transfer_progress(&mut conn, transfer.id, TransferStatus::Transferring, 0, 0)
+        .await?;
+    drop(conn);
+
+    agent::apply_server(destination, &spec).await?;
+    let token = auth::generate_token();
+    agent::expect_transfer(destination, transfer.server_id, &token).await?;
+    agent::send_transfer(source, transfer.server_id, destination, &token).await?;
+    wait_for_volume(db, transfer, source).await?;
+
+    let mut tx = db.begin().await?;
+    node_port::unassign_all_node_port_from_server(&mut tx, transfer.server_id).await?;
+    server::move_server(&mut tx, transfer.server_id, destination.id, generation)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                AppError::Conflict("server changed during the transfer".to_string())
+            }
+            e => e.into(),
+        })?;
+    node_port::assign_transfer_node_ports(
+        &mut tx,
+        transfer.id,
+        transfer.server_id,
+        spec.primary_port.id,
+    )
+    .await?;
+    let removed = backup::delete_all_backups_by_server_id(&mut tx, transfer.server_id).await?;
+    transfer::set_removed_backups(&mut tx, transfer.id, removed as i32).await?;
+    transfer::finish_transfer(&mut tx, transfer.id, TransferStatus::Completed, None).await?;
+    tx.commit().await?;
+    if removed > 0 {
+        tracing::info!(
+            "Deleted {} backups of server {} left on node {}",
+            removed,
+            transfer.server_id,
+            source.id
+        );
+    }
+    Ok(())
+}
+
+/// The server as it will be on the destination, with the ports held there.
+async fn destination_spec(
+    conn: &mut PgConnection,
+    server: ServerModel,
+    transfer: &TransferModel,
+    generation: i64,
+) -> Result<common::orch_types::Server, AppError> {
+    let mut spec = server_model_to_server(server, conn).await?;
+    let mut ports = node_port::get_node_ports_by_transfer_id(conn, transfer.id)
+        .await?
+        .into_iter()
+        .map(Into::into);
+    spec.node_id = transfer.destination_node_id;
+    spec.primary_port = ports.next().ok_or(AppError::InternalServerError)?;
+    spec.additional_ports = ports.collect();
+    spec.generation = generation;
+    Ok(spec)
+}
+
+async fn wait_for_volume(
+    db: &PgPool,
+    transfer: &TransferModel,
+    source: &NodeModel,
+) -> Result<(), AppError> {
+    let mut failures = 0;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let progress = match agent::get_transfer_progress(source, transfer.server_id).await {
+            Ok(Some(progress)) => progress,
+            Ok(None) => {
+                return Err(AppError::NodeError(
+                    "source node lost the transfer".to_string(),
+                ))
+            }
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_POLL_FAILURES {
+                    return Err(e);
+                }
+                tracing::warn!("Failed to poll transfer {}: {}", transfer.id, e);
+                continue;
+            }
+        };
+        failures = 0;
+
+        let mut conn = db.acquire().await?;
+        transfer::set_transfer_progress(
+            &mut conn,
+            transfer.id,
+            TransferStatus::Transferring,
+            progress.bytes_done as i64,
+            progress.bytes_total as i64,
+        )
+        .await?;
+        match progress.state {
+            TransferState::Stopping | TransferState::Sending => {}
+            TransferState::Done => return Ok(()),
+            TransferState::Failed => {
+                return Err(AppError::NodeError(
+                    progress
+                        .error
+                        .unwrap_or_else(|| "transfer failed".to_string()),
+                ))
+            }
+        }
+    }
+}
+
+/// Cleans up the source once the server was moved, which also drops the
+/// snapshots of its backups there. Leftovers are reported by the reconciler.
+async fn finish(transfer: &TransferModel, source: &NodeModel, destination: &NodeModel) {
+    let id = transfer.server_id;
+    tracing::info!("Server {} is now on node {}", id, destination.id);
+    if let Err(e) = agent::delete_server(source, id).await {
+        tracing::error!(
+            "Failed to remove transferred server {} from node {}: {}",
+            id,
+            source.id,
+            e
+        );
+    }
+    if transfer.was_running {
+        if let Err(e) = agent::send_signal(destination, id, ServerSignal::Start).await {
+            tracing::error!("Failed to start transferred server {}: {}", id, e);
+        }
+    }
+}
+
+/// Leaves the server on its source, removing what was set up on the destination.
+async fn rollback(
+    db: &PgPool,
+    transfer: &TransferModel,
+    source: &NodeModel,
+    destination: &NodeModel,
+    error: String,
+) {
+    let id = transfer.server_id;
+    if let Err(e) = agent::delete_server(destination, id).await {
+        tracing::error!(
+            "Failed to remove server {} from node {} after a failed transfer: {}",
+            id,
+            destination.id,
+            e
+        );
+    }
+
+    let recorded = async {
+        let mut tx = db.begin().await?;
+        node_port::release_transfer_node_ports(&mut tx, transfer.id).await?;
+        transfer::finish_transfer(&mut tx, transfer.id, TransferStatus::Failed, Some(error))
+            .await?;
+        tx.commit().await
+    };
+    if let Err(e) = recorded.await {
+        tracing::error!("Failed to record failed transfer {}: {:?}", transfer.id, e);
+    }
+
+    if transfer.was_running {
+        if let Err(e) = agent::send_signal(source, id, ServerSignal::Start).await {
+            tracing::error!(
+                "Failed to restart server {} on node {} after a failed transfer: {}",
+                id,
+                source.id,
+                e
+            );
+        }
+    }
+}
+
+/// Rolls back transfers left behind by an orchestrator that stopped mid transfer.
+pub fn spawn_recovery(db: PgPool) {
+    tokio::spawn(async move {
+        let transfers = async {
+            let mut conn = db.acquire().await?;
+            transfer::get_active_transfers(&mut conn).await
+        };
+        let transfers = match transfers.await {
+            Ok(transfers) => transfers,
+            Err(e) => {
+                tracing::error!("Failed to load interrupted transfers: {:?}", e);
+                return;
+            }
+        };
+        for transfer in transfers {
+            let nodes = async {
+                let mut conn = db.acquire().await?;
+                let source = node::get_node_by_id(&mut conn, transfer.source_node_id).await?;
+                let destination =
+                    node::get_node_by_id(&mut conn, transfer.destination_node_id).await?;
+                Ok::<_, sqlx::Error>((source, destination))
+            };
+            match nodes.await {
+                Ok((source, destination)) => {
+                    tracing::warn!("Rolling back interrupted transfer {}", transfer.id);
+                    let error = "the orchestrator restarted during the transfer".to_string();
+                    rollback(&db, &transfer, &source, &destination, error).await;
+                }
+                Err(e) => tracing::error!("Failed to roll back transfer {}: {:?}", transfer.id, e),
+            }
+        }
+    });
+}
@@ -0,0 +1,493 @@
+use std::{
+    collections::HashSet,
+    path::{Component, Path},
+};
+
+use common::{
+    orch_types::{ConfigFile, EnvVar, PodVariable, Sidecar, VariableRule, VariableType},
+    startup,
+};
+use regex::Regex;
+
+use crate::utils::AppError;
+
+/// Rejects pod variables that servers could never satisfy.
+pub fn validate_pod_variables(variables: &[PodVariable]) -> Result<(), AppError> {
+    let mut keys = HashSet::new();
+    for variable in variables {
+        if variable.key.is_empty() || variable.key.contains('=') {
+            return Err(AppError::BadRequest(format!(
+                "invalid variable key `{}`",
+                variable.key
+            )));
+        }
+        if !keys.insert(variable.key.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "variable {} is defined twice",
+                variable.key
+            )));
+        }
+        for rule in &variable.rules {
+            if let VariableRule::Regex { pattern } = rule {
+                anchored(pattern).map_err(|e| {
+                    AppError::BadRequest(format!(
+                        "invalid regex of variable {}: {}",
+                        variable.key, e
+                    ))
+                })?;
+            }
+        }
+        // an empty default makes whoever creates a server pick a value
+        if !variable.default.is_empty() {
+            check_value(variable, &variable.default).map_err(|e| {
+                AppError::BadRequest(format!("default of variable {}: {}", variable.key, e))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Rejects startup commands that use variables the pod does not have.
+pub fn validate_startup_command(
+    variables: &[PodVariable],
+    startup_command: &str,
+) -> Result<(), AppError> {
+    let keys: Vec<&str> = variables
+        .iter()
+        .map(|variable| variable.key.as_str())
+        .collect();
+    startup::validate(startup_command, &keys)
+        .map_err(|e| AppError::BadRequest(format!("startup command: {}", e)))
+}
+
+/// Rejects config files outside of the volume or with values using variables
+/// the pod does not have.
+pub fn validate_config_files(
+    variables: &[PodVariable],
+    config_files: &[ConfigFile],
+) -> Result<(), AppError> {
+    let keys: Vec<&str> = variables
+        .iter()
+        .map(|variable| variable.key.as_str())
+        .collect();
+    for config_file in config_files {
+        if !inside_volume(&config_file.path) {
+            return Err(AppError::BadRequest(format!(
+                "config file {} is not inside the server's volume",
+                config_file.path
+            )));
+        }
+        for value in &config_file.values {
+            startup::validate_value(&value.value, &keys).map_err(|e| {
+                AppError::BadRequest(format!(
+                    "{} of config file {}: {}",
+                    value.key, config_file.path, e
+                ))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a relative path stays inside the server's volume.
+fn inside_volume(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Rejects sidecars the agent could not run next to a server of the pod.
+pub fn validate_sidecars(variables: &[PodVariable], sidecars: &[Sidecar]) -> Result<(), AppError> {
+    let keys: Vec<&str> = variables
+        .iter()
+        .map(|variable| variable.key.as_str())
+        .collect();
+    let mut names = HashSet::new();
+    for sidecar in sidecars {
+        // the name is part of container and host names
+        let valid_name = !sidecar.name.is_empty()
+            && sidecar.name.len() <= 32
+            && sidecar
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !sidecar.name.starts_with('-');
+        if !valid_name || sidecar.name == "server" {
+            return Err(AppError::BadRequest(format!(
+                "invalid sidecar name `{}`, use lowercase letters, digits and dashes",
+                sidecar.name
+            )));
+        }
+        if !names.insert(sidecar.name.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "sidecar {} is defined twice",
+                sidecar.name
+            )));
+        }
+        if sidecar.image.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "sidecar {} has no image",
+                sidecar.name
+            )));
+        }
+        if let Some(command) = &sidecar.command {
+            startup::validate(command, &keys).map_err(|e| {
+                AppError::BadRequest(format!("command of sidecar {}: {}", sidecar.name, e))
+            })?;
+        }
+        for env_var in &sidecar.env_vars {
+            startup::validate_value(&env_var.value, &keys).map_err(|e| {
+                AppError::BadRequest(format!(
+                    "{} of sidecar {}: {}",
+                    env_var.key, sidecar.name, e
+                ))
+            })?;
+        }
+        for volume in &sidecar.volumes {
+            if !inside_volume(&volume.source) || !volume.target.starts_with('/') {
+                return Err(AppError::BadRequest(format!(
+                    "volume {} of sidecar {} must be a folder of the server's volume mounted at an absolute path",
+                    volume.source, sidecar.name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// Errors leave the value out, it may be one the owner is not allowed to see.
+fn check_value(variable: &PodVariable, value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        if variable.required {
+            return Err("a value is required".to_string());
+        }
+        return Ok(());
+    }
+    match variable.kind {
+        VariableType::String => {}
+        VariableType::Number => {
+            value
+                .parse::<f64>()
+                .map_err(|_| "the value is not a number".to_string())?;
+        }
+        VariableType::Boolean => {
+            if value != "true" && value != "false" {
+                return Err("the value is not true or false".to_string());
+            }
+        }
+    }
+    for rule in &variable.rules {
+        match rule {
+            VariableRule::Regex { pattern } => {
+                let matches = anchored(pattern)
+                    .map(|regex| regex.is_match(value))
+                    .unwrap_or(false);
+                if !matches {
+                    return Err(format!("the value does not match `{}`", pattern));
+                }
+            }
+            VariableRule::Range { min, max } => {
+                let number = value
+                    .parse::<f64>()
+                    .map_err(|_| "the value is not a number".to_string())?;
+                if min.is_some_and(|min| number < min) || max.is_some_and(|max| number > max) {
+                    return Err(format!(
+                        "the value is not between {} and {}",
+                        min.map_or("-".to_string(), |min| min.to_string()),
+                        max.map_or("-".to_string(), |max| max.to_string())
+                    ));
+                }
+            }
+            VariableRule::Enum { values } => {
+                if !values.iter().any(|allowed| allowed == value) {
+                    return Err(format!("the value is not one of {}", values.join(", ")));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The env vars of a server of the pod. Variables that were not given get
+/// their default, unknown ones and invalid values are rejected.
+pub fn resolve(variables: &[PodVariable], env_vars: &[EnvVar]) -> Result<Vec<EnvVar>, AppError> {
+    let mut keys = HashSet::new();
+    for env_var in env_vars {
+        if !variables.iter().any(|variable| variable.key == env_var.key) {
+            return Err(AppError::BadRequest(format!(
+                "the pod has no variable {}",
+                env_var.key
+            )));
+        }
+        if !keys.insert(env_var.key.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "variable {} is given twice",
+                env_var.key
+            )));
+        }
+    }
+
+    variables
+        .iter()
+        .map(|variable| {
+            let value = env_vars
+                .iter()
+                .find(|env_var| env_var.key == variable.key)
+                .map_or(&variable.default, |env_var| &env_var.value);
+            check_value(variable, value)
+                .map_err(|e| AppError::BadRequest(format!("variable {}: {}", variable.key, e)))?;
+            Ok(EnvVar {
+                key: variable.key.clone(),
+                value: value.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Like [`resolve`], for owners that may only change editable variables. The
+/// variables they can not change keep their current value whatever is sent for
+/// them, so owners can not probe the values of variables hidden from them.
+pub fn resolve_for_owner(
+    variables: &[PodVariable],
+    current: &[EnvVar],
+    env_vars: &[EnvVar],
+) -> Result<Vec<EnvVar>, AppError> {
+    let current_value = |key: &str| {
+        current
+            .iter()
+            .find(|env_var| env_var.key == key)
+            .map(|env_var| env_var.value.as_str())
+    };
+    let merged: Vec<EnvVar> = variables
+        .iter()
+        .filter_map(|variable| {
+            let given = env_vars
+                .iter()
+                .find(|env_var| env_var.key == variable.key && variable.user_editable);
+            let value = given
+                .map(|env_var| env_var.value.as_str())
+                .or_else(|| current_value(&variable.key))?;
+            Some(EnvVar {
+                key: variable.key.clone(),
+                value: value.to_string(),
+            })
+        })
+        .collect();
+    resolve(variables, &merged)
+}
+
+/// Carries values of variables the new pod shares over to it, as far as they
+/// are valid for it. Everything else starts at the new pod's defaults.
+pub fn carry_over(variables: &[PodVariable], current: &[EnvVar]) -> Vec<EnvVar> {
+    variables
+        .iter()
+        .map(|variable| {
+            let kept = current
+                .iter()
+                .find(|env_var| env_var.key == variable.key)
+                .filter(|env_var| check_value(variable, &env_var.value).is_ok());
+            EnvVar {
+                key: variable.key.clone(),
+                value: kept
+                    .map_or(&variable.default, |env_var| &env_var.value)
+                    .clone(),
+            }
+        })
+        .collect()
+}
+
+/// Value shown to owners in place of variables they may not see.
+pub const HIDDEN_VALUE: &str = "********";
+
+/// Masks the values of the variables owners may not see.
+pub fn mask_for_owner(variables: &[PodVariable], env_vars: &mut [EnvVar]) {
+    for env_var in env_vars {
+        let visible = variables.iter().any(|variable| {
+            variable.key == env_var.key && (variable.user_viewable || variable.user_editable)
+        });
+        if !visible {
+            env_var.value = HIDDEN_VALUE.to_string();
+        }
+    }
+}
+
+/// Leaves out the variables owners may not see.
+pub fn visible_to_owner(variables: &[PodVariable], env_vars: &mut Vec<EnvVar>) {
+    env_vars.retain(|env_var| {
+        variables.iter().any(|variable| {
+            variable.key == env_var.key && (variable.user_viewable || variable.user_editable)
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn pod_variables() -> Vec<PodVariable> {
+        serde_json::from_value(json!([
+            {
+                "key": "VERSION",
+                "name": "Version",
+                "default": "latest",
+                "rules": [{"type": "regex", "pattern": "latest|[0-9.]+"}],
+                "user_editable": true,
+            },
+            {
+                "key": "PLAYERS",
+                "name": "Players",
+                "default": "20",
+                "type": "number",
+                "rules": [{"type": "range", "min": 1, "max": 100}],
+                "user_editable": true,
+            },
+            {
+                "key": "SECRET",
+                "name": "Secret",
+                "required": true,
+                "user_viewable": false,
+            },
+            {
+                "key": "MODE",
+                "name": "Mode",
+                "default": "survival",
+                "rules": [{"type": "enum", "values": ["survival", "creative"]}],
+            },
+        ]))
+        .unwrap()
+    }
+
+    fn env_vars(pairs: &[(&str, &str)]) -> Vec<EnvVar> {
+        pairs
+            .iter()
+            .map(|(key, value)| EnvVar {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    fn pairs(env_vars: &[EnvVar]) -> Vec<(&str, &str)> {
+        env_vars
+            .iter()
+            .map(|env_var| (env_var.key.as_str(), env_var.value.as_str()))
+            .collect()
+    }
+
+    fn error(result: Result<Vec<EnvVar>, AppError>) -> String {
+        result.err().unwrap().to_string()
+    }
+
+    #[test]
+    fn resolve_fills_in_defaults() {
+        let variables = pod_variables();
+        let resolved = resolve(&variables, &env_vars(&[("SECRET", "s3cret")])).unwrap();
+        assert_eq!(
+            pairs(&resolved),
+            [
+                ("VERSION", "latest"),
+                ("PLAYERS", "20"),
+                ("SECRET", "s3cret"),
+                ("MODE", "survival")
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_invalid_values() {
+        let variables = pod_variables();
+        let resolve = |pairs: &[(&str, &str)]| resolve(&variables, &env_vars(pairs));
+        assert_eq!(error(resolve(&[])), "variable SECRET: a value is required");
+        assert_eq!(
+            error(resolve(&[("SECRET", "s"), ("OTHER", "x")])),
+            "the pod has no variable OTHER"
+        );
+        assert_eq!(
+            error(resolve(&[("SECRET", "s"), ("SECRET", "t")])),
+            "variable SECRET is given twice"
+        );
+        // regex rules have to match the whole value
+        assert!(resolve(&[("SECRET", "s"), ("VERSION", "1.21")]).is_ok());
+        assert!(resolve(&[("SECRET", "s"), ("VERSION", "1.21-beta")]).is_err());
+        assert!(resolve(&[("SECRET", "s"), ("PLAYERS", "many")]).is_err());
+        // errors never repeat the value
+        assert_eq!(
+            error(resolve(&[("SECRET", "s"), ("PLAYERS", "101")])),
+            "variable PLAYERS: the value is not between 1 and 100"
+        );
+        assert_eq!(
+            error(resolve(&[("SECRET", "s"), ("MODE", "hardcore")])),
+            "variable MODE: the value is not one of survival, creative"
+        );
+    }
+
+    #[test]
+    fn owners_only_change_editable_variables() {
+        let variables = pod_variables();
+        let current = env_vars(&[
+            ("VERSION", "latest"),
+            ("PLAYERS", "20"),
+            ("SECRET", "s3cret"),
+            ("MODE", "survival"),
+        ]);
+        let resolved = resolve_for_owner(
+            &variables,
+            &current,
+            &env_vars(&[
+                ("PLAYERS", "50"),
+                ("SECRET", "guess"),
+                ("MODE", "not even valid"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            pairs(&resolved),
+            [
+                ("VERSION", "latest"),
+                ("PLAYERS", "50"),
+                ("SECRET", "s3cret"),
+                ("MODE", "survival")
+            ]
+        );
+        assert!(resolve_for_owner(&variables, &current, &env_vars(&[("PLAYERS", "500")])).is_err());
+    }
+
+    #[test]
+    fn carries_over_valid_values() {
+        let variables = pod_variables();
+        let carried = carry_over(
+            &variables,
+            &env_vars(&[("PLAYERS", "500"), ("SECRET", "s3cret"), ("GONE", "x")]),
+        );
+        assert_eq!(
+            pairs(&carried),
+            [
+                ("VERSION", "latest"),
+                ("PLAYERS", "20"),
+                ("SECRET", "s3cret"),
+                ("MODE", "survival")
+            ]
+        );
+    }
+
+    #[test]
+    fn hides_variables_from_owners() {
+        let variables = pod_variables();
+        let mut masked = env_vars(&[("VERSION", "latest"), ("SECRET", "s3cret")]);
+        mask_for_owner(&variables, &mut masked);
+        assert_eq!(
+            pairs(&masked),
+            [("VERSION", "latest"), ("SECRET", HIDDEN_VALUE)]
+        );
+        let mut visible = env_vars(&[("VERSION", "latest"), ("SECRET", "s3cret")]);
+        visible_to_owner(&variables, &mut visible);
+        assert_eq!(pairs(&visible), [("VERSION", "latest")]);
+    }
+}
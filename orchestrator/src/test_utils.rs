@@ -0,0 +1,36 @@
+use chrono::Utc;
+use common::agent_types::HostResources;
+use sqlx::types::Json;
+
+use crate::models::node::NodeModel;
+
+/// An online node with 8 GiB of memory, 4 CPUs and 100 GiB of disk space, none
+/// of it overallocated.
+pub fn node(id: i32) -> NodeModel {
+    NodeModel {
+        id,
+        name: format!("node-{}", id),
+        fqdn: format!("node-{}.local", id),
+        token_salt: None,
+        last_seen_at: Some(Utc::now()),
+        agent_version: Some("0.1.0".to_string()),
+        docker_version: Some("27.3.1".to_string()),
+        resources: Some(Json(HostResources {
+            cpu_count: 4,
+            load_average: 0.5,
+            memory_total: 8 << 30,
+            memory_available: 4 << 30,
+            disk_total: 100 << 30,
+            disk_available: 50 << 30,
+        })),
+        maintenance: false,
+        maintenance_message: None,
+        memory_total: Some(8192),
+        cpu_total: Some(400),
+        disk_total: Some(102400),
+        memory_overallocate: 0,
+        cpu_overallocate: 0,
+        disk_overallocate: 0,
+        tags: vec![],
+    }
+}
@@ -1,18 +1,27 @@
+use std::collections::HashMap;
+
 use axum::{
     body::Body,
     extract::{Path, Request},
-    http::StatusCode,
+    http::{header::AUTHORIZATION, StatusCode},
     middleware::Next,
     response::Response,
 };
+use hmac::{Hmac, Mac};
 use http_body_util::BodyExt;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgConnection;
 
 use crate::{
     auth::AuthSession,
-    models::server::{self, UpdateServer},
+    models::{
+        node,
+        server::{self, UpdateServer},
+    },
 };
 
-use super::DbConn;
+use super::{AppError, DbConn};
 
 pub async fn require_staff(
     auth_session: AuthSession,
@@ -28,9 +37,28 @@ pub async fn require_staff(
     Ok(response)
 }
 
+/// Refuses actions of owners on a suspended server. Staff can still act on it.
+pub async fn ensure_not_suspended(
+    auth_session: &AuthSession,
+    conn: &mut PgConnection,
+    id: i32,
+) -> Result<(), AppError> {
+    if auth_session.user.as_ref().is_some_and(|user| user.staff) {
+        return Ok(());
+    }
+    let server = server::get_server_by_id(conn, id).await?;
+    if !server.suspended {
+        return Ok(());
+    }
+    Err(AppError::Suspended(match server.suspended_reason {
+        Some(reason) => format!("server is suspended: {}", reason),
+        None => "server is suspended".to_string(),
+    }))
+}
+
 pub async fn require_server_owner_staff_path(
     auth_session: AuthSession,
-    Path(server_id): Path<i32>,
+    Path(params): Path<HashMap<String, String>>,
     DbConn(mut conn): DbConn,
     request: Request,
     next: Next,
@@ -39,6 +67,10 @@ pub async fn require_server_owner_staff_path(
     if user.staff {
         return Ok(next.run(request).await);
     }
+    let server_id: i32 = params
+        .get("id")
+        .and_then(|id| id.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
     let server = server::get_server_by_id(&mut conn, server_id).await;
     if let Ok(server) = server {
         if server.owner_id != user.id {
@@ -80,3 +112,46 @@ pub async fn require_server_owner_staff(
     let response = next.run(request).await;
     Ok(response)
 }
+
+pub fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
+
+/// Derives a node's token from the orchestrator's agent key. The database only
+/// holds the salt and the token's hash, neither of which gives the token.
+pub fn derive_node_token(key: &[u8], node_id: i32, salt: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(format!("{}:{}", node_id, salt).as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+pub fn hash_node_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Authenticates an agent by its node token and makes the node available to
+/// the handler as an extension.
+pub async fn require_node(
+    DbConn(mut conn): DbConn,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let node = node::get_node_by_token_hash(&mut conn, &hash_node_token(token))
+        .await
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    drop(conn);
+
+    request.extensions_mut().insert(node);
+    let response = next.run(request).await;
+    Ok(response)
+}
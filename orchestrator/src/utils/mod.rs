@@ -4,17 +4,23 @@ use axum::{
     http::{request::Parts, StatusCode},
 };
 use axum_thiserror::ErrorStatus;
-use common::orch_types::{Node, Server};
-use sqlx::{pool::PoolConnection, Postgres};
+use chrono::Utc;
+use common::{
+    agent_client::AgentError,
+    orch_types::{Node, NodeCapacity, NodeStatus, Server, SyncStatus},
+    startup::TemplateError,
+};
+use sqlx::{pool::PoolConnection, PgConnection, Postgres};
 use thiserror::Error;
 
 use crate::{
     models::{
-        node::{self, NodeModel},
+        node::{self, NodeAllocation, NodeModel},
         node_port::{
             get_node_ports_by_node_id, get_node_ports_by_server_id,
             get_primary_node_port_by_server_id,
         },
+        pod::get_pod_by_id,
         server::{self, ServerModel},
     },
     AppState,
@@ -54,8 +60,10 @@ pub async fn get_node_from_server_id(
 
 pub async fn server_model_to_server(
     server: ServerModel,
-    conn: &mut PoolConnection<Postgres>,
+    conn: &mut PgConnection,
 ) -> Result<Server, sqlx::Error> {
+    let pod = get_pod_by_id(conn, server.pod_id).await?;
+    let node = node::get_node_by_id(conn, server.node_id).await?;
     let is_primary = get_primary_node_port_by_server_id(conn, server.id).await?;
     let mut additional_ports = get_node_ports_by_server_id(conn, server.id).await?;
     additional_ports.retain(|port| !port.is_primary);
@@ -73,9 +81,67 @@ pub async fn server_model_to_server(
             .map(|port| port.into())
             .collect(),
         pod_id: server.pod_id,
+        pod_revision: server.pod_revision,
         image: server.image,
         startup_command: server.startup_command,
         env_vars: server.env_vars,
+        restart_on_crash: server.restart_on_crash,
+        installer_image: pod.installer_image,
+        installer_script: pod.installer_script,
+        installer_entrypoint: pod.installer_entrypoint,
+        config_files: pod.config_files,
+        sidecars: pod.sidecars,
+        hardening: pod.hardening,
+        node_maintenance: maintenance_notice(&node),
+        suspended: server.suspended,
+        suspended_reason: server.suspended_reason,
+        stop_method: pod.stop_method,
+        stop_timeout: pod.stop_timeout,
+        done_regex: pod.done_regex,
+        generation: server.generation,
+        applied_generation: server.applied_generation,
+        sync_status: if server.applied_generation == server.generation {
+            SyncStatus::Synced
+        } else if server.sync_error.is_some() {
+            SyncStatus::Failed
+        } else {
+            SyncStatus::Pending
+        },
+        sync_error: server.sync_error,
+    })
+}
+
+/// Agents send a heartbeat every 30 seconds, a node that missed a few is offline.
+const HEARTBEAT_TIMEOUT: chrono::Duration = chrono::Duration::seconds(90);
+/// Share of memory or disk space below which a node is degraded.
+const LOW_RESOURCE_RATIO: f64 = 0.05;
+
+pub fn node_status(node: &NodeModel) -> NodeStatus {
+    let seen_recently = node
+        .last_seen_at
+        .is_some_and(|last_seen_at| Utc::now() - last_seen_at < HEARTBEAT_TIMEOUT);
+    if !seen_recently {
+        return NodeStatus::Offline;
+    }
+    let low = |available: u64, total: u64| {
+        total > 0 && (available as f64) < total as f64 * LOW_RESOURCE_RATIO
+    };
+    let starved = node.resources.as_ref().is_some_and(|resources| {
+        low(resources.memory_available, resources.memory_total)
+            || low(resources.disk_available, resources.disk_total)
+    });
+    if node.docker_version.is_none() || starved {
+        return NodeStatus::Degraded;
+    }
+    NodeStatus::Online
+}
+
+/// Notice shown to server owners while a node is in maintenance.
+pub fn maintenance_notice(node: &NodeModel) -> Option<String> {
+    node.maintenance.then(|| {
+        node.maintenance_message
+            .clone()
+            .unwrap_or_else(|| "The node is under maintenance".to_string())
     })
 }
 
@@ -83,8 +149,11 @@ pub async fn node_model_to_node(
     node: NodeModel,
     conn: &mut PoolConnection<Postgres>,
 ) -> Result<Node, sqlx::Error> {
+    let allocation = node::get_node_allocation(conn, node.id).await?;
     Ok(Node {
         id: node.id,
+        status: node_status(&node),
+        capacity: node_capacity(&node, allocation),
         name: node.name,
         fqdn: node.fqdn,
         ports: get_node_ports_by_node_id(conn, node.id)
@@ -92,9 +161,30 @@ pub async fn node_model_to_node(
             .into_iter()
             .map(|port| port.into())
             .collect(),
+        last_seen_at: node.last_seen_at,
+        agent_version: node.agent_version,
+        docker_version: node.docker_version,
+        resources: node.resources.map(|resources| resources.0),
+        maintenance: node.maintenance,
+        maintenance_message: node.maintenance_message,
+        tags: node.tags,
     })
 }
 
+pub fn node_capacity(node: &NodeModel, allocation: NodeAllocation) -> NodeCapacity {
+    NodeCapacity {
+        memory_total: node.memory_total,
+        cpu_total: node.cpu_total,
+        disk_total: node.disk_total,
+        memory_overallocate: node.memory_overallocate,
+        cpu_overallocate: node.cpu_overallocate,
+        disk_overallocate: node.disk_overallocate,
+        memory_allocated: allocation.memory,
+        cpu_allocated: allocation.cpu,
+        disk_allocated: allocation.disk,
+    }
+}
+
 #[derive(Error, Debug, ErrorStatus)]
 pub enum AppError {
     #[error("Internal Server Error")]
@@ -118,10 +208,53 @@ pub enum AppError {
     #[error("Unauthorized")]
     #[status(StatusCode::UNAUTHORIZED)]
     Unauthorized,
+    #[error("{0}")]
+    #[status(StatusCode::BAD_REQUEST)]
+    BadRequest(String),
+    #[error("{0}")]
+    #[status(StatusCode::CONFLICT)]
+    Conflict(String),
+    #[error("{0}")]
+    #[status(StatusCode::FORBIDDEN)]
+    Suspended(String),
+}
+
+/// Turns violations of constraints that guard user input into errors the
+/// caller can act on.
+fn constraint_error(constraint: &str) -> Option<AppError> {
+    let error = match constraint {
+        "node_port_in_use" => AppError::Conflict("port is used by another server".to_string()),
+        "node_port_unique" => AppError::Conflict("port already exists on this node".to_string()),
+        "node_port_one_primary" => {
+            AppError::Conflict("server already has a primary port".to_string())
+        }
+        "node_port_server_node_fkey" => {
+            AppError::BadRequest("port does not belong to the server's node".to_string())
+        }
+        "node_port_range" => AppError::BadRequest("port must be between 1 and 65535".to_string()),
+        "server_transfer_one_active" => {
+            AppError::Conflict("server is already being transferred".to_string())
+        }
+        "node_capacity_total" => AppError::BadRequest("capacity must be positive".to_string()),
+        "server_pod_id_fkey" => AppError::Conflict("pod is still used by servers".to_string()),
+        "pod_category_id_fkey" => AppError::BadRequest("pod category does not exist".to_string()),
+        "node_capacity_overallocate" => {
+            AppError::BadRequest("overallocation can not be negative".to_string())
+        }
+        _ => return None,
+    };
+    Some(error)
 }
 
 impl From<sqlx::Error> for AppError {
     fn from(e: sqlx::Error) -> Self {
+        if let Some(error) = e
+            .as_database_error()
+            .and_then(|db| db.constraint())
+            .and_then(constraint_error)
+        {
+            return error;
+        }
         match e {
             sqlx::Error::RowNotFound => Self::NotFound,
             _ => {
@@ -132,9 +265,83 @@ impl From<sqlx::Error> for AppError {
     }
 }
 
+impl From<TemplateError> for AppError {
+    fn from(e: TemplateError) -> Self {
+        AppError::BadRequest(format!("startup command: {}", e))
+    }
+}
+
 impl From<reqwest::Error> for AppError {
     fn from(e: reqwest::Error) -> Self {
         tracing::error!("Error connecting to agent: {:?}", e);
         Self::NodeRequestError(e)
     }
 }
+
+impl From<AgentError> for AppError {
+    fn from(e: AgentError) -> Self {
+        match e {
+            AgentError::Request(e) => e.into(),
+            AgentError::BadRequest(message) => Self::BadRequest(message),
+            AgentError::Conflict(message) => Self::Conflict(message),
+            e => Self::NodeError(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::node;
+
+    #[test]
+    fn nodes_without_recent_heartbeats_are_offline() {
+        let mut node = node(1);
+        assert_eq!(node_status(&node), NodeStatus::Online);
+        node.last_seen_at = Some(Utc::now() - chrono::Duration::seconds(60));
+        assert_eq!(node_status(&node), NodeStatus::Online);
+        node.last_seen_at = Some(Utc::now() - chrono::Duration::seconds(120));
+        assert_eq!(node_status(&node), NodeStatus::Offline);
+        node.last_seen_at = None;
+        assert_eq!(node_status(&node), NodeStatus::Offline);
+    }
+
+    #[test]
+    fn nodes_low_on_resources_or_without_docker_are_degraded() {
+        let mut without_docker = node(1);
+        without_docker.docker_version = None;
+        assert_eq!(node_status(&without_docker), NodeStatus::Degraded);
+
+        let mut low_disk = node(1);
+        let resources = low_disk.resources.as_mut().unwrap();
+        resources.disk_available = resources.disk_total / 100;
+        assert_eq!(node_status(&low_disk), NodeStatus::Degraded);
+
+        let mut low_memory = node(1);
+        let resources = low_memory.resources.as_mut().unwrap();
+        resources.memory_available = resources.memory_total / 100;
+        assert_eq!(node_status(&low_memory), NodeStatus::Degraded);
+
+        // nodes that did not report their resources yet are not held back
+        let mut unreported = node(1);
+        unreported.resources = None;
+        assert_eq!(node_status(&unreported), NodeStatus::Online);
+    }
+
+    #[test]
+    fn maps_constraints_to_errors() {
+        assert!(matches!(
+            constraint_error("node_port_in_use"),
+            Some(AppError::Conflict(_))
+        ));
+        assert!(matches!(
+            constraint_error("node_port_range"),
+            Some(AppError::BadRequest(_))
+        ));
+        assert_eq!(
+            constraint_error("server_pod_id_fkey").unwrap().to_string(),
+            "pod is still used by servers"
+        );
+        assert!(constraint_error("server_pkey").is_none());
+    }
+}